@@ -0,0 +1,52 @@
+use mixin_sdk_rs::blaze::{BlazeClient, BlazeEvent};
+use mixin_sdk_rs::incoming_message::ConversationEvent;
+use mixin_sdk_rs::message::MessageCategory;
+use mixin_sdk_rs::safe::SafeUser;
+use mixin_sdk_rs::utils::unique_object_id;
+
+#[tokio::main]
+async fn main() -> Result<(), mixin_sdk_rs::error::Error> {
+    let user = SafeUser::new_from_env()?;
+    let mut client = BlazeClient::default();
+    loop {
+        match client.next_event(&user).await? {
+            BlazeEvent::Message(message) => {
+                let message = message.decrypt(&user)?;
+                println!(
+                    "{} from {}: {}",
+                    message.category,
+                    message.user_id,
+                    String::from_utf8_lossy(&message.data()?)
+                );
+                if message.category == MessageCategory::PlainText {
+                    let reply_id = unique_object_id([message.message_id.as_str(), "ECHO"]);
+                    let reply = message.reply(&reply_id, &message.payload()?)?;
+                    let delivery = client.send_message(&reply, &user).await?;
+                    println!("echoed over {delivery:?}");
+                }
+            }
+            BlazeEvent::SafeSnapshot { snapshot, .. } => println!(
+                "snapshot {} {} {}",
+                snapshot.snapshot_id,
+                snapshot.amount.unwrap_or_default(),
+                snapshot.asset_id.unwrap_or_default()
+            ),
+            BlazeEvent::Recall { message, recall } => {
+                println!("{} recalled {}", message.user_id, recall.message_id)
+            }
+            BlazeEvent::Pin { message, pin } => println!(
+                "{} {:?} {}",
+                message.user_id,
+                pin.action,
+                pin.message_ids.join(",")
+            ),
+            BlazeEvent::System(message) => match ConversationEvent::from_message(&message) {
+                Ok(event) => println!("conversation {} {event:?}", message.conversation_id),
+                Err(_) => println!("system event {}", message.category),
+            },
+            BlazeEvent::Receipt(receipt) => {
+                println!("message {} is {}", receipt.message_id, receipt.status)
+            }
+        }
+    }
+}
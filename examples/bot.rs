@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+use mixin_sdk_rs::blaze::BlazeClient;
+use mixin_sdk_rs::bot::{Bot, Dedup, Logger, RateLimit};
+use mixin_sdk_rs::message::MessageCategory;
+use mixin_sdk_rs::safe::SafeUser;
+use regex::Regex;
+
+#[tokio::main]
+async fn main() -> Result<(), mixin_sdk_rs::error::Error> {
+    let user = SafeUser::new_from_env()?;
+    let bot = Bot::new(user)
+        // Logs through the `log` facade, visible once a logger is installed.
+        .with_middleware(Logger)
+        .with_middleware(Dedup::new(1000))
+        .with_middleware(RateLimit::new(10, Duration::from_secs(60)))
+        .command("/start", |ctx| async move {
+            ctx.reply_text("Hi! Try /echo <text> or \"add 1 2\".")
+        })
+        .command(
+            "/echo",
+            |ctx| async move { ctx.reply_text(&ctx.args.join(" ")) },
+        )
+        .regex(
+            Regex::new(r"^add (-?\d+) (-?\d+)$").unwrap(),
+            |ctx| async move {
+                let a: i64 = ctx.args[0].parse().unwrap_or_default();
+                let b: i64 = ctx.args[1].parse().unwrap_or_default();
+                ctx.reply_text(&format!("{}", a.saturating_add(b)))
+            },
+        )
+        .category(MessageCategory::PlainSticker, |ctx| async move {
+            ctx.reply(&ctx.message.payload()?)
+        })
+        .fallback(|ctx| async move {
+            if ctx.message.is_system() {
+                return Ok(());
+            }
+            ctx.reply_text("Unknown command, send /start for help.")
+        });
+    let mut client = BlazeClient::default();
+    bot.run(&mut client).await
+}
@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+use mixin_sdk_rs::broadcast::Broadcast;
+use mixin_sdk_rs::message::MessagePayload;
+use mixin_sdk_rs::message_queue::MessageQueue;
+use mixin_sdk_rs::safe::SafeUser;
+
+#[tokio::main]
+async fn main() -> Result<(), mixin_sdk_rs::error::Error> {
+    let user = SafeUser::new_from_env()?;
+    let recipients_raw = std::env::var("RECIPIENT_IDS")
+        .map_err(|_| mixin_sdk_rs::error::Error::Input("RECIPIENT_IDS is not set".to_string()))?;
+    let recipient_ids: Vec<String> = recipients_raw
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect();
+    let broadcast_id =
+        std::env::var("BROADCAST_ID").unwrap_or_else(|_| "rust-sdk-broadcast".to_string());
+    let text =
+        std::env::var("BROADCAST_TEXT").unwrap_or_else(|_| "Hello from Rust SDK".to_string());
+
+    let queue = MessageQueue::new().with_rate_limit(100, Duration::from_secs(1));
+    let mut broadcast = Broadcast::new(&broadcast_id, MessagePayload::Text(text)).with_queue(queue);
+    let report = broadcast.send(&recipient_ids, &user).await?;
+    println!(
+        "sent {}, blocked {}, failed {}",
+        report.sent.len(),
+        report.blocked.len(),
+        report.failed.len()
+    );
+    for (recipient_id, error) in &report.failed {
+        println!("{recipient_id}: {error}");
+    }
+    Ok(())
+}
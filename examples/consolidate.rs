@@ -0,0 +1,50 @@
+use mixin_sdk_rs::amount::Amount;
+use mixin_sdk_rs::consolidation::{
+    ConsolidationOptions, consolidate_outputs, preview_consolidation,
+};
+use mixin_sdk_rs::safe::SafeUser;
+
+#[tokio::main]
+async fn main() -> Result<(), mixin_sdk_rs::error::Error> {
+    let user = SafeUser::new_from_env()?;
+    let asset_id = std::env::var("ASSET_ID")
+        .map_err(|_| mixin_sdk_rs::error::Error::Input("ASSET_ID is not set".to_string()))?;
+    let max_output_amount = std::env::var("MAX_OUTPUT_AMOUNT")
+        .ok()
+        .map(|amount| amount.parse::<Amount>())
+        .transpose()?;
+    let options = ConsolidationOptions {
+        min_outputs: 2,
+        max_output_amount,
+        ..Default::default()
+    };
+
+    if std::env::var("DRY_RUN").is_ok() {
+        let plan = preview_consolidation(&asset_id, &options, &user).await?;
+        println!("unspent outputs: {}", plan.outputs_count);
+        for batch in plan.batches {
+            println!(
+                "{}: {} inputs, {}",
+                batch.trace_id,
+                batch.outputs.len(),
+                batch.amount
+            );
+        }
+        return Ok(());
+    }
+
+    let transactions = consolidate_outputs(
+        &asset_id,
+        &options,
+        |p| {
+            println!(
+                "batch {}/{}: {} inputs, {}",
+                p.batch, p.batches, p.inputs, p.amount
+            )
+        },
+        &user,
+    )
+    .await?;
+    println!("consolidated in {} transactions", transactions.len());
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use mixin_sdk_rs::deposit::{
+    DepositEvent, DepositWatcher, PendingDepositQuery, read_deposit_entry,
+};
+use mixin_sdk_rs::safe::SafeUser;
+
+#[tokio::main]
+async fn main() -> Result<(), mixin_sdk_rs::error::Error> {
+    let user = SafeUser::new_from_env()?;
+    let chain_id = std::env::var("CHAIN_ID")
+        .map_err(|_| mixin_sdk_rs::error::Error::Input("CHAIN_ID is not set".to_string()))?;
+
+    let entry = read_deposit_entry(&chain_id, &user).await?;
+    let destination = entry.destination.clone().unwrap_or_default();
+    if entry.requires_tag() {
+        println!(
+            "deposit to {destination} with memo {}",
+            entry.tag.clone().unwrap_or_default()
+        );
+    } else {
+        println!("deposit to {destination}");
+    }
+
+    let query = PendingDepositQuery {
+        destination: entry.destination,
+        tag: entry.tag,
+        ..Default::default()
+    };
+    let mut watcher = DepositWatcher::new(query, &user.user_id);
+    loop {
+        match watcher.next(&user).await? {
+            DepositEvent::Confirming(deposit) => println!(
+                "pending {} {}: {} confirmations",
+                deposit.amount.unwrap_or_default(),
+                deposit.asset_id.unwrap_or_default(),
+                deposit.confirmations.unwrap_or_default()
+            ),
+            DepositEvent::Completed {
+                deposit, output, ..
+            } => println!(
+                "credited {} {}, output {}",
+                deposit.amount.unwrap_or_default(),
+                deposit.asset_id.unwrap_or_default(),
+                output.map(|o| o.output_id).unwrap_or_default()
+            ),
+            DepositEvent::Expired(deposit) => {
+                println!("credited {} without a snapshot", deposit.deposit_id)
+            }
+        }
+    }
+}
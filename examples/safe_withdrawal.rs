@@ -0,0 +1,39 @@
+use mixin_sdk_rs::amount::Amount;
+use std::time::Duration;
+
+use mixin_sdk_rs::safe::SafeUser;
+use mixin_sdk_rs::safe_transaction::SafeWithdrawalData;
+use mixin_sdk_rs::withdrawal::{send_safe_withdrawal, wait_withdrawal_hash};
+use uuid::Uuid;
+
+#[tokio::main]
+async fn main() -> Result<(), mixin_sdk_rs::error::Error> {
+    let user = SafeUser::new_from_env()?;
+    let asset_id = std::env::var("ASSET_ID")
+        .map_err(|_| mixin_sdk_rs::error::Error::Input("ASSET_ID is not set".to_string()))?;
+    let destination = std::env::var("DESTINATION")
+        .map_err(|_| mixin_sdk_rs::error::Error::Input("DESTINATION is not set".to_string()))?;
+    let amount = std::env::var("AMOUNT")
+        .map_err(|_| mixin_sdk_rs::error::Error::Input("AMOUNT is not set".to_string()))?
+        .parse::<Amount>()?;
+    let tag = std::env::var("TAG").unwrap_or_default();
+    let memo = std::env::var("MEMO").unwrap_or_default();
+    let trace_id = std::env::var("TRACE_ID").unwrap_or_else(|_| Uuid::new_v4().to_string());
+
+    let withdrawal = SafeWithdrawalData {
+        address: destination,
+        tag,
+    };
+    let sent =
+        send_safe_withdrawal(&asset_id, &withdrawal, amount, &memo, &trace_id, &user).await?;
+    println!(
+        "withdrawal transaction: {}, fee {} {}",
+        sent.transaction.transaction_hash.unwrap_or_default(),
+        sent.fee.amount.unwrap_or_default(),
+        sent.fee.asset_id.unwrap_or_default()
+    );
+
+    let hash = wait_withdrawal_hash(&trace_id, Duration::from_secs(5), &user).await?;
+    println!("withdrawal hash: {hash}");
+    Ok(())
+}
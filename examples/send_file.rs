@@ -0,0 +1,42 @@
+use mixin_sdk_rs::attachment::upload_attachment;
+use mixin_sdk_rs::encrypted_message::SessionCache;
+use mixin_sdk_rs::message::{MessagePayload, MessageRequest, post_message};
+use mixin_sdk_rs::safe::SafeUser;
+use mixin_sdk_rs::utils::unique_conversation_id;
+use uuid::Uuid;
+
+#[tokio::main]
+async fn main() -> Result<(), mixin_sdk_rs::error::Error> {
+    let user = SafeUser::new_from_env()?;
+    let recipient_id = std::env::var("RECIPIENT_ID")
+        .map_err(|_| mixin_sdk_rs::error::Error::Input("RECIPIENT_ID is not set".to_string()))?;
+    let file_path = std::env::var("FILE_PATH")
+        .map_err(|_| mixin_sdk_rs::error::Error::Input("FILE_PATH is not set".to_string()))?;
+    let encrypted = std::env::var("ENCRYPTED").is_ok();
+
+    let data = std::fs::read(&file_path)
+        .map_err(|e| mixin_sdk_rs::error::Error::Input(format!("read {file_path}: {e}")))?;
+    let name = std::path::Path::new(&file_path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let uploaded = upload_attachment(&data, encrypted, &user).await?;
+    println!("uploaded attachment {}", uploaded.attachment_id);
+
+    let conversation_id = unique_conversation_id(&user.user_id, &recipient_id);
+    let message_id = Uuid::new_v4().to_string();
+    let payload = MessagePayload::Data(uploaded.data("application/octet-stream", &name));
+    let message = if encrypted {
+        let sessions = SessionCache::default()
+            .sessions(&[&recipient_id], &user)
+            .await?;
+        MessageRequest::new_encrypted(&conversation_id, &message_id, &payload, &sessions, &user)?
+    } else {
+        MessageRequest::new(&conversation_id, &message_id, &payload)?
+    }
+    .with_recipient_id(&recipient_id);
+
+    post_message(message, &user).await?;
+    println!("file sent");
+    Ok(())
+}
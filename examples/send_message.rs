@@ -1,6 +1,5 @@
-use base64::Engine as _;
-use base64::engine::general_purpose::STANDARD;
-use mixin_sdk_rs::message::{MessageRequest, post_message};
+use mixin_sdk_rs::encrypted_message::SessionCache;
+use mixin_sdk_rs::message::{MessagePayload, MessageRequest, post_message};
 use mixin_sdk_rs::safe::SafeUser;
 use mixin_sdk_rs::utils::unique_conversation_id;
 use uuid::Uuid;
@@ -12,17 +11,17 @@ async fn main() -> Result<(), mixin_sdk_rs::error::Error> {
         .map_err(|_| mixin_sdk_rs::error::Error::Input("RECIPIENT_ID is not set".to_string()))?;
 
     let conversation_id = unique_conversation_id(&user.user_id, &recipient_id);
-    let data = STANDARD.encode("hello from rust sdk");
-
-    let message = MessageRequest {
-        conversation_id,
-        recipient_id: Some(recipient_id),
-        message_id: Uuid::new_v4().to_string(),
-        category: "PLAIN_TEXT".to_string(),
-        data_base64: data,
-        representative_id: None,
-        quote_message_id: None,
-    };
+    let payload = MessagePayload::Text("hello from rust sdk".to_string());
+    let message_id = Uuid::new_v4().to_string();
+    let message = if std::env::var("ENCRYPTED").is_ok() {
+        let sessions = SessionCache::default()
+            .sessions(&[&recipient_id], &user)
+            .await?;
+        MessageRequest::new_encrypted(&conversation_id, &message_id, &payload, &sessions, &user)?
+    } else {
+        MessageRequest::new(&conversation_id, &message_id, &payload)?
+    }
+    .with_recipient_id(&recipient_id);
 
     post_message(message, &user).await?;
     println!("message sent");
@@ -0,0 +1,32 @@
+use mixin_sdk_rs::amount::Amount;
+use mixin_sdk_rs::mix_address::{MIX_ADDRESS_PREFIX, MixAddress};
+use mixin_sdk_rs::safe::SafeUser;
+use mixin_sdk_rs::safe_transaction::{TransactionRecipient, send_transaction};
+use uuid::Uuid;
+
+#[tokio::main]
+async fn main() -> Result<(), mixin_sdk_rs::error::Error> {
+    let user = SafeUser::new_from_env()?;
+    let asset_id = std::env::var("ASSET_ID")
+        .map_err(|_| mixin_sdk_rs::error::Error::Input("ASSET_ID is not set".to_string()))?;
+    let recipient_id = std::env::var("RECIPIENT_ID")
+        .map_err(|_| mixin_sdk_rs::error::Error::Input("RECIPIENT_ID is not set".to_string()))?;
+    let amount = std::env::var("AMOUNT")
+        .map_err(|_| mixin_sdk_rs::error::Error::Input("AMOUNT is not set".to_string()))?
+        .parse::<Amount>()?;
+    let memo = std::env::var("MEMO").unwrap_or_default();
+    let trace_id = std::env::var("TRACE_ID").unwrap_or_else(|_| Uuid::new_v4().to_string());
+
+    let address = if recipient_id.starts_with(MIX_ADDRESS_PREFIX) {
+        MixAddress::from_string(&recipient_id)?
+    } else {
+        MixAddress::from_members(&[recipient_id], 1)?
+    };
+    let recipient = TransactionRecipient::new(&address, amount);
+    let transaction = send_transaction(&asset_id, &[recipient], &memo, &trace_id, &user).await?;
+    println!(
+        "transaction hash: {}",
+        transaction.transaction_hash.unwrap_or_default()
+    );
+    Ok(())
+}
@@ -2,6 +2,7 @@ use serde::{Deserialize, Serialize};
 use url::form_urlencoded;
 
 use crate::{
+    amount::Amount,
     auth::sign_authentication_token,
     error::Error,
     pin::encrypt_ed25519_pin,
@@ -21,8 +22,8 @@ pub struct Address {
     pub destination: Option<String>,
     #[serde(default)]
     pub tag: Option<String>,
-    #[serde(default)]
-    pub fee: Option<String>,
+    #[serde(default, deserialize_with = "crate::amount::deserialize_optional")]
+    pub fee: Option<Amount>,
     #[serde(default)]
     pub dust: Option<String>,
     #[serde(default)]
@@ -0,0 +1,197 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+
+use crate::error::Error;
+
+/// Number of decimals of every Mixin amount, the kernel stores amounts as
+/// integers of 10^-8 units.
+pub const AMOUNT_DECIMALS: u32 = 8;
+
+const UNIT: i128 = 10i128.pow(AMOUNT_DECIMALS);
+
+/// An exact decimal amount with 8 decimals, kept as an integer of units.
+///
+/// Parsing rejects more than 8 decimals instead of rounding, and serde uses
+/// the decimal string encoding of the Mixin API. Amounts are signed because
+/// snapshots report outgoing changes as negative ones, kernel outputs never
+/// are.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(i128);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    pub const fn from_units(units: i128) -> Self {
+        Self(units)
+    }
+
+    pub const fn units(&self) -> i128 {
+        self.0
+    }
+
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let invalid = || Error::Input(format!("invalid amount: {s}"));
+        let (negative, unsigned) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let (int, frac) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+        if int.is_empty() && frac.is_empty()
+            || frac.len() > AMOUNT_DECIMALS as usize
+            || !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+        let int: i128 = if int.is_empty() {
+            0
+        } else {
+            int.parse().map_err(|_| invalid())?
+        };
+        let frac: i128 = format!("{frac:0<width$}", width = AMOUNT_DECIMALS as usize)
+            .parse()
+            .map_err(|_| invalid())?;
+        let units = int
+            .checked_mul(UNIT)
+            .and_then(|v| v.checked_add(frac))
+            .ok_or_else(invalid)?;
+        Ok(Self(if negative { -units } else { units }))
+    }
+
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+
+    /// The sum of `amounts`, failing on overflow.
+    pub fn checked_sum<I>(amounts: I) -> Result<Amount, Error>
+    where
+        I: IntoIterator<Item = Amount>,
+    {
+        amounts.into_iter().try_fold(Amount::ZERO, |total, amount| {
+            total
+                .checked_add(amount)
+                .ok_or_else(|| Error::Input("amount overflow".to_string()))
+        })
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.0 > 0
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0 < 0
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let units = self.0.unsigned_abs();
+        let unit = UNIT as u128;
+        let frac = units % unit;
+        if frac == 0 {
+            return write!(f, "{sign}{}", units / unit);
+        }
+        let frac = format!("{frac:0width$}", width = AMOUNT_DECIMALS as usize);
+        write!(f, "{sign}{}.{}", units / unit, frac.trim_end_matches('0'))
+    }
+}
+
+impl FromStr for Amount {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        Amount::parse(&s).map_err(de::Error::custom)
+    }
+}
+
+/// Deserializes an optional amount, reading the empty string the API sends
+/// for amounts it has no value for as `None`.
+pub fn deserialize_optional<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Amount>, D::Error> {
+    let s = <Option<std::borrow::Cow<'de, str>>>::deserialize(deserializer)?;
+    match s.as_deref() {
+        None | Some("") => Ok(None),
+        Some(s) => Amount::parse(s).map(Some).map_err(de::Error::custom),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn amount(s: &str) -> Amount {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_amount_parse_and_format() {
+        assert_eq!(amount("0.5").units(), 50_000_000);
+        assert_eq!(amount("12").units(), 1_200_000_000);
+        assert_eq!(amount(".1").units(), 10_000_000);
+        assert_eq!(amount("-1.25").units(), -125_000_000);
+        assert_eq!(amount("0.00000001").to_string(), "0.00000001");
+        assert_eq!(amount("1.10000000").to_string(), "1.1");
+        assert_eq!(amount("-0.5").to_string(), "-0.5");
+        assert_eq!(Amount::from_units(100_000_000).to_string(), "1");
+        for invalid in ["", ".", "-", "0.123456789", "1e8", "1,5", "--1", " 1"] {
+            assert!(Amount::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_amount_arithmetic() {
+        assert_eq!(
+            amount("0.1").checked_add(amount("0.2")),
+            Some(amount("0.3"))
+        );
+        assert_eq!(
+            amount("0.1").checked_sub(amount("0.3")),
+            Some(amount("-0.2"))
+        );
+        assert!(
+            Amount::from_units(i128::MAX)
+                .checked_add(amount("0.00000001"))
+                .is_none()
+        );
+        assert!(amount("0.3") > amount("0.29999999"));
+        assert_eq!(
+            Amount::checked_sum([amount("1"), amount("0.5"), amount("0.25")]).unwrap(),
+            amount("1.75")
+        );
+    }
+
+    #[test]
+    fn test_amount_serde() {
+        let value: Option<Amount> = serde_json::from_str(r#""0.01""#).unwrap();
+        assert_eq!(value, Some(amount("0.01")));
+        assert_eq!(
+            serde_json::to_string(&amount("100.5")).unwrap(),
+            r#""100.5""#
+        );
+        assert!(serde_json::from_str::<Amount>(r#""0.000000001""#).is_err());
+    }
+}
@@ -2,6 +2,7 @@ use serde::{Deserialize, Serialize};
 use url::form_urlencoded;
 
 use crate::{
+    amount::Amount,
     auth::sign_authentication_token,
     error::Error,
     models::Asset,
@@ -14,7 +15,8 @@ pub struct AssetFee {
     #[serde(rename = "type")]
     pub fee_type: Option<String>,
     pub asset_id: Option<String>,
-    pub amount: Option<String>,
+    #[serde(default, deserialize_with = "crate::amount::deserialize_optional")]
+    pub amount: Option<Amount>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Default, Clone)]
@@ -30,7 +32,8 @@ pub struct AssetNetwork {
     pub change_usd: Option<String>,
     pub change_btc: Option<String>,
     pub confirmations: Option<i64>,
-    pub balance: Option<String>,
+    #[serde(default, deserialize_with = "crate::amount::deserialize_optional")]
+    pub balance: Option<Amount>,
 }
 
 pub async fn list_assets(safe_user: &SafeUser) -> Result<Vec<Asset>, Error> {
@@ -128,7 +131,7 @@ mod tests {
         }"#;
         let fee: AssetFee = serde_json::from_str(raw).expect("fee");
         assert_eq!(fee.asset_id.as_deref(), Some("asset-id"));
-        assert_eq!(fee.amount.as_deref(), Some("0.01"));
+        assert_eq!(fee.amount, Some("0.01".parse().unwrap()));
     }
 
     #[test]
@@ -0,0 +1,359 @@
+use aes::Aes256;
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use cbc::{Decryptor, Encryptor};
+use cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit, block_padding::Pkcs7};
+use rand::RngCore;
+use reqwest::header::CONTENT_TYPE;
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    auth::sign_authentication_token,
+    error::Error,
+    message::{AudioMessage, DataMessage, ImageMessage, VideoMessage},
+    request::{ApiResponse, HTTP_CLIENT, request},
+    safe::SafeUser,
+};
+
+const ATTACHMENT_KEY_SIZE: usize = 64;
+const IV_SIZE: usize = 16;
+const MAC_SIZE: usize = 32;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Attachment {
+    #[serde(rename = "type", default)]
+    pub type_name: Option<String>,
+    pub attachment_id: String,
+    /// Only set on a newly created attachment.
+    #[serde(default)]
+    pub upload_url: Option<String>,
+    #[serde(default)]
+    pub view_url: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+}
+
+/// An uploaded attachment, with the key and digest to put in the message
+/// payload when it was encrypted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UploadedAttachment {
+    pub attachment_id: String,
+    /// The size of the plain data.
+    pub size: u64,
+    pub key: Option<String>,
+    pub digest: Option<String>,
+}
+
+impl UploadedAttachment {
+    pub fn image(&self, mime_type: &str, width: u32, height: u32) -> ImageMessage {
+        ImageMessage {
+            attachment_id: self.attachment_id.clone(),
+            mime_type: mime_type.to_string(),
+            width,
+            height,
+            size: self.size,
+            thumbnail: None,
+            key: self.key.clone(),
+            digest: self.digest.clone(),
+        }
+    }
+
+    pub fn data(&self, mime_type: &str, name: &str) -> DataMessage {
+        DataMessage {
+            attachment_id: self.attachment_id.clone(),
+            mime_type: mime_type.to_string(),
+            size: self.size,
+            name: name.to_string(),
+            key: self.key.clone(),
+            digest: self.digest.clone(),
+        }
+    }
+
+    /// `duration` in milliseconds.
+    pub fn audio(&self, mime_type: &str, duration: u64) -> AudioMessage {
+        AudioMessage {
+            attachment_id: self.attachment_id.clone(),
+            mime_type: mime_type.to_string(),
+            size: self.size,
+            duration,
+            wave_form: None,
+            key: self.key.clone(),
+            digest: self.digest.clone(),
+        }
+    }
+
+    /// `duration` in milliseconds.
+    pub fn video(&self, mime_type: &str, width: u32, height: u32, duration: u64) -> VideoMessage {
+        VideoMessage {
+            attachment_id: self.attachment_id.clone(),
+            mime_type: mime_type.to_string(),
+            width,
+            height,
+            size: self.size,
+            duration,
+            thumbnail: None,
+            key: self.key.clone(),
+            digest: self.digest.clone(),
+        }
+    }
+}
+
+/// Creates an upload slot, the returned `upload_url` accepts one PUT.
+pub async fn create_attachment(safe_user: &SafeUser) -> Result<Attachment, Error> {
+    let path = "/attachments";
+    let token = sign_authentication_token("POST", path, "", safe_user)?;
+    let body = request("POST", path, &[], &token).await?;
+    parse_attachment(&body)
+}
+
+/// Reads an attachment, with a fresh `view_url` to download it from.
+pub async fn read_attachment(
+    attachment_id: &str,
+    safe_user: &SafeUser,
+) -> Result<Attachment, Error> {
+    let path = format!("/attachments/{attachment_id}");
+    let token = sign_authentication_token("GET", &path, "", safe_user)?;
+    let body = request("GET", &path, &[], &token).await?;
+    parse_attachment(&body)
+}
+
+/// Uploads `data` to a new attachment, encrypting it first when `encrypt`
+/// is set.
+pub async fn upload_attachment(
+    data: &[u8],
+    encrypt: bool,
+    safe_user: &SafeUser,
+) -> Result<UploadedAttachment, Error> {
+    let attachment = create_attachment(safe_user).await?;
+    let upload_url = attachment
+        .upload_url
+        .as_deref()
+        .ok_or_else(|| Error::DataNotFound("attachment upload_url".to_string()))?;
+    let mut uploaded = UploadedAttachment {
+        attachment_id: attachment.attachment_id.clone(),
+        size: data.len() as u64,
+        key: None,
+        digest: None,
+    };
+    if encrypt {
+        let encrypted = encrypt_attachment(data)?;
+        put_attachment_data(upload_url, &encrypted.data).await?;
+        uploaded.key = Some(encrypted.key);
+        uploaded.digest = Some(encrypted.digest);
+    } else {
+        put_attachment_data(upload_url, data).await?;
+    }
+    Ok(uploaded)
+}
+
+/// Downloads an attachment, decrypting it when the message payload carried
+/// a key and digest.
+pub async fn download_attachment(
+    attachment_id: &str,
+    key: Option<&str>,
+    digest: Option<&str>,
+    safe_user: &SafeUser,
+) -> Result<Vec<u8>, Error> {
+    let attachment = read_attachment(attachment_id, safe_user).await?;
+    let view_url = attachment
+        .view_url
+        .as_deref()
+        .ok_or_else(|| Error::DataNotFound("attachment view_url".to_string()))?;
+    let data = get_attachment_data(view_url).await?;
+    match (key, digest) {
+        (Some(key), Some(digest)) => decrypt_attachment(&data, key, digest),
+        (None, None) => Ok(data),
+        _ => Err(Error::Input(
+            "attachment key and digest go together".to_string(),
+        )),
+    }
+}
+
+pub async fn put_attachment_data(upload_url: &str, data: &[u8]) -> Result<(), Error> {
+    let response = HTTP_CLIENT
+        .put(upload_url)
+        .header(CONTENT_TYPE, "application/octet-stream")
+        .header("x-amz-acl", "public-read")
+        .body(data.to_vec())
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(Error::Server(format!(
+            "attachment upload failed: {}",
+            response.status()
+        )));
+    }
+    Ok(())
+}
+
+pub async fn get_attachment_data(view_url: &str) -> Result<Vec<u8>, Error> {
+    let response = HTTP_CLIENT.get(view_url).send().await?;
+    if !response.status().is_success() {
+        return Err(Error::Server(format!(
+            "attachment download failed: {}",
+            response.status()
+        )));
+    }
+    Ok(response.bytes().await?.to_vec())
+}
+
+#[derive(Debug, Clone)]
+pub struct EncryptedAttachment {
+    pub data: Vec<u8>,
+    /// Base64 of the AES key followed by the HMAC key.
+    pub key: String,
+    /// Base64 of the SHA-256 of `data`.
+    pub digest: String,
+}
+
+/// Encrypts attachment data with AES-256-CBC under a random key, followed by
+/// an HMAC-SHA256 of the IV and ciphertext.
+pub fn encrypt_attachment(data: &[u8]) -> Result<EncryptedAttachment, Error> {
+    let mut key = [0u8; ATTACHMENT_KEY_SIZE];
+    rand::rngs::OsRng.fill_bytes(&mut key);
+    let mut iv = [0u8; IV_SIZE];
+    rand::rngs::OsRng.fill_bytes(&mut iv);
+
+    let ciphertext = Encryptor::<Aes256>::new_from_slices(&key[..32], &iv)
+        .map_err(|e| Error::Input(format!("invalid attachment key or iv: {e}")))?
+        .encrypt_padded_vec_mut::<Pkcs7>(data);
+    let mut encrypted = Vec::with_capacity(IV_SIZE + ciphertext.len() + MAC_SIZE);
+    encrypted.extend_from_slice(&iv);
+    encrypted.extend_from_slice(&ciphertext);
+    let mac = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, &key[32..]), &encrypted);
+    encrypted.extend_from_slice(mac.as_ref());
+
+    let digest = Sha256::digest(&encrypted);
+    Ok(EncryptedAttachment {
+        data: encrypted,
+        key: STANDARD.encode(key),
+        digest: STANDARD.encode(digest),
+    })
+}
+
+/// Checks the digest and HMAC of downloaded attachment data and decrypts it.
+pub fn decrypt_attachment(data: &[u8], key: &str, digest: &str) -> Result<Vec<u8>, Error> {
+    let key = STANDARD
+        .decode(key)
+        .ok()
+        .filter(|k| k.len() == ATTACHMENT_KEY_SIZE)
+        .ok_or_else(|| Error::Input("invalid attachment key".to_string()))?;
+    let digest = STANDARD
+        .decode(digest)
+        .map_err(|_| Error::Input("invalid attachment digest".to_string()))?;
+    if data.len() < IV_SIZE + MAC_SIZE || Sha256::digest(data)[..] != digest[..] {
+        return Err(Error::Input("attachment digest mismatch".to_string()));
+    }
+
+    let (encrypted, mac) = data.split_at(data.len() - MAC_SIZE);
+    hmac::verify(
+        &hmac::Key::new(hmac::HMAC_SHA256, &key[32..]),
+        encrypted,
+        mac,
+    )
+    .map_err(|_| Error::Input("attachment mac mismatch".to_string()))?;
+    let (iv, ciphertext) = encrypted.split_at(IV_SIZE);
+    Decryptor::<Aes256>::new_from_slices(&key[..32], iv)
+        .map_err(|e| Error::Input(format!("invalid attachment key or iv: {e}")))?
+        .decrypt_padded_vec_mut::<Pkcs7>(ciphertext)
+        .map_err(|_| Error::Input("invalid attachment padding".to_string()))
+}
+
+fn parse_attachment(body: &[u8]) -> Result<Attachment, Error> {
+    let parsed: ApiResponse<Attachment> = serde_json::from_slice(body)?;
+    if let Some(api_error) = parsed.error {
+        return Err(Error::Api(api_error));
+    }
+    parsed
+        .data
+        .ok_or_else(|| Error::DataNotFound("API response did not contain attachment".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_attachment_encryption() {
+        let data = b"attachment data".repeat(10);
+        let encrypted = encrypt_attachment(&data).unwrap();
+        assert_eq!(encrypted.data.len(), IV_SIZE + 160 + MAC_SIZE);
+        assert_eq!(
+            decrypt_attachment(&encrypted.data, &encrypted.key, &encrypted.digest).unwrap(),
+            data
+        );
+
+        let mut tampered = encrypted.data.clone();
+        tampered[IV_SIZE] ^= 1;
+        let digest = STANDARD.encode(Sha256::digest(&tampered));
+        assert!(decrypt_attachment(&tampered, &encrypted.key, &encrypted.digest).is_err());
+        assert!(decrypt_attachment(&tampered, &encrypted.key, &digest).is_err());
+
+        let uploaded = UploadedAttachment {
+            attachment_id: "attachment-id".to_string(),
+            size: data.len() as u64,
+            key: Some(encrypted.key),
+            digest: Some(encrypted.digest),
+        };
+        let image = uploaded.image("image/png", 10, 20);
+        assert_eq!(image.size, 150);
+        assert_eq!(image.key, uploaded.key);
+    }
+
+    #[tokio::test]
+    async fn test_attachment_upload_and_download() {
+        // A storage stand-in that keeps the body of a PUT and serves it back.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/attachment", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let mut stored = Vec::new();
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                let body_start = loop {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    if let Some(i) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break i + 4;
+                    }
+                };
+                let head = String::from_utf8_lossy(&request[..body_start]).to_lowercase();
+                let length: usize = head
+                    .lines()
+                    .find_map(|l| l.strip_prefix("content-length: "))
+                    .map_or(0, |v| v.trim().parse().unwrap());
+                while request.len() < body_start + length {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+
+                let body = if head.starts_with("put ") {
+                    assert!(head.contains("x-amz-acl: public-read"));
+                    stored = request[body_start..].to_vec();
+                    Vec::new()
+                } else {
+                    stored.clone()
+                };
+                let header = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                    body.len()
+                );
+                stream.write_all(header.as_bytes()).await.unwrap();
+                stream.write_all(&body).await.unwrap();
+            }
+        });
+
+        let encrypted = encrypt_attachment(b"hello").unwrap();
+        put_attachment_data(&url, &encrypted.data).await.unwrap();
+        let downloaded = get_attachment_data(&url).await.unwrap();
+        assert_eq!(
+            decrypt_attachment(&downloaded, &encrypted.key, &encrypted.digest).unwrap(),
+            b"hello"
+        );
+        server.await.unwrap();
+    }
+}
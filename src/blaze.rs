@@ -0,0 +1,952 @@
+use std::collections::{HashSet, VecDeque};
+use std::io::{Read, Write};
+use std::sync::Once;
+use std::time::Duration;
+
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+use tokio_tungstenite::{
+    MaybeTlsStream, WebSocketStream, connect_async_with_config,
+    tungstenite::{
+        client::IntoClientRequest,
+        http::{
+            HeaderValue,
+            header::{AUTHORIZATION, SEC_WEBSOCKET_PROTOCOL},
+        },
+        protocol::Message,
+    },
+};
+use uuid::Uuid;
+
+use crate::{
+    auth::sign_authentication_token,
+    encrypted_message::decrypt_message_data,
+    error::Error,
+    message::{
+        MessageCategory, MessagePayload, MessageRequest, PinMessage, RecallMessage,
+        ReceiptAcknowledgementRequest, decode_message_data, post_messages,
+    },
+    request::{ApiError, get_blaze_uri},
+    safe::SafeUser,
+    snapshot::SafeSnapshot,
+};
+
+pub const BLAZE_SUBPROTOCOL: &str = "Mixin-Blaze-1";
+
+pub const ACTION_CREATE_MESSAGE: &str = "CREATE_MESSAGE";
+pub const ACTION_ACKNOWLEDGE_MESSAGE_RECEIPT: &str = "ACKNOWLEDGE_MESSAGE_RECEIPT";
+pub const ACTION_LIST_PENDING_MESSAGES: &str = "LIST_PENDING_MESSAGES";
+pub const ACTION_CREATE_PLAIN_MESSAGES: &str = "CREATE_PLAIN_MESSAGES";
+
+const DEFAULT_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+const DEFAULT_ACK_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(15);
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(45);
+
+type BlazeSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+static RUSTLS_PROVIDER: Once = Once::new();
+
+/// The envelope of every Blaze frame, sent and received gzip compressed.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BlazeMessage {
+    pub id: String,
+    pub action: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ApiError>,
+}
+
+impl BlazeMessage {
+    pub fn new(action: &str, params: Option<Value>) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            action: action.to_string(),
+            params,
+            data: None,
+            error: None,
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        let json = serde_json::to_vec(self)?;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(&json)
+            .map_err(|e| Error::Input(format!("blaze gzip: {e}")))?;
+        encoder
+            .finish()
+            .map_err(|e| Error::Input(format!("blaze gzip: {e}")))
+    }
+
+    pub fn decode(frame: &[u8]) -> Result<Self, Error> {
+        let mut json = Vec::new();
+        GzDecoder::new(frame)
+            .read_to_end(&mut json)
+            .map_err(|e| Error::Server(format!("invalid blaze frame: {e}")))?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+}
+
+/// A message delivered by `CREATE_MESSAGE`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct MessageView {
+    #[serde(rename = "type", default)]
+    pub type_name: Option<String>,
+    pub conversation_id: String,
+    pub user_id: String,
+    #[serde(default)]
+    pub session_id: Option<String>,
+    pub message_id: String,
+    pub category: MessageCategory,
+    #[serde(default)]
+    pub data_base64: String,
+    #[serde(default)]
+    pub representative_id: Option<String>,
+    #[serde(default)]
+    pub quote_message_id: Option<String>,
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub updated_at: Option<String>,
+}
+
+impl MessageView {
+    pub fn is_system(&self) -> bool {
+        self.category.is_system()
+    }
+
+    pub fn data(&self) -> Result<Vec<u8>, Error> {
+        decode_message_data(&self.data_base64)
+    }
+
+    pub fn payload(&self) -> Result<MessagePayload, Error> {
+        MessagePayload::decode(&self.category, &self.data()?)
+    }
+
+    /// The plain view of an ENCRYPTED_* message for our session, other
+    /// messages are returned unchanged.
+    pub fn decrypt(&self, safe_user: &SafeUser) -> Result<MessageView, Error> {
+        if !self.category.is_encrypted() {
+            return Ok(self.clone());
+        }
+        let data = decrypt_message_data(&self.data_base64, safe_user)?.ok_or_else(|| {
+            Error::DataNotFound(format!(
+                "message {} is not encrypted for this session",
+                self.message_id
+            ))
+        })?;
+        Ok(MessageView {
+            category: self.category.to_plain(),
+            data_base64: STANDARD.encode(data),
+            ..self.clone()
+        })
+    }
+
+    pub fn data_json<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        Ok(serde_json::from_slice(&self.data()?)?)
+    }
+
+    /// A reply quoting this message, sent to its conversation.
+    pub fn reply(
+        &self,
+        message_id: &str,
+        payload: &MessagePayload,
+    ) -> Result<MessageRequest, Error> {
+        Ok(
+            MessageRequest::new(&self.conversation_id, message_id, payload)?
+                .with_recipient_id(&self.user_id)
+                .with_quote(&self.message_id),
+        )
+    }
+}
+
+/// The status update of a message we sent, from `ACKNOWLEDGE_MESSAGE_RECEIPT`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MessageReceipt {
+    pub message_id: String,
+    pub status: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum BlazeEvent {
+    Message(MessageView),
+    SafeSnapshot {
+        message: MessageView,
+        snapshot: Box<SafeSnapshot>,
+    },
+    /// A sender took a message back, clients hide it.
+    Recall {
+        message: MessageView,
+        recall: RecallMessage,
+    },
+    Pin {
+        message: MessageView,
+        pin: PinMessage,
+    },
+    /// Any other `SYSTEM_*` message, e.g. conversation changes.
+    System(MessageView),
+    Receipt(MessageReceipt),
+}
+
+impl BlazeEvent {
+    /// The typed event of a received frame, `None` for frames that carry no
+    /// event such as the replies to our own requests.
+    pub fn from_blaze_message(message: &BlazeMessage) -> Result<Option<Self>, Error> {
+        let Some(data) = message.data.clone().filter(|d| !d.is_null()) else {
+            return Ok(None);
+        };
+        match message.action.as_str() {
+            ACTION_ACKNOWLEDGE_MESSAGE_RECEIPT => {
+                Ok(Some(BlazeEvent::Receipt(serde_json::from_value(data)?)))
+            }
+            ACTION_CREATE_MESSAGE => {
+                let view: MessageView = serde_json::from_value(data)?;
+                if view.category == MessageCategory::SystemSafeSnapshot {
+                    // An unreadable snapshot is still reported, as a system message.
+                    match view.data_json() {
+                        Ok(snapshot) => {
+                            return Ok(Some(BlazeEvent::SafeSnapshot {
+                                message: view,
+                                snapshot: Box::new(snapshot),
+                            }));
+                        }
+                        Err(e) => log::warn!("blaze snapshot {}: {e}", view.message_id),
+                    }
+                }
+                if view.is_system() {
+                    return Ok(Some(BlazeEvent::System(view)));
+                }
+                match view.payload() {
+                    Ok(MessagePayload::Recall(recall)) => {
+                        return Ok(Some(BlazeEvent::Recall {
+                            message: view,
+                            recall,
+                        }));
+                    }
+                    Ok(MessagePayload::Pin(pin)) => {
+                        return Ok(Some(BlazeEvent::Pin { message: view, pin }));
+                    }
+                    _ => {}
+                }
+                Ok(Some(BlazeEvent::Message(view)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// The id of the received message to acknowledge, receipts need none.
+    pub fn message_id(&self) -> Option<&str> {
+        match self {
+            BlazeEvent::Message(view) | BlazeEvent::System(view) => Some(&view.message_id),
+            BlazeEvent::SafeSnapshot { message, .. }
+            | BlazeEvent::Recall { message, .. }
+            | BlazeEvent::Pin { message, .. } => Some(&message.message_id),
+            BlazeEvent::Receipt(_) => None,
+        }
+    }
+}
+
+/// How a message sent with `BlazeClient::send_message` was delivered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    Blaze,
+    /// Blaze did not confirm in time, the message went through
+    /// `POST /messages` with the same message id.
+    Http,
+}
+
+/// A Blaze connection which reconnects by itself.
+///
+/// Every (re)connection signs a new session token and asks for the pending
+/// messages, messages are acknowledged as READ when they are returned by
+/// `next_event` unless auto acknowledgement is turned off. An idle
+/// connection is pinged, and one that sends nothing back for the read
+/// timeout is dropped and reconnected.
+pub struct BlazeClient {
+    url: String,
+    socket: Option<BlazeSocket>,
+    auto_ack: bool,
+    reconnect_delay: Duration,
+    max_reconnect_delay: Duration,
+    max_reconnects: Option<u32>,
+    ack_timeout: Duration,
+    ping_interval: Duration,
+    read_timeout: Duration,
+    last_read: Instant,
+    // Events read while waiting for the reply to a request.
+    events: VecDeque<BlazeEvent>,
+    // Requests given up on, their late replies are dropped.
+    abandoned: HashSet<String>,
+}
+
+impl Default for BlazeClient {
+    fn default() -> Self {
+        Self::new(&format!("wss://{}/", get_blaze_uri()))
+    }
+}
+
+impl BlazeClient {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            socket: None,
+            auto_ack: true,
+            reconnect_delay: DEFAULT_RECONNECT_DELAY,
+            max_reconnect_delay: MAX_RECONNECT_DELAY,
+            max_reconnects: None,
+            ack_timeout: DEFAULT_ACK_TIMEOUT,
+            ping_interval: DEFAULT_PING_INTERVAL,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            last_read: Instant::now(),
+            events: VecDeque::new(),
+            abandoned: HashSet::new(),
+        }
+    }
+
+    pub fn with_auto_ack(mut self, auto_ack: bool) -> Self {
+        self.auto_ack = auto_ack;
+        self
+    }
+
+    pub fn set_auto_ack(&mut self, auto_ack: bool) {
+        self.auto_ack = auto_ack;
+    }
+
+    /// The first reconnect waits `initial`, doubling up to `max`.
+    pub fn with_reconnect_delay(mut self, initial: Duration, max: Duration) -> Self {
+        self.reconnect_delay = initial;
+        self.max_reconnect_delay = max.max(initial);
+        self
+    }
+
+    /// Gives up after `max` failed connection attempts in a row.
+    pub fn with_max_reconnects(mut self, max: u32) -> Self {
+        self.max_reconnects = Some(max);
+        self
+    }
+
+    /// How long a request waits for its reply before giving up.
+    pub fn with_ack_timeout(mut self, timeout: Duration) -> Self {
+        self.ack_timeout = timeout;
+        self
+    }
+
+    /// Pings the server after `ping_interval` without frames and drops the
+    /// connection after `read_timeout` without any, pongs included.
+    pub fn with_keepalive(mut self, ping_interval: Duration, read_timeout: Duration) -> Self {
+        self.ping_interval = ping_interval;
+        self.read_timeout = read_timeout.max(ping_interval);
+        self
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    pub async fn connect(&mut self, safe_user: &SafeUser) -> Result<(), Error> {
+        RUSTLS_PROVIDER.call_once(|| {
+            let _ = rustls::crypto::ring::default_provider().install_default();
+        });
+
+        let token = sign_authentication_token("GET", "/", "", safe_user)?;
+        let mut request = self
+            .url
+            .as_str()
+            .into_client_request()
+            .map_err(|e| Error::Input(format!("invalid blaze url: {e}")))?;
+        request.headers_mut().insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {token}"))
+                .map_err(|e| Error::Server(e.to_string()))?,
+        );
+        request.headers_mut().insert(
+            SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static(BLAZE_SUBPROTOCOL),
+        );
+        // Nagle would hold small frames such as acknowledgements back.
+        let (socket, _) = connect_async_with_config(request, None, true)
+            .await
+            .map_err(|e| Error::Server(format!("blaze connect: {e}")))?;
+        self.socket = Some(socket);
+        self.last_read = Instant::now();
+        self.send(&BlazeMessage::new(ACTION_LIST_PENDING_MESSAGES, None))
+            .await
+    }
+
+    /// Waits for the next event, reconnecting with backoff whenever the
+    /// connection drops. Error replies to no pending request are logged and
+    /// skipped.
+    pub async fn next_event(&mut self, safe_user: &SafeUser) -> Result<BlazeEvent, Error> {
+        loop {
+            let event = match self.events.pop_front() {
+                Some(event) => event,
+                None => {
+                    let message = self.next_message(safe_user).await?;
+                    if self.abandoned.remove(&message.id) {
+                        continue;
+                    }
+                    if let Some(error) = message.error {
+                        log::warn!("blaze {} {}: {error}", message.action, message.id);
+                        continue;
+                    }
+                    match BlazeEvent::from_blaze_message(&message)? {
+                        Some(event) => event,
+                        None => continue,
+                    }
+                }
+            };
+            if self.auto_ack
+                && let Some(message_id) = event.message_id()
+            {
+                self.acknowledge(message_id).await?;
+            }
+            return Ok(event);
+        }
+    }
+
+    /// Sends a message with `CREATE_MESSAGE`, falling back to
+    /// `POST /messages` when Blaze is down or does not confirm in time.
+    /// An error reply from Blaze fails the send without a fallback.
+    pub async fn send_message(
+        &mut self,
+        message: &MessageRequest,
+        safe_user: &SafeUser,
+    ) -> Result<Delivery, Error> {
+        let params = serde_json::to_value(message)?;
+        self.send_with_fallback(
+            ACTION_CREATE_MESSAGE,
+            params,
+            std::slice::from_ref(message),
+            safe_user,
+        )
+        .await
+    }
+
+    /// Sends a batch with `CREATE_PLAIN_MESSAGES`, with the same fallback as
+    /// `send_message`.
+    pub async fn send_messages(
+        &mut self,
+        messages: &[MessageRequest],
+        safe_user: &SafeUser,
+    ) -> Result<Delivery, Error> {
+        let params = serde_json::json!({ "messages": messages });
+        self.send_with_fallback(ACTION_CREATE_PLAIN_MESSAGES, params, messages, safe_user)
+            .await
+    }
+
+    /// Sends `message` and waits for the reply with the same id, queueing
+    /// the events received meanwhile for `next_event`.
+    pub async fn request(&mut self, message: &BlazeMessage) -> Result<BlazeMessage, Error> {
+        self.send(message).await?;
+        let timeout = self.ack_timeout;
+        let wait = async {
+            loop {
+                let Some(reply) = self.read_frame().await? else {
+                    return Err(Error::Server("blaze connection closed".to_string()));
+                };
+                if reply.id == message.id {
+                    return Ok(reply);
+                }
+                if self.abandoned.remove(&reply.id) || reply.error.is_some() {
+                    continue;
+                }
+                // Left unacknowledged, so an unreadable frame is sent again.
+                match BlazeEvent::from_blaze_message(&reply) {
+                    Ok(Some(event)) => self.events.push_back(event),
+                    Ok(None) => {}
+                    Err(e) => log::warn!("blaze {} {}: {e}", reply.action, reply.id),
+                }
+            }
+        };
+        match tokio::time::timeout(timeout, wait).await {
+            Ok(Ok(reply)) => match reply.error {
+                Some(error) => Err(Error::Api(error)),
+                None => Ok(reply),
+            },
+            Ok(Err(e)) => Err(e),
+            Err(_) => {
+                self.abandoned.insert(message.id.clone());
+                Err(Error::Server(format!(
+                    "blaze {} timed out after {:?}",
+                    message.action, timeout
+                )))
+            }
+        }
+    }
+
+    pub async fn acknowledge(&mut self, message_id: &str) -> Result<(), Error> {
+        let params = ReceiptAcknowledgementRequest {
+            message_id: message_id.to_string(),
+            status: "READ".to_string(),
+        };
+        self.send(&BlazeMessage::new(
+            ACTION_ACKNOWLEDGE_MESSAGE_RECEIPT,
+            Some(serde_json::to_value(params)?),
+        ))
+        .await
+    }
+
+    pub async fn send(&mut self, message: &BlazeMessage) -> Result<(), Error> {
+        let frame = message.encode()?;
+        let socket = self
+            .socket
+            .as_mut()
+            .ok_or_else(|| Error::Server("blaze is not connected".to_string()))?;
+        if let Err(e) = socket.send(Message::Binary(frame.into())).await {
+            self.socket = None;
+            return Err(Error::Server(format!("blaze send: {e}")));
+        }
+        Ok(())
+    }
+
+    pub async fn close(&mut self) -> Result<(), Error> {
+        if let Some(mut socket) = self.socket.take() {
+            socket
+                .close(None)
+                .await
+                .map_err(|e| Error::Server(format!("blaze close: {e}")))?;
+        }
+        Ok(())
+    }
+
+    async fn send_with_fallback(
+        &mut self,
+        action: &str,
+        params: Value,
+        messages: &[MessageRequest],
+        safe_user: &SafeUser,
+    ) -> Result<Delivery, Error> {
+        if self.socket.is_none() {
+            // A failed connection is handled by the fallback below.
+            let _ = self.connect(safe_user).await;
+        }
+        if self.socket.is_some() {
+            match self.request(&BlazeMessage::new(action, Some(params))).await {
+                Ok(_) => return Ok(Delivery::Blaze),
+                Err(e @ Error::Api(_)) => return Err(e),
+                Err(_) => {}
+            }
+        }
+        post_messages(messages, safe_user).await?;
+        Ok(Delivery::Http)
+    }
+
+    // Reads the next frame, (re)connecting first if there is no connection.
+    async fn next_message(&mut self, safe_user: &SafeUser) -> Result<BlazeMessage, Error> {
+        let mut attempts = 0;
+        loop {
+            if self.socket.is_none() {
+                if attempts > 0 {
+                    if self.max_reconnects.is_some_and(|max| attempts > max) {
+                        return Err(Error::Server(format!(
+                            "blaze reconnect failed after {} attempts",
+                            attempts - 1
+                        )));
+                    }
+                    tokio::time::sleep(self.backoff(attempts)).await;
+                }
+                attempts += 1;
+                match self.connect(safe_user).await {
+                    Ok(()) => {}
+                    Err(e @ (Error::Auth(_) | Error::Input(_))) => return Err(e),
+                    Err(_) => continue,
+                }
+            }
+            if let Some(message) = self.read_frame().await? {
+                return Ok(message);
+            }
+        }
+    }
+
+    // Reads the next frame of the current connection, `None` once it is
+    // dropped or timed out.
+    async fn read_frame(&mut self) -> Result<Option<BlazeMessage>, Error> {
+        while let Some(socket) = self.socket.as_mut() {
+            let deadline = self.last_read + self.read_timeout;
+            let wait = deadline.min(Instant::now() + self.ping_interval);
+            let frame = match tokio::time::timeout_at(wait, socket.next()).await {
+                Ok(frame) => frame,
+                Err(_) if Instant::now() >= deadline => {
+                    self.socket = None;
+                    continue;
+                }
+                Err(_) => {
+                    if socket
+                        .send(Message::Ping(Default::default()))
+                        .await
+                        .is_err()
+                    {
+                        self.socket = None;
+                    }
+                    continue;
+                }
+            };
+            self.last_read = Instant::now();
+            match frame {
+                Some(Ok(Message::Binary(frame))) => return BlazeMessage::decode(&frame).map(Some),
+                Some(Ok(Message::Text(text))) => return Ok(Some(serde_json::from_str(&text)?)),
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => self.socket = None,
+                Some(Ok(_)) => {}
+            }
+        }
+        Ok(None)
+    }
+
+    fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempts.saturating_sub(1).min(16));
+        self.reconnect_delay
+            .saturating_mul(factor)
+            .min(self.max_reconnect_delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::test_user;
+    use crate::message::PinAction;
+    use serde_json::json;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::{
+        accept_hdr_async,
+        tungstenite::handshake::server::{ErrorResponse, Request, Response},
+    };
+
+    fn create_message(id: &str, category: &str, data: &[u8]) -> BlazeMessage {
+        BlazeMessage {
+            id: Uuid::new_v4().to_string(),
+            action: ACTION_CREATE_MESSAGE.to_string(),
+            params: None,
+            data: Some(json!({
+                "type": "message",
+                "conversation_id": "conversation-id",
+                "user_id": "user-id",
+                "message_id": id,
+                "category": category,
+                "data_base64": STANDARD.encode(data),
+                "status": "SENT",
+            })),
+            error: None,
+        }
+    }
+
+    // Accepts a Blaze connection, checking the token and subprotocol.
+    async fn accept(listener: &TcpListener) -> WebSocketStream<TcpStream> {
+        #[allow(clippy::result_large_err)]
+        fn handshake(request: &Request, mut response: Response) -> Result<Response, ErrorResponse> {
+            let auth = request.headers()[AUTHORIZATION].to_str().unwrap();
+            assert!(auth.starts_with("Bearer "));
+            assert_eq!(request.headers()[SEC_WEBSOCKET_PROTOCOL], BLAZE_SUBPROTOCOL);
+            response.headers_mut().insert(
+                SEC_WEBSOCKET_PROTOCOL,
+                HeaderValue::from_static(BLAZE_SUBPROTOCOL),
+            );
+            Ok(response)
+        }
+
+        let (stream, _) = listener.accept().await.unwrap();
+        accept_hdr_async(stream, handshake).await.unwrap()
+    }
+
+    async fn read_frame(socket: &mut WebSocketStream<TcpStream>) -> BlazeMessage {
+        loop {
+            if let Message::Binary(frame) = socket.next().await.unwrap().unwrap() {
+                return BlazeMessage::decode(&frame).unwrap();
+            }
+        }
+    }
+
+    async fn write_frame(socket: &mut WebSocketStream<TcpStream>, frame: BlazeMessage) {
+        let frame = frame.encode().unwrap();
+        socket.send(Message::Binary(frame.into())).await.unwrap();
+    }
+
+    #[test]
+    fn test_blaze_frame_events() {
+        let message = create_message("m1", "PLAIN_TEXT", b"hello");
+        let decoded = BlazeMessage::decode(&message.encode().unwrap()).unwrap();
+        assert_eq!(decoded, message);
+        match BlazeEvent::from_blaze_message(&decoded).unwrap() {
+            Some(BlazeEvent::Message(view)) => {
+                assert_eq!(view.data().unwrap(), b"hello");
+                assert_eq!(
+                    view.payload().unwrap(),
+                    MessagePayload::Text("hello".to_string())
+                );
+                assert!(!view.is_system());
+            }
+            event => panic!("unexpected event {event:?}"),
+        }
+
+        let snapshot = json!({"snapshot_id": "s1", "asset_id": "a1", "amount": "0.5"});
+        let message = create_message(
+            "m2",
+            "SYSTEM_SAFE_SNAPSHOT",
+            snapshot.to_string().as_bytes(),
+        );
+        match BlazeEvent::from_blaze_message(&message).unwrap() {
+            Some(BlazeEvent::SafeSnapshot { message, snapshot }) => {
+                assert_eq!(message.message_id, "m2");
+                assert_eq!(snapshot.snapshot_id, "s1");
+                assert_eq!(snapshot.amount.unwrap().to_string(), "0.5");
+            }
+            event => panic!("unexpected event {event:?}"),
+        }
+
+        let message = create_message("m3", "SYSTEM_SAFE_SNAPSHOT", b"not json");
+        assert!(matches!(
+            BlazeEvent::from_blaze_message(&message).unwrap(),
+            Some(BlazeEvent::System(view)) if view.message_id == "m3"
+        ));
+
+        let message = create_message("m3", "SYSTEM_CONVERSATION", b"{}");
+        assert!(matches!(
+            BlazeEvent::from_blaze_message(&message).unwrap(),
+            Some(BlazeEvent::System(_))
+        ));
+
+        let receipt = BlazeMessage {
+            data: Some(json!({"message_id": "m4", "status": "READ"})),
+            ..BlazeMessage::new(ACTION_ACKNOWLEDGE_MESSAGE_RECEIPT, None)
+        };
+        let event = BlazeEvent::from_blaze_message(&receipt).unwrap().unwrap();
+        assert_eq!(event.message_id(), None);
+
+        let reply = BlazeMessage::new(ACTION_LIST_PENDING_MESSAGES, None);
+        assert!(BlazeEvent::from_blaze_message(&reply).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_recall_pin_and_reply() {
+        let target = "7766b24c-1a03-4c3a-83a3-b4358266875d";
+        let recall = json!({ "message_id": target });
+        let message = create_message("m1", "MESSAGE_RECALL", recall.to_string().as_bytes());
+        match BlazeEvent::from_blaze_message(&message).unwrap() {
+            Some(BlazeEvent::Recall { message, recall }) => {
+                assert_eq!(message.message_id, "m1");
+                assert_eq!(recall.message_id, target);
+            }
+            event => panic!("unexpected event {event:?}"),
+        }
+
+        let pin = json!({ "action": "PIN", "message_ids": [target] });
+        let message = create_message("m2", "MESSAGE_PIN", pin.to_string().as_bytes());
+        let event = BlazeEvent::from_blaze_message(&message).unwrap().unwrap();
+        assert_eq!(event.message_id(), Some("m2"));
+        match event {
+            BlazeEvent::Pin { pin, .. } => {
+                assert_eq!(pin.action, PinAction::Pin);
+                assert_eq!(pin.message_ids, [target]);
+            }
+            event => panic!("unexpected event {event:?}"),
+        }
+
+        let message = create_message("m3", "PLAIN_TEXT", b"hello");
+        let Some(BlazeEvent::Message(view)) = BlazeEvent::from_blaze_message(&message).unwrap()
+        else {
+            panic!("expected a message");
+        };
+        let reply = view
+            .reply("r3", &MessagePayload::Text("hi".to_string()))
+            .unwrap();
+        assert_eq!(reply.conversation_id, view.conversation_id);
+        assert_eq!(reply.recipient_id.as_ref(), Some(&view.user_id));
+        assert_eq!(reply.quote_message_id.as_deref(), Some("m3"));
+    }
+
+    #[test]
+    fn test_reconnect_backoff() {
+        let client = BlazeClient::new("ws://127.0.0.1/")
+            .with_reconnect_delay(Duration::from_millis(100), Duration::from_secs(1));
+        assert_eq!(client.backoff(1), Duration::from_millis(100));
+        assert_eq!(client.backoff(2), Duration::from_millis(200));
+        assert_eq!(client.backoff(4), Duration::from_millis(800));
+        assert_eq!(client.backoff(5), Duration::from_secs(1));
+        assert_eq!(client.backoff(40), Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_blaze_client_with_local_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            // Serves one message after an error reply, drops the connection
+            // and serves another one on the reconnection.
+            for id in ["m1", "m2"] {
+                let mut socket = accept(&listener).await;
+
+                let list = read_frame(&mut socket).await;
+                assert_eq!(list.action, ACTION_LIST_PENDING_MESSAGES);
+                let error = BlazeMessage {
+                    error: Some(ApiError {
+                        status: 500,
+                        code: 500,
+                        description: "Internal Server Error".to_string(),
+                    }),
+                    ..BlazeMessage::new(ACTION_CREATE_MESSAGE, None)
+                };
+                write_frame(&mut socket, error).await;
+                let frame = create_message(id, "PLAIN_TEXT", id.as_bytes());
+                write_frame(&mut socket, frame).await;
+
+                let ack = read_frame(&mut socket).await;
+                assert_eq!(ack.action, ACTION_ACKNOWLEDGE_MESSAGE_RECEIPT);
+                let params = ack.params.unwrap();
+                assert_eq!(params["message_id"], id);
+                assert_eq!(params["status"], "READ");
+            }
+        });
+
+        let user = test_user();
+        let mut client = BlazeClient::new(&url)
+            .with_reconnect_delay(Duration::from_millis(10), Duration::from_millis(50))
+            .with_max_reconnects(3);
+        for id in ["m1", "m2"] {
+            match client.next_event(&user).await.unwrap() {
+                BlazeEvent::Message(view) => assert_eq!(view.message_id, id),
+                event => panic!("unexpected event {event:?}"),
+            }
+        }
+        server.await.unwrap();
+
+        // The server is gone, reconnecting gives up after the limit.
+        assert!(client.next_event(&user).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_blaze_reconnects_unresponsive_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            // Stops reading after the pending messages request, so the pings
+            // of the client are never answered.
+            let mut silent = accept(&listener).await;
+            read_frame(&mut silent).await;
+
+            let mut socket = accept(&listener).await;
+            read_frame(&mut socket).await;
+            write_frame(&mut socket, create_message("m1", "PLAIN_TEXT", b"")).await;
+            (silent, socket)
+        });
+
+        let user = test_user();
+        let mut client = BlazeClient::new(&url)
+            .with_auto_ack(false)
+            .with_keepalive(Duration::from_millis(50), Duration::from_millis(200))
+            .with_reconnect_delay(Duration::from_millis(10), Duration::from_millis(50))
+            .with_max_reconnects(3);
+        let started = Instant::now();
+        match client.next_event(&user).await.unwrap() {
+            BlazeEvent::Message(view) => assert_eq!(view.message_id, "m1"),
+            event => panic!("unexpected event {event:?}"),
+        }
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_blaze_send_with_ack_correlation() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/", listener.local_addr().unwrap());
+        let (notify_timeout, timed_out) = tokio::sync::oneshot::channel();
+
+        let server = tokio::spawn(async move {
+            let mut socket = accept(&listener).await;
+            assert_eq!(
+                read_frame(&mut socket).await.action,
+                ACTION_LIST_PENDING_MESSAGES
+            );
+
+            // An incoming message arrives before the reply.
+            let request = read_frame(&mut socket).await;
+            assert_eq!(request.action, ACTION_CREATE_MESSAGE);
+            assert_eq!(request.params.as_ref().unwrap()["message_id"], "out-1");
+            write_frame(&mut socket, create_message("in-1", "PLAIN_TEXT", b"hi")).await;
+            let reply = BlazeMessage {
+                id: request.id,
+                ..BlazeMessage::new(ACTION_CREATE_MESSAGE, None)
+            };
+            write_frame(&mut socket, reply).await;
+
+            let request = read_frame(&mut socket).await;
+            assert_eq!(request.action, ACTION_CREATE_PLAIN_MESSAGES);
+            assert_eq!(
+                request.params.as_ref().unwrap()["messages"][0]["message_id"],
+                "out-2"
+            );
+            let reply = BlazeMessage {
+                id: request.id,
+                error: Some(ApiError {
+                    status: 403,
+                    code: 403,
+                    description: "Forbidden".to_string(),
+                }),
+                ..BlazeMessage::new(ACTION_CREATE_PLAIN_MESSAGES, None)
+            };
+            write_frame(&mut socket, reply).await;
+
+            // Replies too late, after the client gave up.
+            let request = read_frame(&mut socket).await;
+            timed_out.await.unwrap();
+            let reply = BlazeMessage {
+                id: request.id,
+                ..BlazeMessage::new(ACTION_LIST_PENDING_MESSAGES, None)
+            };
+            write_frame(&mut socket, reply).await;
+            write_frame(&mut socket, create_message("in-2", "PLAIN_TEXT", b"")).await;
+            socket
+        });
+
+        let user = test_user();
+        let mut client = BlazeClient::new(&url)
+            .with_auto_ack(false)
+            .with_ack_timeout(Duration::from_millis(200));
+        client.connect(&user).await.unwrap();
+
+        let message = |id: &str| MessageRequest {
+            conversation_id: "conversation-id".to_string(),
+            recipient_id: None,
+            message_id: id.to_string(),
+            category: MessageCategory::PlainText,
+            data_base64: "aGk".to_string(),
+            representative_id: None,
+            quote_message_id: None,
+            checksum: None,
+            recipient_sessions: None,
+        };
+        let delivery = client.send_message(&message("out-1"), &user).await.unwrap();
+        assert_eq!(delivery, Delivery::Blaze);
+        let result = client.send_messages(&[message("out-2")], &user).await;
+        assert!(matches!(result, Err(Error::Api(e)) if e.code == 403));
+
+        let request = BlazeMessage::new(ACTION_LIST_PENDING_MESSAGES, None);
+        assert!(matches!(
+            client.request(&request).await,
+            Err(Error::Server(_))
+        ));
+        notify_timeout.send(()).unwrap();
+
+        // The queued message comes first and the late reply is skipped.
+        for id in ["in-1", "in-2"] {
+            match client.next_event(&user).await.unwrap() {
+                BlazeEvent::Message(view) => assert_eq!(view.message_id, id),
+                event => panic!("unexpected event {event:?}"),
+            }
+        }
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,596 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures_util::future::BoxFuture;
+use regex::Regex;
+
+use crate::{
+    blaze::{BlazeClient, BlazeEvent, MessageView},
+    error::Error,
+    message::{
+        MessageCategory, MessagePayload, MessageRequest, ReceiptAcknowledgementRequest,
+        post_acknowledgements, post_messages,
+    },
+    safe::SafeUser,
+    utils::unique_object_id,
+};
+
+type Handler = Arc<dyn Fn(Context) -> BoxFuture<'static, Result<(), Error>> + Send + Sync>;
+
+/// What a handler gets: the message, the arguments of the route that
+/// matched it and helpers to reply.
+#[derive(Clone)]
+pub struct Context {
+    pub message: MessageView,
+    /// The words after a command, or the capture groups of a regex route.
+    pub args: Vec<String>,
+    safe_user: Arc<SafeUser>,
+    replies: Arc<Mutex<Vec<MessageRequest>>>,
+}
+
+impl Context {
+    pub fn safe_user(&self) -> &SafeUser {
+        &self.safe_user
+    }
+
+    /// Queues a reply to the sender, sent once the handler returns. Reply ids
+    /// derive from the message id, so handling a message again after a
+    /// crash can not send its replies twice.
+    pub fn reply(&self, payload: &MessagePayload) -> Result<(), Error> {
+        let mut replies = self.replies.lock().unwrap();
+        let index = replies.len().to_string();
+        let message_id = unique_object_id([self.message.message_id.as_str(), "REPLY", &index]);
+        let reply = MessageRequest::new(&self.message.conversation_id, &message_id, payload)?
+            .with_recipient_id(&self.message.user_id);
+        replies.push(reply);
+        Ok(())
+    }
+
+    pub fn reply_text(&self, text: &str) -> Result<(), Error> {
+        self.reply(&MessagePayload::Text(text.to_string()))
+    }
+
+    fn take_replies(&self) -> Vec<MessageRequest> {
+        std::mem::take(&mut *self.replies.lock().unwrap())
+    }
+}
+
+/// Hooks around every message. `before` can drop a message, `after` sees
+/// how its handler went and `failed` why its replies or acknowledgement
+/// could not be sent.
+pub trait Middleware: Send + Sync {
+    fn before(&self, _message: &MessageView) -> bool {
+        true
+    }
+
+    fn after(&self, _message: &MessageView, _result: &Result<(), Error>, _elapsed: Duration) {}
+
+    fn failed(&self, _message: &MessageView, _error: &Error) {}
+}
+
+/// Logs every handled message and its outcome through the `log` facade.
+#[derive(Debug, Default)]
+pub struct Logger;
+
+impl Middleware for Logger {
+    fn after(&self, message: &MessageView, result: &Result<(), Error>, elapsed: Duration) {
+        match result {
+            Ok(()) => log::info!(
+                "{} {} from {} handled in {elapsed:?}",
+                message.category,
+                message.message_id,
+                message.user_id
+            ),
+            Err(e) => log::warn!(
+                "{} {} from {} failed in {elapsed:?}: {e}",
+                message.category,
+                message.message_id,
+                message.user_id
+            ),
+        }
+    }
+
+    fn failed(&self, message: &MessageView, error: &Error) {
+        log::error!(
+            "{} {} from {} not delivered: {error}",
+            message.category,
+            message.message_id,
+            message.user_id
+        );
+    }
+}
+
+/// Drops messages whose id was handled among the last `capacity` ones, Blaze
+/// redelivers messages that were not acknowledged in time. A message whose
+/// replies or acknowledgement failed is forgotten, so its redelivery is
+/// handled again.
+#[derive(Debug)]
+pub struct Dedup {
+    capacity: usize,
+    seen: Mutex<(HashSet<String>, VecDeque<String>)>,
+}
+
+impl Dedup {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            seen: Mutex::new((HashSet::new(), VecDeque::new())),
+        }
+    }
+}
+
+impl Middleware for Dedup {
+    fn before(&self, message: &MessageView) -> bool {
+        !self.seen.lock().unwrap().0.contains(&message.message_id)
+    }
+
+    fn after(&self, message: &MessageView, _result: &Result<(), Error>, _elapsed: Duration) {
+        let mut seen = self.seen.lock().unwrap();
+        let (ids, order) = &mut *seen;
+        if !ids.insert(message.message_id.clone()) {
+            return;
+        }
+        order.push_back(message.message_id.clone());
+        if order.len() > self.capacity
+            && let Some(oldest) = order.pop_front()
+        {
+            ids.remove(&oldest);
+        }
+    }
+
+    fn failed(&self, message: &MessageView, _error: &Error) {
+        let mut seen = self.seen.lock().unwrap();
+        let (ids, order) = &mut *seen;
+        if ids.remove(&message.message_id) {
+            order.retain(|id| *id != message.message_id);
+        }
+    }
+}
+
+/// Drops the messages of a user beyond `max` within `window`.
+#[derive(Debug)]
+pub struct RateLimit {
+    max: usize,
+    window: Duration,
+    recent: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl RateLimit {
+    pub fn new(max: usize, window: Duration) -> Self {
+        Self {
+            max,
+            window,
+            recent: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Middleware for RateLimit {
+    fn before(&self, message: &MessageView) -> bool {
+        let now = Instant::now();
+        let mut recent = self.recent.lock().unwrap();
+        recent.retain(|_, times| {
+            times.retain(|t| now.duration_since(*t) < self.window);
+            !times.is_empty()
+        });
+        let times = recent.entry(message.user_id.clone()).or_default();
+        if times.len() >= self.max {
+            return false;
+        }
+        times.push_back(now);
+        true
+    }
+}
+
+enum Route {
+    Command(String),
+    Regex(Regex),
+    Category(MessageCategory),
+    Any,
+}
+
+impl Route {
+    // The arguments of `message` if it matches.
+    fn matches(&self, message: &MessageView) -> Option<Vec<String>> {
+        let text = || match message.payload() {
+            Ok(MessagePayload::Text(text)) => Some(text),
+            _ => None,
+        };
+        match self {
+            Route::Command(command) => {
+                let text = text()?;
+                let mut words = text.split_whitespace();
+                (words.next()? == command).then(|| words.map(str::to_string).collect())
+            }
+            Route::Regex(regex) => {
+                let text = text()?;
+                let captures = regex.captures(&text)?;
+                Some(
+                    captures
+                        .iter()
+                        .skip(1)
+                        .map(|c| c.map_or_else(String::new, |c| c.as_str().to_string()))
+                        .collect(),
+                )
+            }
+            Route::Category(category) => (message.category == *category).then(Vec::new),
+            Route::Any => Some(Vec::new()),
+        }
+    }
+}
+
+/// Routes incoming messages to async handlers.
+///
+/// The first route matching a message wins, in the order they were added.
+/// ENCRYPTED_* messages are decrypted before routing. Handlers run in their
+/// own task so a panic fails only its message, replies are sent once the
+/// handler returns and messages are acknowledged with
+/// `post_acknowledgements` once their replies are sent.
+pub struct Bot {
+    safe_user: Arc<SafeUser>,
+    routes: Vec<(Route, Handler)>,
+    middlewares: Vec<Box<dyn Middleware>>,
+    auto_ack: bool,
+}
+
+impl Bot {
+    pub fn new(safe_user: SafeUser) -> Self {
+        Self {
+            safe_user: Arc::new(safe_user),
+            routes: Vec::new(),
+            middlewares: Vec::new(),
+            auto_ack: true,
+        }
+    }
+
+    pub fn with_auto_ack(mut self, auto_ack: bool) -> Self {
+        self.auto_ack = auto_ack;
+        self
+    }
+
+    pub fn with_middleware<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.middlewares.push(Box::new(middleware));
+        self
+    }
+
+    /// Text messages whose first word is `command`, e.g. "/start".
+    pub fn command<F, Fut>(self, command: &str, handler: F) -> Self
+    where
+        F: Fn(Context) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        self.route(Route::Command(command.to_string()), handler)
+    }
+
+    /// Text messages matching `regex`.
+    pub fn regex<F, Fut>(self, regex: Regex, handler: F) -> Self
+    where
+        F: Fn(Context) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        self.route(Route::Regex(regex), handler)
+    }
+
+    pub fn category<F, Fut>(self, category: MessageCategory, handler: F) -> Self
+    where
+        F: Fn(Context) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        self.route(Route::Category(category), handler)
+    }
+
+    /// Every message no earlier route matched.
+    pub fn fallback<F, Fut>(self, handler: F) -> Self
+    where
+        F: Fn(Context) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        self.route(Route::Any, handler)
+    }
+
+    pub fn safe_user(&self) -> &SafeUser {
+        &self.safe_user
+    }
+
+    /// Handles the messages of `client` until the client fails. Replies or
+    /// acknowledgements that can't be sent go to the middlewares' `failed`
+    /// and the message is delivered again by Blaze, unreadable frames are
+    /// logged and skipped. The bot acknowledges messages itself, so the
+    /// client's own acknowledgement is turned off.
+    pub async fn run(&self, client: &mut BlazeClient) -> Result<(), Error> {
+        client.set_auto_ack(false);
+        loop {
+            let event = match client.next_event(&self.safe_user).await {
+                Ok(event) => event,
+                Err(e @ Error::Json(_)) => {
+                    log::warn!("blaze frame skipped: {e}");
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let message = match event {
+                BlazeEvent::Message(message) | BlazeEvent::System(message) => message,
+                BlazeEvent::SafeSnapshot { message, .. }
+                | BlazeEvent::Recall { message, .. }
+                | BlazeEvent::Pin { message, .. } => message,
+                BlazeEvent::Receipt(_) => continue,
+            };
+            let client = &mut *client;
+            let send = |replies: Vec<MessageRequest>| async move {
+                client.send_messages(&replies, &self.safe_user).await?;
+                Ok(())
+            };
+            // Already reported to the middlewares.
+            let _ = self.process(message, send).await;
+        }
+    }
+
+    /// Handles one message from any source, e.g. polling, and sends the
+    /// replies over HTTP.
+    pub async fn dispatch(&self, message: MessageView) -> Result<(), Error> {
+        let send = |replies: Vec<MessageRequest>| async move {
+            post_messages(&replies, &self.safe_user).await
+        };
+        self.process(message, send).await
+    }
+
+    // Handles a message, sends its replies with `send` and acknowledges it
+    // once they are sent. Handler failures only reach the middlewares'
+    // `after`, failures to send reach their `failed`.
+    async fn process<F, Fut>(&self, message: MessageView, send: F) -> Result<(), Error>
+    where
+        F: FnOnce(Vec<MessageRequest>) -> Fut,
+        Fut: Future<Output = Result<(), Error>>,
+    {
+        let replies = self.handle_message(message.clone()).await;
+        let result = self.deliver(&message.message_id, replies, send).await;
+        if let Err(e) = &result {
+            for middleware in &self.middlewares {
+                middleware.failed(&message, e);
+            }
+        }
+        result
+    }
+
+    async fn deliver<F, Fut>(
+        &self,
+        message_id: &str,
+        replies: Vec<MessageRequest>,
+        send: F,
+    ) -> Result<(), Error>
+    where
+        F: FnOnce(Vec<MessageRequest>) -> Fut,
+        Fut: Future<Output = Result<(), Error>>,
+    {
+        if !replies.is_empty() {
+            send(replies).await?;
+        }
+        if self.auto_ack {
+            let ack = ReceiptAcknowledgementRequest {
+                message_id: message_id.to_string(),
+                status: "READ".to_string(),
+            };
+            post_acknowledgements(&[ack], &self.safe_user).await?;
+        }
+        Ok(())
+    }
+
+    async fn handle_message(&self, message: MessageView) -> Vec<MessageRequest> {
+        if !self.middlewares.iter().all(|m| m.before(&message)) {
+            return Vec::new();
+        }
+        let started = Instant::now();
+        let (context, result) = match message.decrypt(&self.safe_user) {
+            Ok(message) => match self.find_route(&message) {
+                Some((handler, args)) => {
+                    let context = Context {
+                        message,
+                        args,
+                        safe_user: self.safe_user.clone(),
+                        replies: Arc::default(),
+                    };
+                    let result = run_isolated(handler, context.clone()).await;
+                    (Some(context), result)
+                }
+                None => (None, Ok(())),
+            },
+            Err(e) => (None, Err(e)),
+        };
+        for middleware in &self.middlewares {
+            middleware.after(&message, &result, started.elapsed());
+        }
+        context.map(|c| c.take_replies()).unwrap_or_default()
+    }
+
+    fn find_route(&self, message: &MessageView) -> Option<(Handler, Vec<String>)> {
+        self.routes
+            .iter()
+            .find_map(|(route, handler)| route.matches(message).map(|args| (handler.clone(), args)))
+    }
+
+    fn route<F, Fut>(mut self, route: Route, handler: F) -> Self
+    where
+        F: Fn(Context) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        let handler: Handler = Arc::new(move |context| Box::pin(handler(context)));
+        self.routes.push((route, handler));
+        self
+    }
+}
+
+// Runs a handler in its own task, turning a panic into an error.
+async fn run_isolated(handler: Handler, context: Context) -> Result<(), Error> {
+    match tokio::spawn(handler(context)).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => Err(Error::Server("message handler panicked".to_string())),
+        Err(e) => Err(Error::Server(format!("message handler failed: {e}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::test_user;
+    use base64::{Engine as _, engine::general_purpose::STANDARD};
+
+    fn text_message(id: &str, user_id: &str, text: &str) -> MessageView {
+        MessageView {
+            conversation_id: "conversation-id".to_string(),
+            user_id: user_id.to_string(),
+            message_id: id.to_string(),
+            category: MessageCategory::PlainText,
+            data_base64: STANDARD.encode(text),
+            ..Default::default()
+        }
+    }
+
+    fn test_bot() -> Bot {
+        Bot::new(test_user()).with_auto_ack(false)
+    }
+
+    #[tokio::test]
+    async fn test_bot_routes() {
+        let bot = test_bot()
+            .command(
+                "/echo",
+                |ctx| async move { ctx.reply_text(&ctx.args.join(" ")) },
+            )
+            .regex(Regex::new(r"^pay (\d+)$").unwrap(), |ctx| async move {
+                ctx.reply_text(&format!("paying {}", ctx.args[0]))?;
+                ctx.reply_text("done")
+            })
+            .category(MessageCategory::PlainSticker, |ctx| async move {
+                ctx.reply_text("nice sticker")
+            })
+            .command("/panic", |_| async move { panic!("handler bug") })
+            .fallback(|_| async move { Err(Error::Input("unknown".to_string())) });
+
+        let replies = bot
+            .handle_message(text_message("m1", "u1", "/echo a  b"))
+            .await;
+        assert_eq!(replies.len(), 1);
+        assert_eq!(
+            replies[0].payload().unwrap(),
+            MessagePayload::Text("a b".to_string())
+        );
+        assert_eq!(replies[0].recipient_id.as_deref(), Some("u1"));
+        assert_eq!(
+            replies[0].message_id,
+            unique_object_id(["m1", "REPLY", "0"])
+        );
+
+        let replies = bot.handle_message(text_message("m2", "u1", "pay 42")).await;
+        assert_eq!(replies.len(), 2);
+        assert_eq!(
+            replies[0].payload().unwrap(),
+            MessagePayload::Text("paying 42".to_string())
+        );
+        assert_ne!(replies[0].message_id, replies[1].message_id);
+        // Handling the same message again replies with the same ids.
+        let again = bot.handle_message(text_message("m2", "u1", "pay 42")).await;
+        assert_eq!(again[1].message_id, replies[1].message_id);
+
+        let sticker = MessageView {
+            category: MessageCategory::PlainSticker,
+            data_base64: STANDARD.encode(r#"{"sticker_id":"s"}"#),
+            ..text_message("m3", "u1", "")
+        };
+        assert_eq!(bot.handle_message(sticker).await.len(), 1);
+
+        // Panics and errors fail only their own message.
+        assert!(
+            bot.handle_message(text_message("m4", "u1", "/panic"))
+                .await
+                .is_empty()
+        );
+        assert!(
+            bot.handle_message(text_message("m5", "u1", "/echo2"))
+                .await
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bot_middlewares() {
+        #[derive(Default)]
+        struct Record(Arc<Mutex<Vec<(String, bool)>>>);
+
+        impl Middleware for Record {
+            fn after(&self, message: &MessageView, result: &Result<(), Error>, _: Duration) {
+                let mut results = self.0.lock().unwrap();
+                results.push((message.message_id.clone(), result.is_ok()));
+            }
+        }
+
+        let record = Record::default();
+        let results = record.0.clone();
+        let bot = test_bot()
+            .with_middleware(Dedup::new(2))
+            .with_middleware(RateLimit::new(2, Duration::from_secs(60)))
+            .with_middleware(record)
+            .command("/panic", |_| async move { panic!("handler bug") })
+            .fallback(|_| async move { Ok(()) });
+
+        for (id, user) in [
+            ("m1", "u1"),
+            ("m1", "u1"),
+            ("m2", "u1"),
+            ("m3", "u1"),
+            ("m4", "u2"),
+        ] {
+            bot.handle_message(text_message(id, user, "hi")).await;
+        }
+        bot.handle_message(text_message("m5", "u2", "/panic")).await;
+        let results = results.lock().unwrap().clone();
+        let expected = [("m1", true), ("m2", true), ("m4", true), ("m5", false)];
+        assert_eq!(
+            results,
+            expected.map(|(id, ok)| (id.to_string(), ok)).to_vec()
+        );
+
+        let dedup = Dedup::new(1);
+        let (a, b) = (text_message("a", "u", ""), text_message("b", "u", ""));
+        dedup.after(&a, &Ok(()), Duration::ZERO);
+        assert!(!dedup.before(&a));
+        dedup.failed(&a, &Error::Input("send failed".to_string()));
+        assert!(dedup.before(&a));
+        dedup.after(&a, &Ok(()), Duration::ZERO);
+        dedup.after(&b, &Ok(()), Duration::ZERO);
+        assert!(dedup.before(&a));
+        assert!(!dedup.before(&b));
+    }
+
+    #[tokio::test]
+    async fn test_bot_acknowledges_after_replies() {
+        #[derive(Default)]
+        struct Failures(Arc<Mutex<Vec<String>>>);
+
+        impl Middleware for Failures {
+            fn failed(&self, message: &MessageView, _: &Error) {
+                self.0.lock().unwrap().push(message.message_id.clone());
+            }
+        }
+
+        let failures = Failures::default();
+        let failed = failures.0.clone();
+        let bot = Bot::new(test_user())
+            .with_middleware(failures)
+            .fallback(|ctx| async move { ctx.reply_text("hi") });
+
+        // The acknowledgement would go out over HTTP, a failed send must
+        // return before it.
+        let sent = Mutex::new(Vec::new());
+        let result = bot
+            .process(text_message("m1", "u1", "hi"), |replies| async {
+                sent.lock().unwrap().extend(replies);
+                Err(Error::Input("send failed".to_string()))
+            })
+            .await;
+        assert!(matches!(result, Err(Error::Input(_))));
+        assert_eq!(sent.lock().unwrap().len(), 1);
+        assert_eq!(*failed.lock().unwrap(), ["m1"]);
+    }
+}
@@ -0,0 +1,195 @@
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::Arc;
+
+use crate::{
+    error::Error,
+    message::{MessagePayload, MessageRequest, post_messages},
+    message_queue::{MessageOutcome, MessageQueue},
+    safe::SafeUser,
+    utils::{unique_conversation_id, unique_object_id},
+};
+
+/// The error code of messages to users who blocked the bot.
+pub const ERROR_CODE_FORBIDDEN: i32 = 403;
+
+/// Where each recipient of a broadcast ended up.
+#[derive(Debug, Clone, Default)]
+pub struct BroadcastReport {
+    pub sent: Vec<String>,
+    /// Recipients who blocked the bot, skipped without failing.
+    pub blocked: Vec<String>,
+    pub failed: Vec<(String, Arc<Error>)>,
+}
+
+impl BroadcastReport {
+    pub fn total(&self) -> usize {
+        self.sent.len() + self.blocked.len() + self.failed.len()
+    }
+
+    /// Recipients worth sending the broadcast to again.
+    pub fn failed_recipients(&self) -> Vec<String> {
+        self.failed.iter().map(|(id, _)| id.clone()).collect()
+    }
+
+    fn add(&mut self, recipient_id: String, outcome: MessageOutcome) {
+        match outcome.error {
+            None => self.sent.push(recipient_id),
+            Some(e) if matches!(&*e, Error::Api(e) if e.code == ERROR_CODE_FORBIDDEN) => {
+                self.blocked.push(recipient_id)
+            }
+            Some(e) => self.failed.push((recipient_id, e)),
+        }
+    }
+}
+
+/// The same message sent to many users, each in their conversation with the
+/// bot.
+///
+/// Message ids derive from `broadcast_id` and the recipient, so sending a
+/// broadcast again after a crash or for its failed recipients can not
+/// deliver it twice to anyone.
+#[derive(Debug)]
+pub struct Broadcast {
+    broadcast_id: String,
+    payload: MessagePayload,
+    queue: MessageQueue,
+}
+
+impl Broadcast {
+    pub fn new(broadcast_id: &str, payload: MessagePayload) -> Self {
+        Self {
+            broadcast_id: broadcast_id.to_string(),
+            payload,
+            queue: MessageQueue::default(),
+        }
+    }
+
+    /// The queue sending the messages, to tune batches, retries and the rate
+    /// limit.
+    pub fn with_queue(mut self, queue: MessageQueue) -> Self {
+        self.queue = queue;
+        self
+    }
+
+    pub fn message_id(&self, recipient_id: &str) -> String {
+        unique_object_id([self.broadcast_id.as_str(), "BROADCAST", recipient_id])
+    }
+
+    /// The message for each recipient, skipping duplicates and the bot itself.
+    pub fn messages(
+        &self,
+        recipient_ids: &[String],
+        safe_user: &SafeUser,
+    ) -> Result<Vec<MessageRequest>, Error> {
+        self.payload.validate()?;
+        let mut seen = HashSet::new();
+        recipient_ids
+            .iter()
+            .filter(|id| *id != &safe_user.user_id && seen.insert(id.as_str()))
+            .map(|recipient_id| {
+                let conversation_id = unique_conversation_id(&safe_user.user_id, recipient_id);
+                let message = MessageRequest::new(
+                    &conversation_id,
+                    &self.message_id(recipient_id),
+                    &self.payload,
+                )?;
+                Ok(message.with_recipient_id(recipient_id))
+            })
+            .collect()
+    }
+
+    pub async fn send(
+        &mut self,
+        recipient_ids: &[String],
+        safe_user: &SafeUser,
+    ) -> Result<BroadcastReport, Error> {
+        let messages = self.messages(recipient_ids, safe_user)?;
+        Ok(self
+            .send_with(messages, |batch| async move {
+                post_messages(&batch, safe_user).await
+            })
+            .await)
+    }
+
+    async fn send_with<F, Fut>(&mut self, messages: Vec<MessageRequest>, send: F) -> BroadcastReport
+    where
+        F: FnMut(Vec<MessageRequest>) -> Fut,
+        Fut: Future<Output = Result<(), Error>>,
+    {
+        self.queue.extend(messages);
+        let mut report = BroadcastReport::default();
+        for outcome in self.queue.flush_with(send).await {
+            let recipient_id = outcome.recipient_id.clone().unwrap_or_default();
+            report.add(recipient_id, outcome);
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::test_user;
+    use crate::request::ApiError;
+
+    #[test]
+    fn test_broadcast_messages() {
+        let user = test_user();
+        let broadcast = Broadcast::new("launch", MessagePayload::Text("hello".to_string()));
+        let recipients: Vec<String> = ["user-a", "user-b", "user-a", user.user_id.as_str()]
+            .map(str::to_string)
+            .to_vec();
+        let messages = broadcast.messages(&recipients, &user).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            messages[0].conversation_id,
+            unique_conversation_id(&user.user_id, "user-a")
+        );
+        assert_eq!(messages[1].recipient_id.as_deref(), Some("user-b"));
+
+        // Resuming the broadcast builds the same message ids.
+        let again = Broadcast::new("launch", MessagePayload::Text("hello".to_string()));
+        assert_eq!(
+            again.messages(&recipients, &user).unwrap()[1].message_id,
+            messages[1].message_id
+        );
+        let other = Broadcast::new("other", MessagePayload::Text("hello".to_string()));
+        assert_ne!(other.message_id("user-b"), messages[1].message_id);
+
+        let invalid = Broadcast::new("launch", MessagePayload::Text(String::new()));
+        assert!(invalid.messages(&recipients, &user).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_report() {
+        let user = test_user();
+        let mut broadcast = Broadcast::new("launch", MessagePayload::Text("hello".to_string()))
+            .with_queue(MessageQueue::new().with_batch_size(2).with_max_attempts(1));
+        let recipients: Vec<String> = (0..5).map(|i| format!("user-{i}")).collect();
+        let messages = broadcast.messages(&recipients, &user).unwrap();
+        let report = broadcast
+            .send_with(messages, |batch| async move {
+                let recipient =
+                    |id: &str| batch.iter().any(|m| m.recipient_id.as_deref() == Some(id));
+                let (status, code) = if recipient("user-1") {
+                    (403, ERROR_CODE_FORBIDDEN)
+                } else if recipient("user-4") {
+                    (500, 500)
+                } else {
+                    return Ok(());
+                };
+                Err(Error::Api(ApiError {
+                    status,
+                    code,
+                    description: String::new(),
+                }))
+            })
+            .await;
+
+        assert_eq!(report.total(), 5);
+        assert_eq!(report.sent, ["user-0", "user-2", "user-3"]);
+        assert_eq!(report.blocked, ["user-1"]);
+        assert_eq!(report.failed_recipients(), ["user-4"]);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::utils::unique_object_id;
+
+pub const BITCOIN_CHAIN_ID: &str = "c6d0c728-2624-429b-8e0d-d9d19b6592fa";
+pub const ETHEREUM_CHAIN_ID: &str = "43d61dcd-e413-450d-80b8-101d5e903357";
+pub const BSC_CHAIN_ID: &str = "1949e683-6a08-49e2-b087-d6b72398588f";
+pub const POLYGON_CHAIN_ID: &str = "b7938396-3f94-4e0a-9179-d3440718156f";
+pub const TRON_CHAIN_ID: &str = "25dabac5-056a-48ff-b9f9-f67395dc407c";
+pub const SOLANA_CHAIN_ID: &str = "64692c23-8971-4cf4-84a7-4dd1271dd887";
+pub const LITECOIN_CHAIN_ID: &str = "76c802a2-7c88-447f-a93e-c29c9e5dd9c8";
+pub const DOGECOIN_CHAIN_ID: &str = "6770a1e5-6086-44d5-b60f-545f9d9e8ffd";
+pub const EOS_CHAIN_ID: &str = "6cfe566e-4aad-470b-8c9a-2fd35b49c68d";
+pub const RIPPLE_CHAIN_ID: &str = "23dfb5a5-5d7b-48b6-905f-3970e3176e27";
+pub const TON_CHAIN_ID: &str = "ef660437-d915-4e27-ad3f-632bfb6ba0ee";
+
+pub const XIN_ASSET_ID: &str = "c94ac88f-4671-3976-b60a-09064f1811e8";
+pub const USDT_ERC20_ASSET_ID: &str = "4d8c508b-91c5-375b-92b0-ee702ed2dac5";
+pub const USDT_TRC20_ASSET_ID: &str = "b91e18ff-a9ae-3dc7-8679-e935d9a4b34b";
+pub const USDC_ERC20_ASSET_ID: &str = "9b180ab6-6abe-3dc0-a13f-04169eb34bfa";
+
+/// The asset key of the native asset on EVM chains.
+pub const EVM_NATIVE_ASSET_KEY: &str = "0x0000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chain {
+    pub name: &'static str,
+    pub symbol: &'static str,
+    pub chain_id: &'static str,
+    /// The asset id of the native asset, which pays the withdrawal fees of
+    /// every asset on the chain.
+    pub asset_id: &'static str,
+}
+
+pub const KNOWN_CHAINS: &[Chain] = &[
+    chain("Bitcoin", "BTC", BITCOIN_CHAIN_ID),
+    chain("Ethereum", "ETH", ETHEREUM_CHAIN_ID),
+    chain("BNB Smart Chain", "BNB", BSC_CHAIN_ID),
+    chain("Polygon", "POL", POLYGON_CHAIN_ID),
+    chain("Tron", "TRX", TRON_CHAIN_ID),
+    chain("Solana", "SOL", SOLANA_CHAIN_ID),
+    chain("Litecoin", "LTC", LITECOIN_CHAIN_ID),
+    chain("Dogecoin", "DOGE", DOGECOIN_CHAIN_ID),
+    chain("EOS", "EOS", EOS_CHAIN_ID),
+    chain("Ripple", "XRP", RIPPLE_CHAIN_ID),
+    chain("TON", "TON", TON_CHAIN_ID),
+];
+
+// The native asset of every chain has the chain id as its asset id.
+const fn chain(name: &'static str, symbol: &'static str, chain_id: &'static str) -> Chain {
+    Chain {
+        name,
+        symbol,
+        chain_id,
+        asset_id: chain_id,
+    }
+}
+
+pub fn find_chain(chain_id: &str) -> Option<&'static Chain> {
+    KNOWN_CHAINS.iter().find(|c| c.chain_id == chain_id)
+}
+
+/// The Mixin asset id of the token `asset_key` on `chain_id`, e.g. an ERC-20
+/// contract address, computed locally the way Mixin assigns it. EVM keys are
+/// lowercased first, and the native asset of a chain has the chain id itself.
+pub fn asset_id_from_key(chain_id: &str, asset_key: &str) -> String {
+    if asset_key == chain_id || asset_key == EVM_NATIVE_ASSET_KEY {
+        return chain_id.to_string();
+    }
+    if asset_key.starts_with("0x") {
+        return unique_object_id([chain_id, &asset_key.to_lowercase()]);
+    }
+    unique_object_id([chain_id, asset_key])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_asset_id_from_key() {
+        assert_eq!(
+            asset_id_from_key(
+                ETHEREUM_CHAIN_ID,
+                "0xdAC17F958D2ee523a2206206994597C13D831ec7"
+            ),
+            USDT_ERC20_ASSET_ID
+        );
+        assert_eq!(
+            asset_id_from_key(
+                ETHEREUM_CHAIN_ID,
+                "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"
+            ),
+            USDC_ERC20_ASSET_ID
+        );
+        assert_eq!(
+            asset_id_from_key(TRON_CHAIN_ID, "TR7NHqjeKQxGTCi8q8ZY4pL8otSzgjLj6t"),
+            USDT_TRC20_ASSET_ID
+        );
+        assert_eq!(
+            asset_id_from_key(ETHEREUM_CHAIN_ID, EVM_NATIVE_ASSET_KEY),
+            ETHEREUM_CHAIN_ID
+        );
+        assert_eq!(
+            asset_id_from_key(BITCOIN_CHAIN_ID, BITCOIN_CHAIN_ID),
+            BITCOIN_CHAIN_ID
+        );
+    }
+
+    #[test]
+    fn test_find_chain() {
+        let chain = find_chain(ETHEREUM_CHAIN_ID).unwrap();
+        assert_eq!(chain.symbol, "ETH");
+        assert_eq!(chain.asset_id, ETHEREUM_CHAIN_ID);
+        assert!(find_chain(XIN_ASSET_ID).is_none());
+    }
+}
@@ -0,0 +1,323 @@
+use std::cmp::Reverse;
+
+use crate::amount::Amount;
+use crate::error::Error;
+use crate::models::Output;
+use crate::output::OUTPUT_STATE_UNSPENT;
+use crate::safe_transaction::SLICE_COUNT_LIMIT;
+
+/// Upper bound of branches explored by `BranchAndBound` before falling back.
+const BRANCH_AND_BOUND_TRIES: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SelectionStrategy {
+    /// Spends the largest outputs first, using as few inputs as possible.
+    #[default]
+    LargestFirst,
+    /// Spends the smallest outputs first, consolidating dust along the way.
+    SmallestFirst,
+    /// Picks the outputs leaving the smallest change.
+    ClosestMatch,
+    /// Searches for a set of outputs matching the amount exactly, leaving no
+    /// change, and falls back to `ClosestMatch` when there is none.
+    BranchAndBound,
+}
+
+#[derive(Debug, Clone)]
+pub struct Selection {
+    pub outputs: Vec<Output>,
+    pub total: Amount,
+    pub change: Amount,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CoinSelector {
+    pub strategy: SelectionStrategy,
+    pub max_inputs: usize,
+}
+
+impl Default for CoinSelector {
+    fn default() -> Self {
+        Self::new(SelectionStrategy::default())
+    }
+}
+
+impl CoinSelector {
+    pub fn new(strategy: SelectionStrategy) -> Self {
+        Self {
+            strategy,
+            max_inputs: SLICE_COUNT_LIMIT,
+        }
+    }
+
+    pub fn with_max_inputs(mut self, max_inputs: usize) -> Self {
+        self.max_inputs = max_inputs.clamp(1, SLICE_COUNT_LIMIT);
+        self
+    }
+
+    /// Selects unspent outputs covering `amount`.
+    ///
+    /// Fails with `Error::Input` when the outputs are not enough in total, and
+    /// with `Error::ConsolidationRequired` when they are but more than
+    /// `max_inputs` of them would be needed, so the caller should consolidate
+    /// before retrying.
+    pub fn select(&self, outputs: &[Output], amount: Amount) -> Result<Selection, Error> {
+        if !amount.is_positive() {
+            return Err(Error::Input(format!("invalid amount: {amount}")));
+        }
+        let target = amount.units() as u128;
+        let candidates = candidates(outputs);
+        let values: Vec<u128> = candidates.iter().map(|(_, v)| *v).collect();
+
+        let available: u128 = values.iter().sum();
+        if available < target {
+            return Err(Error::Input(format!(
+                "insufficient outputs {} < {amount}",
+                units_amount(available)
+            )));
+        }
+        let required = inputs_required(&values, target);
+        if required > self.max_inputs {
+            return Err(Error::ConsolidationRequired(required, self.max_inputs));
+        }
+
+        let indexes = match self.strategy {
+            SelectionStrategy::LargestFirst => largest_first(&values, target),
+            SelectionStrategy::SmallestFirst => smallest_first(&values, target, self.max_inputs),
+            SelectionStrategy::ClosestMatch => closest_match(&values, target),
+            SelectionStrategy::BranchAndBound => branch_and_bound(&values, target, self.max_inputs)
+                .unwrap_or_else(|| closest_match(&values, target)),
+        };
+
+        let total: u128 = indexes.iter().map(|i| values[*i]).sum();
+        Ok(Selection {
+            outputs: indexes.iter().map(|i| candidates[*i].0.clone()).collect(),
+            total: units_amount(total),
+            change: units_amount(total - target),
+        })
+    }
+
+    /// Whether paying `amount` from `outputs` needs more than `max_inputs` inputs.
+    pub fn needs_consolidation(&self, outputs: &[Output], amount: Amount) -> Result<bool, Error> {
+        match self.select(outputs, amount) {
+            Ok(_) => Ok(false),
+            Err(Error::ConsolidationRequired(..)) => Ok(true),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Unspent outputs with their amounts in units, sorted by amount descending.
+fn candidates(outputs: &[Output]) -> Vec<(&Output, u128)> {
+    let mut candidates = Vec::with_capacity(outputs.len());
+    for output in outputs {
+        if output
+            .state
+            .as_deref()
+            .is_some_and(|s| s != OUTPUT_STATE_UNSPENT)
+        {
+            continue;
+        }
+        let value = output.amount.unwrap_or_default();
+        if value.is_positive() {
+            candidates.push((output, value.units() as u128));
+        }
+    }
+    candidates.sort_by_key(|(_, value)| Reverse(*value));
+    candidates
+}
+
+fn units_amount(units: u128) -> Amount {
+    Amount::from_units(i128::try_from(units).unwrap_or(i128::MAX))
+}
+
+/// The least number of inputs able to cover `target`, with `values` sorted descending.
+fn inputs_required(values: &[u128], target: u128) -> usize {
+    let mut sum = 0;
+    for (i, value) in values.iter().enumerate() {
+        sum += value;
+        if sum >= target {
+            return i + 1;
+        }
+    }
+    values.len()
+}
+
+fn largest_first(values: &[u128], target: u128) -> Vec<usize> {
+    (0..inputs_required(values, target)).collect()
+}
+
+/// Takes the smallest outputs in ascending order, sliding the window towards
+/// larger ones whenever `max_inputs` would be exceeded.
+fn smallest_first(values: &[u128], target: u128, max_inputs: usize) -> Vec<usize> {
+    let mut window = Vec::new();
+    let mut sum = 0;
+    for i in (0..values.len()).rev() {
+        window.push(i);
+        sum += values[i];
+        if window.len() > max_inputs {
+            sum -= values[window.remove(0)];
+        }
+        if sum >= target {
+            break;
+        }
+    }
+    window
+}
+
+/// Adds the smallest output covering what is left when there is one, or the
+/// largest output otherwise.
+fn closest_match(values: &[u128], target: u128) -> Vec<usize> {
+    let mut selected = Vec::new();
+    let mut remaining = target;
+    let mut next = 0;
+    while remaining > 0 && next < values.len() {
+        let covering = values[next..].partition_point(|v| *v >= remaining);
+        if covering > 0 {
+            selected.push(next + covering - 1);
+            break;
+        }
+        selected.push(next);
+        remaining -= values[next];
+        next += 1;
+    }
+    selected
+}
+
+/// Depth first search for outputs summing exactly to `target`.
+fn branch_and_bound(values: &[u128], target: u128, max_inputs: usize) -> Option<Vec<usize>> {
+    let start = values.partition_point(|v| *v > target);
+    let mut remaining = vec![0u128; values.len() + 1];
+    for i in (start..values.len()).rev() {
+        remaining[i] = remaining[i + 1] + values[i];
+    }
+
+    let mut search = Search {
+        values,
+        remaining: &remaining,
+        target,
+        max_inputs,
+        tries: BRANCH_AND_BOUND_TRIES,
+        selected: Vec::new(),
+    };
+    search.run(start, 0).then_some(search.selected)
+}
+
+struct Search<'a> {
+    values: &'a [u128],
+    remaining: &'a [u128],
+    target: u128,
+    max_inputs: usize,
+    tries: usize,
+    selected: Vec<usize>,
+}
+
+impl Search<'_> {
+    fn run(&mut self, index: usize, sum: u128) -> bool {
+        if sum == self.target {
+            return true;
+        }
+        if index == self.values.len()
+            || self.selected.len() == self.max_inputs
+            || sum + self.remaining[index] < self.target
+            || self.tries == 0
+        {
+            return false;
+        }
+        self.tries -= 1;
+
+        let value = self.values[index];
+        if sum + value <= self.target {
+            self.selected.push(index);
+            if self.run(index + 1, sum + value) {
+                return true;
+            }
+            self.selected.pop();
+        }
+        // Excluding an output also excludes its equals, they lead to the same branches.
+        let next = index + self.values[index..].partition_point(|v| *v == value);
+        self.run(next, sum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::outputs;
+
+    fn amount(amount: &str) -> Amount {
+        amount.parse().unwrap()
+    }
+
+    fn select(strategy: SelectionStrategy, outputs: &[Output], amount: &str) -> Selection {
+        CoinSelector::new(strategy)
+            .select(outputs, amount.parse().unwrap())
+            .expect("select")
+    }
+
+    #[test]
+    fn test_selection_strategies() {
+        let outputs = outputs(&["0.1", "5", "0.2", "1.5", "0.3", "2"]);
+
+        let selection = select(SelectionStrategy::LargestFirst, &outputs, "0.5");
+        assert_eq!(selection.outputs.len(), 1);
+        assert_eq!(selection.change.to_string(), "4.5");
+
+        let selection = select(SelectionStrategy::SmallestFirst, &outputs, "0.5");
+        assert_eq!(selection.outputs.len(), 3);
+        assert_eq!(selection.total.to_string(), "0.6");
+
+        let selection = select(SelectionStrategy::ClosestMatch, &outputs, "1.7");
+        assert_eq!(selection.total.to_string(), "2");
+
+        let selection = select(SelectionStrategy::BranchAndBound, &outputs, "1.7");
+        assert_eq!(selection.total.to_string(), "1.7");
+        assert_eq!(selection.change.to_string(), "0");
+        let mut ids: Vec<_> = selection.outputs.iter().map(|o| &o.output_id).collect();
+        ids.sort();
+        assert_eq!(ids, ["output-2", "output-3"]);
+
+        let selection = select(SelectionStrategy::BranchAndBound, &outputs, "8.95");
+        assert_eq!(selection.total.to_string(), "9");
+    }
+
+    #[test]
+    fn test_selection_skips_spent_outputs() {
+        let mut outputs = outputs(&["1", "2"]);
+        outputs[1].state = Some("spent".to_string());
+        let selector = CoinSelector::default();
+        assert!(selector.select(&outputs, amount("1.5")).is_err());
+        assert!(
+            selector
+                .select(&outputs, amount("1"))
+                .unwrap()
+                .change
+                .is_zero()
+        );
+    }
+
+    #[test]
+    fn test_selection_requires_consolidation() {
+        let outputs = outputs(&["1"; 10]);
+        let selector = CoinSelector::new(SelectionStrategy::SmallestFirst).with_max_inputs(4);
+
+        assert_eq!(
+            selector
+                .select(&outputs, amount("4"))
+                .unwrap()
+                .outputs
+                .len(),
+            4
+        );
+        assert!(matches!(
+            selector.select(&outputs, amount("6")),
+            Err(Error::ConsolidationRequired(6, 4))
+        ));
+        assert!(selector.needs_consolidation(&outputs, amount("6")).unwrap());
+        assert!(!selector.needs_consolidation(&outputs, amount("3")).unwrap());
+        assert!(matches!(
+            selector.select(&outputs, amount("11")),
+            Err(Error::Input(_))
+        ));
+    }
+}
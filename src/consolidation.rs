@@ -0,0 +1,238 @@
+use crate::{
+    amount::Amount,
+    error::Error,
+    models::Output,
+    output::{OUTPUT_STATE_UNSPENT, list_all_unspent_outputs},
+    safe::SafeUser,
+    safe_transaction::{SLICE_COUNT_LIMIT, TransactionRecipient, send_transaction_with_outputs},
+    transaction::TransactionView,
+    utils::{hash_members, unique_object_id},
+};
+
+#[derive(Debug, Clone)]
+pub struct ConsolidationOptions {
+    /// Inputs merged by each transaction, at most `SLICE_COUNT_LIMIT`.
+    pub batch_size: usize,
+    /// Nothing is consolidated unless there are at least this many eligible outputs.
+    pub min_outputs: usize,
+    /// Only outputs below this amount are merged, all of them when `None`.
+    pub max_output_amount: Option<Amount>,
+    /// Stops after this many transactions, unlimited when `None`.
+    pub max_batches: Option<usize>,
+}
+
+impl Default for ConsolidationOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: SLICE_COUNT_LIMIT,
+            min_outputs: SLICE_COUNT_LIMIT,
+            max_output_amount: None,
+            max_batches: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConsolidationBatch {
+    pub trace_id: String,
+    pub outputs: Vec<Output>,
+    pub amount: Amount,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConsolidationPlan {
+    pub asset_id: String,
+    /// Unspent outputs of the asset, eligible or not.
+    pub outputs_count: usize,
+    pub batches: Vec<ConsolidationBatch>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConsolidationProgress {
+    /// 1-based index of the batch just sent.
+    pub batch: usize,
+    pub batches: usize,
+    pub trace_id: String,
+    pub inputs: usize,
+    pub amount: Amount,
+}
+
+/// Splits the eligible outputs, smallest first, into batches merged back to
+/// the user. Each trace id is derived from the user, the asset and the output
+/// ids of its batch, so planning the same outputs again yields the same
+/// transactions and resending a batch is idempotent.
+pub fn plan_consolidation(
+    asset_id: &str,
+    outputs: &[Output],
+    options: &ConsolidationOptions,
+    user_id: &str,
+) -> Result<ConsolidationPlan, Error> {
+    let batch_size = options.batch_size.min(SLICE_COUNT_LIMIT);
+    if batch_size < 2 {
+        return Err(Error::Input(format!(
+            "invalid consolidation batch size: {}",
+            options.batch_size
+        )));
+    }
+
+    let mut eligible = Vec::with_capacity(outputs.len());
+    for output in outputs {
+        if output
+            .state
+            .as_deref()
+            .is_some_and(|s| s != OUTPUT_STATE_UNSPENT)
+        {
+            continue;
+        }
+        let value = output.amount.unwrap_or_default();
+        if options.max_output_amount.is_none_or(|max| value < max) {
+            eligible.push((output, value));
+        }
+    }
+
+    let mut plan = ConsolidationPlan {
+        asset_id: asset_id.to_string(),
+        outputs_count: outputs.len(),
+        batches: Vec::new(),
+    };
+    if eligible.len() < options.min_outputs.max(2) {
+        return Ok(plan);
+    }
+    eligible.sort_by_key(|(_, value)| *value);
+
+    for chunk in eligible.chunks(batch_size) {
+        if chunk.len() < 2
+            || options
+                .max_batches
+                .is_some_and(|max| plan.batches.len() >= max)
+        {
+            break;
+        }
+        let mut ids = vec![
+            user_id.to_string(),
+            asset_id.to_string(),
+            "CONSOLIDATION".to_string(),
+        ];
+        ids.extend(chunk.iter().map(|(o, _)| o.output_id.clone()));
+        plan.batches.push(ConsolidationBatch {
+            trace_id: unique_object_id(ids),
+            outputs: chunk.iter().map(|(o, _)| (*o).clone()).collect(),
+            amount: Amount::checked_sum(chunk.iter().map(|(_, v)| *v))?,
+        });
+    }
+    Ok(plan)
+}
+
+/// Lists the unspent outputs of `asset_id` owned by the user alone and plans
+/// their consolidation without sending anything.
+pub async fn preview_consolidation(
+    asset_id: &str,
+    options: &ConsolidationOptions,
+    safe_user: &SafeUser,
+) -> Result<ConsolidationPlan, Error> {
+    let members_hash = hash_members([&safe_user.user_id]);
+    let outputs = list_all_unspent_outputs(&members_hash, 1, Some(asset_id), safe_user).await?;
+    plan_consolidation(asset_id, &outputs, options, &safe_user.user_id)
+}
+
+/// Sends every batch of the plan to the user, reporting each one to `progress`.
+///
+/// The merged outputs appear as new unspent outputs, so a run can leave enough
+/// of them to consolidate again when the user held more than
+/// `batch_size * batch_size` outputs.
+pub async fn consolidate_outputs<F>(
+    asset_id: &str,
+    options: &ConsolidationOptions,
+    mut progress: F,
+    safe_user: &SafeUser,
+) -> Result<Vec<TransactionView>, Error>
+where
+    F: FnMut(&ConsolidationProgress),
+{
+    let plan = preview_consolidation(asset_id, options, safe_user).await?;
+    let batches = plan.batches.len();
+
+    let mut views = Vec::with_capacity(batches);
+    for (i, batch) in plan.batches.into_iter().enumerate() {
+        let recipient = TransactionRecipient {
+            members: vec![safe_user.user_id.clone()],
+            threshold: 1,
+            amount: batch.amount,
+        };
+        let view = send_transaction_with_outputs(
+            &batch.outputs,
+            &[recipient],
+            "",
+            &batch.trace_id,
+            safe_user,
+        )
+        .await?;
+        views.push(view);
+        progress(&ConsolidationProgress {
+            batch: i + 1,
+            batches,
+            trace_id: batch.trace_id,
+            inputs: batch.outputs.len(),
+            amount: batch.amount,
+        });
+    }
+    Ok(views)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::outputs;
+
+    const USER_ID: &str = "67a87828-18f5-46a1-b6cc-c72a97a77c43";
+    const ASSET_ID: &str = "965e5c6e-434c-3fa9-b780-c50f43cd955c";
+
+    fn options(batch_size: usize, min_outputs: usize) -> ConsolidationOptions {
+        ConsolidationOptions {
+            batch_size,
+            min_outputs,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_plan_consolidation_batches() {
+        let outputs = outputs(&["0.1"; 7]);
+        let plan = plan_consolidation(ASSET_ID, &outputs, &options(3, 5), USER_ID).unwrap();
+        assert_eq!(plan.outputs_count, 7);
+        assert_eq!(plan.batches.len(), 2);
+        assert_eq!(plan.batches[0].outputs.len(), 3);
+        assert_eq!(plan.batches[0].amount.to_string(), "0.3");
+
+        let again = plan_consolidation(ASSET_ID, &outputs, &options(3, 5), USER_ID).unwrap();
+        assert_eq!(again.batches[0].trace_id, plan.batches[0].trace_id);
+        assert_ne!(plan.batches[0].trace_id, plan.batches[1].trace_id);
+
+        let plan = plan_consolidation(ASSET_ID, &outputs, &options(3, 8), USER_ID).unwrap();
+        assert!(plan.batches.is_empty());
+        assert!(plan_consolidation(ASSET_ID, &outputs, &options(1, 2), USER_ID).is_err());
+    }
+
+    #[test]
+    fn test_plan_consolidation_thresholds() {
+        let mut outputs = outputs(&["0.1"; 4]);
+        outputs[0].amount = Some("10".parse().unwrap());
+        outputs[1].state = Some("spent".to_string());
+        let options = ConsolidationOptions {
+            batch_size: 10,
+            min_outputs: 2,
+            max_output_amount: Some("1".parse().unwrap()),
+            max_batches: Some(1),
+        };
+
+        let plan = plan_consolidation(ASSET_ID, &outputs, &options, USER_ID).unwrap();
+        assert_eq!(plan.batches.len(), 1);
+        let ids: Vec<_> = plan.batches[0]
+            .outputs
+            .iter()
+            .map(|o| &o.output_id)
+            .collect();
+        assert_eq!(ids, ["output-2", "output-3"]);
+        assert_eq!(plan.batches[0].amount.to_string(), "0.2");
+    }
+}
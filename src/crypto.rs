@@ -0,0 +1,358 @@
+use std::fmt;
+use std::str::FromStr;
+
+use curve25519_dalek::{
+    edwards::{CompressedEdwardsY, EdwardsPoint},
+    scalar::Scalar,
+};
+use rand::RngCore;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha512};
+use sha3::Sha3_256;
+
+use crate::error::Error;
+
+pub const MAIN_NETWORK_ID: &str = "XIN";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, PartialOrd, Ord)]
+pub struct Key(pub [u8; 32]);
+
+impl Key {
+    pub fn from_string(s: &str) -> Result<Self, Error> {
+        Self::from_slice(&hex::decode(s)?)
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, Error> {
+        let key: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| Error::Input(format!("invalid key length: {}", bytes.len())))?;
+        Ok(Self(key))
+    }
+
+    /// Reduces a 64 bytes seed into a private scalar, as `crypto.NewKeyFromSeed` does.
+    pub fn new_from_seed(seed: &[u8; 64]) -> Self {
+        Self(Scalar::from_bytes_mod_order_wide(seed).to_bytes())
+    }
+
+    pub fn random() -> Self {
+        let mut seed = [0u8; 64];
+        rand::rngs::OsRng.fill_bytes(&mut seed);
+        Self::new_from_seed(&seed)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    pub fn has_value(&self) -> bool {
+        self.0 != [0u8; 32]
+    }
+
+    pub fn public(&self) -> Result<Key, Error> {
+        let scalar = self.scalar()?;
+        Ok(Key(EdwardsPoint::mul_base(&scalar).compress().to_bytes()))
+    }
+
+    pub fn scalar(&self) -> Result<Scalar, Error> {
+        Option::from(Scalar::from_canonical_bytes(self.0))
+            .ok_or_else(|| Error::Input("invalid private key scalar".to_string()))
+    }
+
+    pub fn point(&self) -> Result<EdwardsPoint, Error> {
+        CompressedEdwardsY(self.0)
+            .decompress()
+            .ok_or_else(|| Error::Input("invalid public key point".to_string()))
+    }
+
+    /// Ed25519 signature with the key used directly as the private scalar.
+    pub fn sign(&self, message: &[u8]) -> Result<[u8; 64], Error> {
+        let x = self.scalar()?;
+        let digest = Sha512::digest(self.0);
+
+        let mut hasher = Sha512::new();
+        hasher.update(&digest[32..]);
+        hasher.update(message);
+        let r = Scalar::from_bytes_mod_order_wide(&hasher.finalize().into());
+        let big_r = EdwardsPoint::mul_base(&r).compress().to_bytes();
+
+        let public = self.public()?;
+        let mut hasher = Sha512::new();
+        hasher.update(big_r);
+        hasher.update(public.0);
+        hasher.update(message);
+        let k = Scalar::from_bytes_mod_order_wide(&hasher.finalize().into());
+        let s = k * x + r;
+
+        let mut signature = [0u8; 64];
+        signature[..32].copy_from_slice(&big_r);
+        signature[32..].copy_from_slice(&s.to_bytes());
+        Ok(signature)
+    }
+
+    pub fn verify(&self, message: &[u8], signature: &[u8; 64]) -> bool {
+        let Ok(public) = ed25519_dalek::VerifyingKey::from_bytes(&self.0) else {
+            return false;
+        };
+        let signature = ed25519_dalek::Signature::from_bytes(signature);
+        public.verify_strict(message, &signature).is_ok()
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl FromStr for Key {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_string(s)
+    }
+}
+
+impl Serialize for Key {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Key::from_string(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+pub fn key_mult_pub_priv(public: &Key, private: &Key) -> Result<Key, Error> {
+    let point = public.point()? * private.scalar()?;
+    Ok(Key(point.compress().to_bytes()))
+}
+
+pub fn key_add_pub(a: &Key, b: &Key) -> Result<Key, Error> {
+    let point = a.point()? + b.point()?;
+    Ok(Key(point.compress().to_bytes()))
+}
+
+pub fn hash_scalar(key: &Key, output_index: u64) -> Scalar {
+    let mut data = key.0.to_vec();
+    data.extend_from_slice(&put_uvarint(output_index));
+
+    let mut src = [0u8; 64];
+    let hash = blake3::hash(&data);
+    src[..32].copy_from_slice(hash.as_bytes());
+    src[32..].copy_from_slice(blake3::hash(hash.as_bytes()).as_bytes());
+    let scalar = Scalar::from_bytes_mod_order_wide(&src);
+
+    let hash = blake3::hash(&scalar.to_bytes());
+    src[..32].copy_from_slice(hash.as_bytes());
+    src[32..].copy_from_slice(blake3::hash(hash.as_bytes()).as_bytes());
+    Scalar::from_bytes_mod_order_wide(&src)
+}
+
+/// P = H(r*A, index)*G + B, the one-time key a sender derives for the receiver (A, B).
+pub fn derive_ghost_public_key(
+    r: &Key,
+    public_view: &Key,
+    public_spend: &Key,
+    output_index: u64,
+) -> Result<Key, Error> {
+    let x = hash_scalar(&key_mult_pub_priv(public_view, r)?, output_index);
+    let point = public_spend.point()? + EdwardsPoint::mul_base(&x);
+    Ok(Key(point.compress().to_bytes()))
+}
+
+/// x = H(a*R, index) + b, the private key of a ghost key owned by (a, b).
+pub fn derive_ghost_private_key(
+    mask: &Key,
+    private_view: &Key,
+    private_spend: &Key,
+    output_index: u64,
+) -> Result<Key, Error> {
+    let x = hash_scalar(&key_mult_pub_priv(mask, private_view)?, output_index);
+    Ok(Key((x + private_spend.scalar()?).to_bytes()))
+}
+
+/// Recovers B = P - H(a*R, index)*G, which equals the receiver public spend key
+/// when the output belongs to the view key holder.
+pub fn view_ghost_output_key(
+    ghost: &Key,
+    private_view: &Key,
+    mask: &Key,
+    output_index: u64,
+) -> Result<Key, Error> {
+    let x = hash_scalar(&key_mult_pub_priv(mask, private_view)?, output_index);
+    let point = ghost.point()? - EdwardsPoint::mul_base(&x);
+    Ok(Key(point.compress().to_bytes()))
+}
+
+/// Derives the output mask R = r*G and one ghost key per receiver.
+pub fn derive_ghost_keys(
+    r: &Key,
+    receivers: &[Address],
+    output_index: u64,
+) -> Result<(Key, Vec<Key>), Error> {
+    let mask = r.public()?;
+    let keys = receivers
+        .iter()
+        .map(|a| derive_ghost_public_key(r, &a.public_view_key, &a.public_spend_key, output_index))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((mask, keys))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Address {
+    pub public_spend_key: Key,
+    pub public_view_key: Key,
+}
+
+impl Address {
+    pub fn new(public_spend_key: Key, public_view_key: Key) -> Self {
+        Self {
+            public_spend_key,
+            public_view_key,
+        }
+    }
+
+    pub fn from_string(s: &str) -> Result<Self, Error> {
+        let encoded = s
+            .strip_prefix(MAIN_NETWORK_ID)
+            .ok_or_else(|| Error::Input(format!("invalid address network: {s}")))?;
+        let data = bs58::decode(encoded)
+            .into_vec()
+            .map_err(|e| Error::Input(format!("invalid address base58: {e}")))?;
+        if data.len() != 68 {
+            return Err(Error::Input(format!(
+                "invalid address length: {}",
+                data.len()
+            )));
+        }
+        let address = Self::from_public_bytes(&data[..64])?;
+        if address.checksum() != data[64..] {
+            return Err(Error::Input("invalid address checksum".to_string()));
+        }
+        Ok(address)
+    }
+
+    /// Parses the 64 bytes public spend key followed by public view key.
+    pub fn from_public_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() != 64 {
+            return Err(Error::Input(format!(
+                "invalid address public length: {}",
+                bytes.len()
+            )));
+        }
+        Ok(Self::new(
+            Key::from_slice(&bytes[..32])?,
+            Key::from_slice(&bytes[32..])?,
+        ))
+    }
+
+    /// The 64 bytes public spend key followed by public view key.
+    pub fn public_bytes(&self) -> [u8; 64] {
+        let mut bytes = [0u8; 64];
+        bytes[..32].copy_from_slice(&self.public_spend_key.0);
+        bytes[32..].copy_from_slice(&self.public_view_key.0);
+        bytes
+    }
+
+    fn checksum(&self) -> [u8; 4] {
+        let mut data = MAIN_NETWORK_ID.as_bytes().to_vec();
+        data.extend_from_slice(&self.public_bytes());
+        let hash = sha3_256(&data);
+        [hash[0], hash[1], hash[2], hash[3]]
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut data = self.public_bytes().to_vec();
+        data.extend_from_slice(&self.checksum());
+        write!(f, "{MAIN_NETWORK_ID}{}", bs58::encode(data).into_string())
+    }
+}
+
+impl FromStr for Address {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_string(s)
+    }
+}
+
+pub fn sha3_256(data: &[u8]) -> [u8; 32] {
+    Sha3_256::digest(data).into()
+}
+
+fn put_uvarint(mut value: u64) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(10);
+    while value >= 0x80 {
+        bytes.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_key(byte: u8) -> Key {
+        Key::new_from_seed(&[byte; 64])
+    }
+
+    #[test]
+    fn test_key_hex_roundtrip() {
+        let key = test_key(1);
+        let parsed = Key::from_string(&key.to_string()).expect("key");
+        assert_eq!(parsed, key);
+        assert!(Key::from_string("00").is_err());
+
+        let json = serde_json::to_string(&key).unwrap();
+        assert_eq!(json, format!("\"{key}\""));
+        assert_eq!(serde_json::from_str::<Key>(&json).unwrap(), key);
+    }
+
+    #[test]
+    fn test_ghost_key_derivation() {
+        let (a, b) = (test_key(1), test_key(2));
+        let address = Address::new(b.public().unwrap(), a.public().unwrap());
+        let r = test_key(3);
+
+        let (mask, keys) = derive_ghost_keys(&r, &[address], 7).expect("ghost");
+        let ghost = keys[0];
+        let private = derive_ghost_private_key(&mask, &a, &b, 7).expect("private");
+        assert_eq!(private.public().unwrap(), ghost);
+        assert_eq!(
+            view_ghost_output_key(&ghost, &a, &mask, 7).unwrap(),
+            address.public_spend_key
+        );
+        assert_ne!(
+            view_ghost_output_key(&ghost, &a, &mask, 8).unwrap(),
+            address.public_spend_key
+        );
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let key = test_key(4);
+        let signature = key.sign(b"hello").expect("sign");
+        let public = key.public().unwrap();
+        assert!(public.verify(b"hello", &signature));
+        assert!(!public.verify(b"world", &signature));
+    }
+
+    #[test]
+    fn test_address_roundtrip() {
+        let s = "XIN3BMNy9pQyj5XWDJtTbaBVE2zQ66zBo2weyc43iL286asdqwApWswAzQC5qba26fh3fzHK9iMoxyx1q3Lgj45KJftzGD9q";
+        let address = Address::from_string(s).expect("address");
+        assert_eq!(address.to_string(), s);
+
+        let mut broken = s.to_string();
+        broken.replace_range(10..11, "A");
+        assert!(Address::from_string(&broken).is_err());
+        assert!(Address::from_string(&s.replacen("XIN", "MIX", 1)).is_err());
+    }
+}
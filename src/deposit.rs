@@ -0,0 +1,499 @@
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use url::form_urlencoded;
+
+use crate::{
+    amount::Amount,
+    auth::sign_authentication_token,
+    error::Error,
+    models::Output,
+    output::list_all_unspent_outputs,
+    request::{ApiResponse, request},
+    safe::SafeUser,
+    snapshot::{SafeSnapshot, SafeSnapshotQuery, list_safe_snapshots},
+    utils::hash_members,
+};
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(10);
+const SNAPSHOTS_LIMIT: u32 = 500;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DepositEntryRequest {
+    pub chain_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub members: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<u8>,
+}
+
+/// Where to deposit assets of a chain. Chains sharing one address between
+/// users, e.g. EOS or XRP, also return a `tag` that must be sent as the memo
+/// of the deposit, or the funds won't be credited.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DepositEntry {
+    #[serde(default, rename = "type")]
+    pub type_name: Option<String>,
+    pub entry_id: String,
+    #[serde(default)]
+    pub chain_id: Option<String>,
+    #[serde(default)]
+    pub members: Vec<String>,
+    #[serde(default)]
+    pub threshold: Option<u8>,
+    #[serde(default)]
+    pub destination: Option<String>,
+    #[serde(default)]
+    pub tag: Option<String>,
+    #[serde(default)]
+    pub signature: Option<String>,
+    #[serde(default)]
+    pub is_primary: Option<bool>,
+}
+
+impl DepositEntry {
+    /// Whether deposits must carry `tag` as their memo.
+    pub fn requires_tag(&self) -> bool {
+        self.tag.as_deref().is_some_and(|t| !t.is_empty())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PendingDepositQuery {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asset: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+}
+
+/// A deposit seen on chain that doesn't have enough confirmations yet.
+/// `transaction_hash` and `output_index` are the on-chain ones.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PendingDeposit {
+    pub deposit_id: String,
+    #[serde(default)]
+    pub chain_id: Option<String>,
+    #[serde(default)]
+    pub asset_id: Option<String>,
+    #[serde(default)]
+    pub asset_key: Option<String>,
+    #[serde(default)]
+    pub kernel_asset_id: Option<String>,
+    #[serde(default, deserialize_with = "crate::amount::deserialize_optional")]
+    pub amount: Option<Amount>,
+    #[serde(default)]
+    pub sender: Option<String>,
+    #[serde(default)]
+    pub destination: Option<String>,
+    #[serde(default)]
+    pub tag: Option<String>,
+    #[serde(default)]
+    pub transaction_hash: Option<String>,
+    #[serde(default)]
+    pub output_index: Option<i64>,
+    #[serde(default)]
+    pub block_hash: Option<String>,
+    #[serde(default)]
+    pub block_number: Option<i64>,
+    #[serde(default)]
+    pub confirmations: Option<i64>,
+    #[serde(default)]
+    pub threshold: Option<i64>,
+    #[serde(default)]
+    pub state: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub updated_at: Option<String>,
+}
+
+/// Creates, or returns the existing, deposit entries of `chain_id`.
+pub async fn create_deposit_entries(
+    request_body: &DepositEntryRequest,
+    safe_user: &SafeUser,
+) -> Result<Vec<DepositEntry>, Error> {
+    let path = "/safe/deposit/entries";
+    let data_str = serde_json::to_string(request_body)?;
+    let token = sign_authentication_token("POST", path, &data_str, safe_user)?;
+    let body = request("POST", path, data_str.as_bytes(), &token).await?;
+
+    let parsed: ApiResponse<Vec<DepositEntry>> = serde_json::from_slice(&body)?;
+    if let Some(api_error) = parsed.error {
+        return Err(Error::Api(api_error));
+    }
+    parsed.data.ok_or_else(|| {
+        Error::DataNotFound("API response did not contain deposit entry data".to_string())
+    })
+}
+
+/// The primary deposit entry of the user on `chain_id`.
+pub async fn read_deposit_entry(
+    chain_id: &str,
+    safe_user: &SafeUser,
+) -> Result<DepositEntry, Error> {
+    let request_body = DepositEntryRequest {
+        chain_id: chain_id.to_string(),
+        members: Some(vec![safe_user.user_id.clone()]),
+        threshold: Some(1),
+    };
+    let entries = create_deposit_entries(&request_body, safe_user).await?;
+    entries
+        .iter()
+        .find(|e| e.is_primary == Some(true))
+        .or_else(|| entries.first())
+        .cloned()
+        .ok_or_else(|| Error::DataNotFound(format!("no deposit entry for chain {chain_id}")))
+}
+
+pub async fn list_pending_deposits(
+    query: &PendingDepositQuery,
+    safe_user: &SafeUser,
+) -> Result<Vec<PendingDeposit>, Error> {
+    let path = pending_deposits_path(query);
+    let token = sign_authentication_token("GET", &path, "", safe_user)?;
+    let body = request("GET", &path, &[], &token).await?;
+
+    let parsed: ApiResponse<Vec<PendingDeposit>> = serde_json::from_slice(&body)?;
+    if let Some(api_error) = parsed.error {
+        return Err(Error::Api(api_error));
+    }
+    parsed.data.ok_or_else(|| {
+        Error::DataNotFound("API response did not contain pending deposit data".to_string())
+    })
+}
+
+fn pending_deposits_path(query: &PendingDepositQuery) -> String {
+    let mut serializer = form_urlencoded::Serializer::new(String::new());
+    let filters = [
+        ("asset", &query.asset),
+        ("destination", &query.destination),
+        ("tag", &query.tag),
+        ("offset", &query.offset),
+    ];
+    for (key, value) in filters {
+        if let Some(value) = value.as_deref().filter(|v| !v.is_empty()) {
+            serializer.append_pair(key, value);
+        }
+    }
+    if let Some(limit) = query.limit {
+        serializer.append_pair("limit", &limit.to_string());
+    }
+    let query = serializer.finish();
+    if query.is_empty() {
+        "/safe/deposits".to_string()
+    } else {
+        format!("/safe/deposits?{query}")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum DepositEvent {
+    /// A deposit seen for the first time or with more confirmations.
+    Confirming(PendingDeposit),
+    /// A deposit credited to the user. `output` is `None` when the output was
+    /// already spent by the time the watcher looked for it.
+    Completed {
+        deposit: PendingDeposit,
+        snapshot: Box<SafeSnapshot>,
+        output: Option<Box<Output>>,
+    },
+    /// A deposit no longer pending whose snapshot wasn't found before it left
+    /// the latest snapshots of the asset, look it up with `list_safe_snapshots`.
+    Expired(PendingDeposit),
+}
+
+/// A deposit no longer pending, waiting for its snapshot.
+#[derive(Debug, Clone)]
+struct CreditedDeposit {
+    deposit: PendingDeposit,
+    /// The newest snapshot when the deposit was first looked for, its own
+    /// snapshot is no newer.
+    seen_before: Option<DateTime<Utc>>,
+}
+
+/// Follows the pending deposits matching a query and reports them until
+/// they are credited as unspent outputs of the user.
+pub struct DepositWatcher {
+    query: PendingDepositQuery,
+    members_hash: String,
+    poll_interval: Duration,
+    pending: BTreeMap<String, PendingDeposit>,
+    credited: BTreeMap<String, CreditedDeposit>,
+    events: VecDeque<DepositEvent>,
+}
+
+impl DepositWatcher {
+    pub fn new(query: PendingDepositQuery, user_id: &str) -> Self {
+        Self {
+            query,
+            members_hash: hash_members([user_id]),
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            pending: BTreeMap::new(),
+            credited: BTreeMap::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// The deposits still waiting for confirmations.
+    pub fn pending(&self) -> impl Iterator<Item = &PendingDeposit> {
+        self.pending.values()
+    }
+
+    /// Waits for the next event, polling `/safe/deposits` while there is none.
+    pub async fn next(&mut self, safe_user: &SafeUser) -> Result<DepositEvent, Error> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(event);
+            }
+            self.poll(safe_user).await?;
+            if self.events.is_empty() {
+                tokio::time::sleep(self.poll_interval).await;
+            }
+        }
+    }
+
+    /// Reads the pending deposits once and queues the events it finds.
+    pub async fn poll(&mut self, safe_user: &SafeUser) -> Result<(), Error> {
+        let deposits = list_pending_deposits(&self.query, safe_user).await?;
+        self.update_pending(deposits);
+        if self.credited.is_empty() {
+            return Ok(());
+        }
+
+        let query = SafeSnapshotQuery {
+            asset: self.query.asset.clone(),
+            limit: Some(SNAPSHOTS_LIMIT),
+            order: Some("DESC".to_string()),
+            ..Default::default()
+        };
+        let snapshots = list_safe_snapshots(&query, safe_user).await?;
+        let matched = self.match_snapshots(&snapshots);
+
+        let mut outputs: HashMap<String, Vec<Output>> = HashMap::new();
+        for (_, snapshot) in &matched {
+            let asset_id = snapshot.asset_id.clone().unwrap_or_default();
+            if let Entry::Vacant(entry) = outputs.entry(asset_id) {
+                let unspent =
+                    list_all_unspent_outputs(&self.members_hash, 1, Some(entry.key()), safe_user)
+                        .await?;
+                entry.insert(unspent);
+            }
+        }
+        for (deposit_id, snapshot) in matched {
+            let output = outputs[snapshot.asset_id.as_deref().unwrap_or_default()]
+                .iter()
+                .find(|o| {
+                    o.transaction_hash == snapshot.transaction_hash
+                        && o.output_index == snapshot.output_index
+                })
+                .cloned()
+                .map(Box::new);
+            if let Some(credited) = self.credited.remove(&deposit_id) {
+                self.events.push_back(DepositEvent::Completed {
+                    deposit: credited.deposit,
+                    snapshot: Box::new(snapshot),
+                    output,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// The credited deposits with their snapshot among `snapshots`, the latest
+    /// ones. Deposits are expired once a full page of snapshots newer than
+    /// the first one they were looked for in no longer includes theirs.
+    fn match_snapshots(&mut self, snapshots: &[SafeSnapshot]) -> Vec<(String, SafeSnapshot)> {
+        let times: Vec<DateTime<Utc>> = snapshots
+            .iter()
+            .filter_map(|s| s.created_at.as_deref())
+            .filter_map(|t| DateTime::parse_from_rfc3339(t).ok())
+            .map(|t| t.with_timezone(&Utc))
+            .collect();
+        let (newest, oldest) = (times.iter().max(), times.iter().min());
+        let full = snapshots.len() >= SNAPSHOTS_LIMIT as usize;
+
+        let mut matched = Vec::new();
+        let mut expired = Vec::new();
+        for (deposit_id, credited) in &mut self.credited {
+            if let Some(snapshot) = snapshots
+                .iter()
+                .find(|s| deposit_matches(&credited.deposit, s))
+            {
+                matched.push((deposit_id.clone(), snapshot.clone()));
+            } else if credited.seen_before.is_none() {
+                credited.seen_before = newest.copied();
+            } else if full && oldest.is_some_and(|o| Some(*o) > credited.seen_before) {
+                expired.push(deposit_id.clone());
+            }
+        }
+        for deposit_id in expired {
+            if let Some(credited) = self.credited.remove(&deposit_id) {
+                self.events
+                    .push_back(DepositEvent::Expired(credited.deposit));
+            }
+        }
+        matched
+    }
+
+    /// Queues the new and more confirmed deposits, and moves the ones no
+    /// longer pending to the credited set until their snapshot shows up.
+    fn update_pending(&mut self, deposits: Vec<PendingDeposit>) {
+        let mut current = BTreeMap::new();
+        for deposit in deposits {
+            let changed = self
+                .pending
+                .get(&deposit.deposit_id)
+                .is_none_or(|d| d.confirmations != deposit.confirmations);
+            if changed {
+                self.events
+                    .push_back(DepositEvent::Confirming(deposit.clone()));
+            }
+            current.insert(deposit.deposit_id.clone(), deposit);
+        }
+        let previous = std::mem::replace(&mut self.pending, current);
+        for (deposit_id, deposit) in previous {
+            if !self.pending.contains_key(&deposit_id) {
+                self.credited.insert(
+                    deposit_id,
+                    CreditedDeposit {
+                        deposit,
+                        seen_before: None,
+                    },
+                );
+            }
+        }
+    }
+}
+
+fn deposit_matches(deposit: &PendingDeposit, snapshot: &SafeSnapshot) -> bool {
+    let Some(info) = &snapshot.deposit else {
+        return false;
+    };
+    info.deposit_hash.is_some()
+        && info.deposit_hash == deposit.transaction_hash
+        && info.deposit_index == deposit.output_index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::SafeSnapshotDeposit;
+
+    fn deposit(id: &str, confirmations: i64) -> PendingDeposit {
+        PendingDeposit {
+            deposit_id: id.to_string(),
+            transaction_hash: Some(format!("hash-{id}")),
+            output_index: Some(0),
+            confirmations: Some(confirmations),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_pending_deposits_path() {
+        let query = PendingDepositQuery {
+            asset: Some("asset-id".to_string()),
+            destination: Some("dest".to_string()),
+            tag: Some(String::new()),
+            limit: Some(20),
+            ..Default::default()
+        };
+        assert_eq!(
+            pending_deposits_path(&query),
+            "/safe/deposits?asset=asset-id&destination=dest&limit=20"
+        );
+        assert_eq!(
+            pending_deposits_path(&PendingDepositQuery::default()),
+            "/safe/deposits"
+        );
+
+        let entry: DepositEntry = serde_json::from_str(
+            r#"{"entry_id":"entry-id","destination":"eoswithmixin","tag":"303"}"#,
+        )
+        .unwrap();
+        assert!(entry.requires_tag());
+    }
+
+    #[test]
+    fn test_deposit_watcher_updates() {
+        let mut watcher = DepositWatcher::new(PendingDepositQuery::default(), "user-id");
+        watcher.update_pending(vec![deposit("a", 1), deposit("b", 1)]);
+        assert_eq!(watcher.events.len(), 2);
+        watcher.events.clear();
+
+        watcher.update_pending(vec![deposit("a", 1), deposit("b", 3)]);
+        assert!(matches!(
+            watcher.events.pop_front(),
+            Some(DepositEvent::Confirming(d)) if d.deposit_id == "b"
+        ));
+        assert!(watcher.events.is_empty());
+
+        watcher.update_pending(vec![deposit("b", 4)]);
+        watcher.events.clear();
+        assert_eq!(watcher.pending().count(), 1);
+        let credited = &watcher.credited["a"].deposit;
+
+        let mut snapshot = SafeSnapshot {
+            deposit: Some(SafeSnapshotDeposit {
+                deposit_hash: Some("hash-a".to_string()),
+                deposit_index: Some(0),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(deposit_matches(credited, &snapshot));
+        snapshot.deposit.as_mut().unwrap().deposit_index = Some(1);
+        assert!(!deposit_matches(credited, &snapshot));
+    }
+
+    #[test]
+    fn test_deposit_watcher_matches_and_expires() {
+        let snapshot = |id: &str, minute: u32, hash: &str| SafeSnapshot {
+            snapshot_id: id.to_string(),
+            created_at: Some(format!("2024-01-01T00:{minute:02}:00Z")),
+            deposit: Some(SafeSnapshotDeposit {
+                deposit_hash: Some(hash.to_string()),
+                deposit_index: Some(0),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut watcher = DepositWatcher::new(PendingDepositQuery::default(), "user-id");
+        watcher.update_pending(vec![deposit("a", 1), deposit("b", 1)]);
+        watcher.update_pending(vec![]);
+        watcher.events.clear();
+
+        let matched = watcher.match_snapshots(&[snapshot("1", 1, "hash-a"), snapshot("2", 2, "x")]);
+        assert_eq!(matched.len(), 1);
+        assert_eq!(matched[0].0, "a");
+        watcher.credited.remove("a");
+        assert!(watcher.credited["b"].seen_before.is_some());
+
+        // A full page newer than the first one no longer holds the snapshot.
+        let newer: Vec<SafeSnapshot> = (0..SNAPSHOTS_LIMIT)
+            .map(|i| snapshot(&i.to_string(), 3, "x"))
+            .collect();
+        assert!(watcher.match_snapshots(&newer[..10]).is_empty());
+        assert_eq!(watcher.credited.len(), 1);
+        assert!(watcher.match_snapshots(&newer).is_empty());
+        assert!(watcher.credited.is_empty());
+        assert!(matches!(
+            watcher.events.pop_front(),
+            Some(DepositEvent::Expired(d)) if d.deposit_id == "b"
+        ));
+    }
+}
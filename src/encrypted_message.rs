@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use aes::Aes256;
+use aes_gcm::{Aes128Gcm, KeyInit, Nonce, aead::Aead};
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use cbc::{Decryptor, Encryptor};
+use cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit, block_padding::Pkcs7};
+use ed25519_dalek::SigningKey;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    conversation::ParticipantSession,
+    error::Error,
+    message::decode_message_data,
+    pin::{private_key_to_curve25519, public_key_to_curve25519},
+    safe::SafeUser,
+    user::fetch_user_sessions,
+};
+
+const ENCRYPTION_VERSION: u8 = 1;
+// Session id followed by the IV and the AES-CBC encrypted message key.
+const SESSION_BLOCK_SIZE: usize = 16 + 16 + 32;
+const HEADER_SIZE: usize = 1 + 2 + 32;
+const NONCE_SIZE: usize = 12;
+
+const DEFAULT_SESSION_TTL: Duration = Duration::from_secs(600);
+
+/// A recipient session of an encrypted message, as sent in
+/// `MessageRequest.recipient_sessions`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct RecipientSession {
+    pub session_id: String,
+}
+
+/// Encrypts `data` for every session with a fresh AES-GCM message key, the
+/// key itself is wrapped for each session with the curve25519 secret shared
+/// with our session key. Returns the base64 message data.
+pub fn encrypt_message_data(
+    data: &[u8],
+    sessions: &[ParticipantSession],
+    safe_user: &SafeUser,
+) -> Result<String, Error> {
+    encrypt_message_data_with_rng(data, sessions, safe_user, &mut rand::rngs::OsRng)
+}
+
+fn encrypt_message_data_with_rng(
+    data: &[u8],
+    sessions: &[ParticipantSession],
+    safe_user: &SafeUser,
+    rng: &mut impl RngCore,
+) -> Result<String, Error> {
+    if sessions.is_empty() || sessions.len() > u16::MAX as usize {
+        return Err(Error::Input(format!(
+            "invalid recipient sessions count: {}",
+            sessions.len()
+        )));
+    }
+    let seed = session_seed(safe_user)?;
+    let public = SigningKey::from_bytes(&seed).verifying_key().to_bytes();
+    let private = private_key_to_curve25519(&seed);
+
+    let mut message_key = [0u8; 16];
+    rng.fill_bytes(&mut message_key);
+    let mut nonce = [0u8; NONCE_SIZE];
+    rng.fill_bytes(&mut nonce);
+    let ciphertext = Aes128Gcm::new_from_slice(&message_key)
+        .map_err(|e| Error::Input(format!("invalid message key: {e}")))?
+        .encrypt(Nonce::from_slice(&nonce), data)
+        .map_err(|e| Error::Input(format!("message encryption: {e}")))?;
+
+    let mut result = Vec::with_capacity(
+        HEADER_SIZE + sessions.len() * SESSION_BLOCK_SIZE + NONCE_SIZE + ciphertext.len(),
+    );
+    result.push(ENCRYPTION_VERSION);
+    result.extend_from_slice(&(sessions.len() as u16).to_le_bytes());
+    result.extend_from_slice(&public_key_to_curve25519(&public)?);
+    for session in sessions {
+        let session_id = session.session_id.as_deref().unwrap_or_default();
+        let session_id = Uuid::parse_str(session_id)
+            .map_err(|_| Error::Input(format!("invalid session id: {session_id}")))?;
+        let shared = x25519_dalek::x25519(private, session_public_key(session)?);
+
+        let mut iv = [0u8; 16];
+        rng.fill_bytes(&mut iv);
+        let mut buf = [0u8; 32];
+        buf[..16].copy_from_slice(&message_key);
+        let wrapped = Encryptor::<Aes256>::new_from_slices(&shared, &iv)
+            .map_err(|e| Error::Input(format!("invalid session key or iv: {e}")))?
+            .encrypt_padded_mut::<Pkcs7>(&mut buf, message_key.len())
+            .map_err(|e| Error::Input(format!("message key encryption: {e}")))?;
+
+        result.extend_from_slice(session_id.as_bytes());
+        result.extend_from_slice(&iv);
+        result.extend_from_slice(wrapped);
+    }
+    result.extend_from_slice(&nonce);
+    result.extend_from_slice(&ciphertext);
+    Ok(URL_SAFE_NO_PAD.encode(result))
+}
+
+/// Decrypts the data of an encrypted message, `None` if it was not
+/// encrypted for our session.
+pub fn decrypt_message_data(
+    data_base64: &str,
+    safe_user: &SafeUser,
+) -> Result<Option<Vec<u8>>, Error> {
+    let data = decode_message_data(data_base64)?;
+    if data.len() < HEADER_SIZE + NONCE_SIZE {
+        return Err(Error::Input("encrypted message is too short".to_string()));
+    }
+    if data[0] != ENCRYPTION_VERSION {
+        return Err(Error::Input(format!(
+            "unsupported encrypted message version: {}",
+            data[0]
+        )));
+    }
+    let count = u16::from_le_bytes([data[1], data[2]]) as usize;
+    let prefix_size = HEADER_SIZE + count * SESSION_BLOCK_SIZE;
+    if data.len() < prefix_size + NONCE_SIZE {
+        return Err(Error::Input("encrypted message is truncated".to_string()));
+    }
+
+    let session_id = Uuid::parse_str(&safe_user.session_id)
+        .map_err(|_| Error::Input(format!("invalid session id: {}", safe_user.session_id)))?;
+    let Some(block) = data[HEADER_SIZE..prefix_size]
+        .chunks_exact(SESSION_BLOCK_SIZE)
+        .find(|block| block[..16] == session_id.as_bytes()[..])
+    else {
+        return Ok(None);
+    };
+
+    let seed = session_seed(safe_user)?;
+    let mut sender = [0u8; 32];
+    sender.copy_from_slice(&data[3..HEADER_SIZE]);
+    let shared = x25519_dalek::x25519(private_key_to_curve25519(&seed), sender);
+    let mut buf = block[32..].to_vec();
+    let message_key = Decryptor::<Aes256>::new_from_slices(&shared, &block[16..32])
+        .map_err(|e| Error::Input(format!("invalid session key or iv: {e}")))?
+        .decrypt_padded_mut::<Pkcs7>(&mut buf)
+        .map_err(|_| Error::Input("invalid encrypted message key".to_string()))?;
+    if message_key.len() != 16 {
+        return Err(Error::Input("invalid encrypted message key".to_string()));
+    }
+
+    let nonce = &data[prefix_size..prefix_size + NONCE_SIZE];
+    let plaintext = Aes128Gcm::new_from_slice(message_key)
+        .map_err(|e| Error::Input(format!("invalid message key: {e}")))?
+        .decrypt(Nonce::from_slice(nonce), &data[prefix_size + NONCE_SIZE..])
+        .map_err(|_| Error::Input("encrypted message authentication failed".to_string()))?;
+    Ok(Some(plaintext))
+}
+
+/// The checksum of the recipient sessions the API compares with its own
+/// list, rejecting the message when a session is missing or stale.
+pub fn sessions_checksum(sessions: &[ParticipantSession]) -> String {
+    let mut ids: Vec<&str> = sessions
+        .iter()
+        .filter_map(|s| s.session_id.as_deref())
+        .collect();
+    ids.sort_unstable();
+    format!("{:x}", md5::compute(ids.concat()))
+}
+
+/// Caches the sessions of users for encrypting messages to them, fetching
+/// the missing or expired ones with `POST /sessions/fetch`.
+#[derive(Debug)]
+pub struct SessionCache {
+    ttl: Duration,
+    sessions: HashMap<String, (Instant, Vec<ParticipantSession>)>,
+}
+
+impl Default for SessionCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_SESSION_TTL)
+    }
+}
+
+impl SessionCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            sessions: HashMap::new(),
+        }
+    }
+
+    /// The sessions of all `user_ids`.
+    pub async fn sessions<T: AsRef<str>>(
+        &mut self,
+        user_ids: &[T],
+        safe_user: &SafeUser,
+    ) -> Result<Vec<ParticipantSession>, Error> {
+        let missing: Vec<String> = user_ids
+            .iter()
+            .map(|id| id.as_ref())
+            .filter(|id| self.cached(id).is_none())
+            .map(str::to_string)
+            .collect();
+        if !missing.is_empty() {
+            let fetched = fetch_user_sessions(&missing, safe_user).await?;
+            self.insert(&missing, fetched);
+        }
+        Ok(user_ids
+            .iter()
+            .filter_map(|id| self.cached(id.as_ref()))
+            .flatten()
+            .cloned()
+            .collect())
+    }
+
+    /// Drops the sessions of `user_id`, e.g. after the API rejected the
+    /// checksum of a message to them.
+    pub fn invalidate(&mut self, user_id: &str) {
+        self.sessions.remove(user_id);
+    }
+
+    // Stores fetched sessions, users without any get an empty entry so they
+    // are not fetched again until it expires.
+    fn insert(&mut self, user_ids: &[String], fetched: Vec<ParticipantSession>) {
+        let now = Instant::now();
+        for user_id in user_ids {
+            self.sessions.insert(user_id.clone(), (now, Vec::new()));
+        }
+        for session in fetched {
+            let Some(user_id) = session.user_id.clone() else {
+                continue;
+            };
+            self.sessions
+                .entry(user_id)
+                .or_insert_with(|| (now, Vec::new()))
+                .1
+                .push(session);
+        }
+    }
+
+    fn cached(&self, user_id: &str) -> Option<&Vec<ParticipantSession>> {
+        self.sessions
+            .get(user_id)
+            .filter(|(fetched_at, _)| fetched_at.elapsed() < self.ttl)
+            .map(|(_, sessions)| sessions)
+    }
+}
+
+fn session_seed(safe_user: &SafeUser) -> Result<[u8; 32], Error> {
+    hex::decode(&safe_user.session_private_key)?
+        .try_into()
+        .map_err(|_| Error::Input("invalid session private key length".to_string()))
+}
+
+fn session_public_key(session: &ParticipantSession) -> Result<[u8; 32], Error> {
+    let public_key = session.public_key.as_deref().unwrap_or_default();
+    URL_SAFE_NO_PAD
+        .decode(public_key.trim_end_matches('='))
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| Error::Input(format!("invalid session public key: {public_key}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{TEST_SESSION_ID, session_user};
+    use crate::message::{MessageCategory, MessagePayload, MessageRequest};
+    use rand::{SeedableRng, rngs::StdRng};
+
+    // The curve25519 session public key the API returns for `user`.
+    fn session_of(user: &SafeUser) -> ParticipantSession {
+        let seed = session_seed(user).unwrap();
+        let public = SigningKey::from_bytes(&seed).verifying_key().to_bytes();
+        ParticipantSession {
+            session_type: None,
+            user_id: Some(user.user_id.clone()),
+            session_id: Some(user.session_id.clone()),
+            public_key: Some(URL_SAFE_NO_PAD.encode(public_key_to_curve25519(&public).unwrap())),
+        }
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_message_data() {
+        let sender = session_user(1, TEST_SESSION_ID);
+        let alice = session_user(2, "3ad5d4a3-60a4-4ee6-9f3c-a33ec8fc4a1e");
+        let bob = session_user(3, "2d8a0d2f-2e9a-4b0f-8c0a-4cda7cb48a35");
+        let stranger = session_user(4, "9b5d3c43-3d8e-4c8a-9b5c-2b8b1e3f3f7a");
+
+        let sessions = [session_of(&alice), session_of(&bob)];
+        let mut rng = StdRng::seed_from_u64(7);
+        let encrypted =
+            encrypt_message_data_with_rng(b"hello", &sessions, &sender, &mut rng).unwrap();
+        let raw = decode_message_data(&encrypted).unwrap();
+        assert_eq!(
+            raw.len(),
+            HEADER_SIZE + 2 * SESSION_BLOCK_SIZE + NONCE_SIZE + 5 + 16
+        );
+
+        for user in [&alice, &bob] {
+            assert_eq!(
+                decrypt_message_data(&encrypted, user).unwrap().unwrap(),
+                b"hello"
+            );
+        }
+        assert!(
+            decrypt_message_data(&encrypted, &stranger)
+                .unwrap()
+                .is_none()
+        );
+
+        let mut tampered = raw.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(decrypt_message_data(&URL_SAFE_NO_PAD.encode(tampered), &alice).is_err());
+        assert!(encrypt_message_data(b"hello", &[], &sender).is_err());
+
+        let payload = MessagePayload::Text("hi".to_string());
+        let request =
+            MessageRequest::new_encrypted("c", "m", &payload, &sessions, &sender).unwrap();
+        assert_eq!(request.category, MessageCategory::EncryptedText);
+        assert_eq!(request.checksum.unwrap(), sessions_checksum(&sessions));
+        assert_eq!(request.recipient_sessions.unwrap().len(), 2);
+        let data = decrypt_message_data(&request.data_base64, &bob)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            MessagePayload::decode(&MessageCategory::PlainText, &data).unwrap(),
+            payload
+        );
+    }
+
+    #[test]
+    fn test_sessions_checksum_and_cache() {
+        let session = |user_id: &str, session_id: &str| ParticipantSession {
+            user_id: Some(user_id.to_string()),
+            session_id: Some(session_id.to_string()),
+            ..Default::default()
+        };
+        let a = session("u1", "b");
+        let b = session("u1", "a");
+        assert_eq!(
+            sessions_checksum(&[a.clone(), b.clone()]),
+            format!("{:x}", md5::compute("ab"))
+        );
+        assert_eq!(
+            sessions_checksum(&[a.clone(), b.clone()]),
+            sessions_checksum(&[b, a])
+        );
+
+        let mut cache = SessionCache::default();
+        cache.insert(
+            &["u1".to_string(), "u2".to_string()],
+            vec![session("u1", "a"), session("u1", "b")],
+        );
+        assert_eq!(cache.cached("u1").unwrap().len(), 2);
+        assert!(cache.cached("u2").unwrap().is_empty());
+        assert!(cache.cached("u3").is_none());
+        cache.invalidate("u1");
+        assert!(cache.cached("u1").is_none());
+
+        let expired = SessionCache {
+            ttl: Duration::ZERO,
+            sessions: cache.sessions,
+        };
+        assert!(expired.cached("u2").is_none());
+    }
+}
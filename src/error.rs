@@ -21,6 +21,10 @@ pub enum Error {
     DataNotFound(String),
     #[error("Server error: {0}")]
     Server(String),
+    #[error("Storage error: {0}")]
+    Storage(String),
+    #[error("Consolidation required: {0} inputs needed, at most {1} allowed")]
+    ConsolidationRequired(usize, usize),
 }
 
 impl From<reqwest::Error> for Error {
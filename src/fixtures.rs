@@ -0,0 +1,59 @@
+//! Fixtures shared by the tests of every module.
+
+use crate::{
+    models::Output,
+    output::OUTPUT_STATE_UNSPENT,
+    safe::{GhostKeys, SafeUser},
+};
+
+pub(crate) const TEST_USER_ID: &str = "7766b24c-1a03-4c3a-83a3-b4358266875d";
+pub(crate) const TEST_SESSION_ID: &str = "b3f0c1a0-3f3b-4a0e-9c4b-5b1a8b3c3f70";
+
+/// A user whose session key is derived from `seed`.
+pub(crate) fn session_user(seed: u8, session_id: &str) -> SafeUser {
+    SafeUser::new(
+        TEST_USER_ID.to_string(),
+        session_id.to_string(),
+        hex::encode([seed; 32]),
+        String::new(),
+        String::new(),
+    )
+}
+
+/// The session user of seed 7, with a spend key for signing transactions.
+pub(crate) fn test_user() -> SafeUser {
+    SafeUser {
+        spend_private_key: "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f"
+            .to_string(),
+        ..session_user(7, TEST_SESSION_ID)
+    }
+}
+
+/// An unspent output of `amount`, override other fields with
+/// `Output { .., ..output(id, amount) }`.
+pub(crate) fn output(output_id: &str, amount: &str) -> Output {
+    Output {
+        output_id: output_id.to_string(),
+        amount: Some(amount.parse().unwrap()),
+        state: Some(OUTPUT_STATE_UNSPENT.to_string()),
+        ..Default::default()
+    }
+}
+
+/// Unspent outputs "output-0", "output-1"... of `amounts`.
+pub(crate) fn outputs(amounts: &[&str]) -> Vec<Output> {
+    amounts
+        .iter()
+        .enumerate()
+        .map(|(i, amount)| output(&format!("output-{i}"), amount))
+        .collect()
+}
+
+/// Ghost keys of a single key, for outputs whose keys don't matter.
+pub(crate) fn ghost_keys() -> GhostKeys {
+    GhostKeys {
+        key_type: "ghost_key".to_string(),
+        mask: "22".repeat(32),
+        keys: vec!["33".repeat(32)],
+    }
+}
@@ -0,0 +1,418 @@
+use std::collections::HashSet;
+use std::future::Future;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    blaze::MessageView,
+    conversation::Participant,
+    error::Error,
+    message::{
+        AudioMessage, ContactMessage, DataMessage, ImageMessage, MessageCategory, MessagePayload,
+        StickerMessage, TranscriptMessage, VideoMessage,
+    },
+    models::Snapshot,
+    safe::SafeUser,
+    snapshot::SafeSnapshot,
+    user::{User, search_user},
+};
+
+// An @ and an identity number, at the start of the text or after a space.
+static MENTION: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?:^|\s)@(\d+)\b").unwrap());
+
+/// The data of a SYSTEM_CONVERSATION message, as sent.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct SystemConversationMessage {
+    pub action: String,
+    #[serde(default)]
+    pub participant_id: Option<String>,
+    /// Who did it.
+    #[serde(default)]
+    pub user_id: Option<String>,
+    #[serde(default)]
+    pub role: Option<String>,
+}
+
+/// A change to a conversation, decoded from a SYSTEM_CONVERSATION message.
+#[derive(Debug, Clone)]
+pub enum ConversationEvent {
+    Created {
+        creator_id: Option<String>,
+    },
+    ParticipantAdded {
+        participant: Participant,
+        by: Option<String>,
+    },
+    ParticipantRemoved {
+        participant: Participant,
+        by: Option<String>,
+    },
+    /// Joined with an invitation link.
+    ParticipantJoined {
+        participant: Participant,
+    },
+    ParticipantExited {
+        participant: Participant,
+    },
+    /// `participant.role` is the new role, empty when it was revoked.
+    RoleChanged {
+        participant: Participant,
+        by: Option<String>,
+    },
+    /// The name, announcement or another setting changed, the message does
+    /// not say which one so read the conversation again.
+    Updated {
+        by: Option<String>,
+    },
+    /// An action this SDK does not know yet.
+    Other(SystemConversationMessage),
+}
+
+impl ConversationEvent {
+    pub fn from_message(message: &MessageView) -> Result<Self, Error> {
+        if message.category != MessageCategory::SystemConversation {
+            return Err(Error::Input(format!(
+                "{} is not a conversation event",
+                message.category
+            )));
+        }
+        Ok(Self::from_system_message(message.data_json()?))
+    }
+
+    pub fn from_system_message(message: SystemConversationMessage) -> Self {
+        let participant = || {
+            message.participant_id.clone().map(|user_id| Participant {
+                user_id,
+                role: message.role.clone(),
+                created_at: None,
+            })
+        };
+        let by = message.user_id.clone();
+        let event = match message.action.as_str() {
+            "CREATE" => Some(ConversationEvent::Created { creator_id: by }),
+            "ADD" => participant()
+                .map(|participant| ConversationEvent::ParticipantAdded { participant, by }),
+            "REMOVE" => participant()
+                .map(|participant| ConversationEvent::ParticipantRemoved { participant, by }),
+            "JOIN" => participant()
+                .map(|participant| ConversationEvent::ParticipantJoined { participant }),
+            "EXIT" => participant()
+                .map(|participant| ConversationEvent::ParticipantExited { participant }),
+            "ROLE" => {
+                participant().map(|participant| ConversationEvent::RoleChanged { participant, by })
+            }
+            "UPDATE" => Some(ConversationEvent::Updated { by }),
+            _ => None,
+        };
+        event.unwrap_or(ConversationEvent::Other(message))
+    }
+}
+
+/// A change to the balance of the bot, decoded from a
+/// SYSTEM_ACCOUNT_SNAPSHOT or SYSTEM_SAFE_SNAPSHOT message.
+#[derive(Debug, Clone)]
+pub enum AccountEvent {
+    Snapshot(Box<Snapshot>),
+    SafeSnapshot(Box<SafeSnapshot>),
+}
+
+impl AccountEvent {
+    pub fn from_message(message: &MessageView) -> Result<Self, Error> {
+        match message.category {
+            MessageCategory::SystemAccountSnapshot => {
+                Ok(AccountEvent::Snapshot(Box::new(message.data_json()?)))
+            }
+            MessageCategory::SystemSafeSnapshot => {
+                Ok(AccountEvent::SafeSnapshot(Box::new(message.data_json()?)))
+            }
+            ref other => Err(Error::Input(format!("{other} is not an account event"))),
+        }
+    }
+}
+
+/// The items of a PLAIN_TRANSCRIPT message, in the order they were sent.
+pub fn transcript_items(message: &MessageView) -> Result<Vec<TranscriptMessage>, Error> {
+    match message.payload()? {
+        MessagePayload::Transcript(mut items) => {
+            items.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+            Ok(items)
+        }
+        _ => Err(Error::Input(format!(
+            "{} is not a transcript",
+            message.category
+        ))),
+    }
+}
+
+/// The payload of a transcript item, as if it was sent on its own.
+/// Attachment items keep their attachment id in `content`.
+pub fn transcript_item_payload(item: &TranscriptMessage) -> Result<MessagePayload, Error> {
+    let content = || {
+        item.content
+            .clone()
+            .ok_or_else(|| Error::DataNotFound(format!("transcript item {}", item.message_id)))
+    };
+    let mime_type = || item.media_mime_type.clone().unwrap_or_default();
+    // Items keep the category they were sent with, e.g. ENCRYPTED_TEXT or
+    // SIGNAL_TEXT.
+    let category = item.category.to_plain();
+    let category = match &category {
+        MessageCategory::Other(other) => other
+            .strip_prefix("SIGNAL_")
+            .map(|kind| MessageCategory::from(format!("PLAIN_{kind}").as_str()))
+            .unwrap_or(category),
+        _ => category,
+    };
+    Ok(match category {
+        MessageCategory::PlainText => MessagePayload::Text(content()?),
+        MessageCategory::PlainPost => MessagePayload::Post(content()?),
+        MessageCategory::PlainImage => MessagePayload::Image(ImageMessage {
+            attachment_id: content()?,
+            mime_type: mime_type(),
+            width: item.media_width.unwrap_or_default(),
+            height: item.media_height.unwrap_or_default(),
+            size: item.media_size.unwrap_or_default(),
+            thumbnail: item.thumb_image.clone(),
+            key: item.media_key.clone(),
+            digest: item.media_digest.clone(),
+        }),
+        MessageCategory::PlainVideo => MessagePayload::Video(VideoMessage {
+            attachment_id: content()?,
+            mime_type: mime_type(),
+            width: item.media_width.unwrap_or_default(),
+            height: item.media_height.unwrap_or_default(),
+            size: item.media_size.unwrap_or_default(),
+            duration: item.media_duration.unwrap_or_default(),
+            thumbnail: item.thumb_image.clone(),
+            key: item.media_key.clone(),
+            digest: item.media_digest.clone(),
+        }),
+        MessageCategory::PlainAudio => MessagePayload::Audio(AudioMessage {
+            attachment_id: content()?,
+            mime_type: mime_type(),
+            size: item.media_size.unwrap_or_default(),
+            duration: item.media_duration.unwrap_or_default(),
+            wave_form: None,
+            key: item.media_key.clone(),
+            digest: item.media_digest.clone(),
+        }),
+        MessageCategory::PlainData => MessagePayload::Data(DataMessage {
+            attachment_id: content()?,
+            mime_type: mime_type(),
+            size: item.media_size.unwrap_or_default(),
+            name: item.media_name.clone().unwrap_or_default(),
+            key: item.media_key.clone(),
+            digest: item.media_digest.clone(),
+        }),
+        MessageCategory::PlainSticker => MessagePayload::Sticker(StickerMessage {
+            sticker_id: item.sticker_id.clone().map_or_else(content, Ok)?,
+            name: None,
+            album_id: None,
+        }),
+        MessageCategory::PlainContact => MessagePayload::Contact(ContactMessage {
+            user_id: item.shared_user_id.clone().map_or_else(content, Ok)?,
+        }),
+        other => MessagePayload::decode(&other, content()?.as_bytes())?,
+    })
+}
+
+/// The identity numbers mentioned with @ in `text`, each once.
+pub fn mentioned_identity_numbers(text: &str) -> Vec<String> {
+    let mut seen = HashSet::new();
+    MENTION
+        .captures_iter(text)
+        .map(|c| c[1].to_string())
+        .filter(|number| seen.insert(number.clone()))
+        .collect()
+}
+
+/// The ids of the `users` mentioned in `text`, e.g. the participants of the
+/// conversation, mentions of anyone else are left out.
+pub fn mentioned_user_ids(text: &str, users: &[User]) -> Vec<String> {
+    mentioned_identity_numbers(text)
+        .iter()
+        .filter_map(|number| {
+            users
+                .iter()
+                .find(|u| u.identity_number.as_deref() == Some(number))
+                .map(|u| u.user_id.clone())
+        })
+        .collect()
+}
+
+/// Looks up the users mentioned in `text`, skipping identity numbers with
+/// no user.
+pub async fn fetch_mentioned_users(text: &str, safe_user: &SafeUser) -> Result<Vec<User>, Error> {
+    fetch_mentioned_users_with(text, |number| async move {
+        search_user(&number, safe_user).await
+    })
+    .await
+}
+
+async fn fetch_mentioned_users_with<F, Fut>(text: &str, mut search: F) -> Result<Vec<User>, Error>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = Result<Vec<User>, Error>>,
+{
+    let mut users = Vec::new();
+    for number in mentioned_identity_numbers(text) {
+        let found = match search(number.clone()).await {
+            Ok(found) => found,
+            Err(e) if is_not_found(&e) => continue,
+            Err(e) => return Err(e),
+        };
+        if let Some(user) = found
+            .into_iter()
+            .find(|u| u.identity_number.as_deref() == Some(&number))
+        {
+            users.push(user);
+        }
+    }
+    Ok(users)
+}
+
+fn is_not_found(error: &Error) -> bool {
+    match error {
+        Error::DataNotFound(_) => true,
+        Error::Api(e) => e.status == 404 || e.code == 404 || e.code == 10404,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::ApiError;
+    use base64::{Engine as _, engine::general_purpose::STANDARD};
+
+    fn message(category: &str, data: &str) -> MessageView {
+        MessageView {
+            conversation_id: "conversation-id".to_string(),
+            user_id: "user-id".to_string(),
+            message_id: "message-id".to_string(),
+            category: MessageCategory::from(category),
+            data_base64: STANDARD.encode(data),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_system_events() {
+        let added = message(
+            "SYSTEM_CONVERSATION",
+            r#"{"action":"ADD","participant_id":"p1","user_id":"admin"}"#,
+        );
+        match ConversationEvent::from_message(&added).unwrap() {
+            ConversationEvent::ParticipantAdded { participant, by } => {
+                assert_eq!(participant.user_id, "p1");
+                assert_eq!(by.as_deref(), Some("admin"));
+            }
+            event => panic!("unexpected event {event:?}"),
+        }
+        let role = message(
+            "SYSTEM_CONVERSATION",
+            r#"{"action":"ROLE","participant_id":"p1","user_id":"owner","role":"ADMIN"}"#,
+        );
+        assert!(matches!(
+            ConversationEvent::from_message(&role).unwrap(),
+            ConversationEvent::RoleChanged { participant, .. } if participant.role.as_deref() == Some("ADMIN")
+        ));
+        let update = message(
+            "SYSTEM_CONVERSATION",
+            r#"{"action":"UPDATE","user_id":"owner"}"#,
+        );
+        assert!(matches!(
+            ConversationEvent::from_message(&update).unwrap(),
+            ConversationEvent::Updated { .. }
+        ));
+        // A participant action without a participant is kept as sent.
+        let unknown = message("SYSTEM_CONVERSATION", r#"{"action":"REMOVE"}"#);
+        assert!(matches!(
+            ConversationEvent::from_message(&unknown).unwrap(),
+            ConversationEvent::Other(m) if m.action == "REMOVE"
+        ));
+        assert!(ConversationEvent::from_message(&message("PLAIN_TEXT", "hi")).is_err());
+
+        let snapshot = message(
+            "SYSTEM_ACCOUNT_SNAPSHOT",
+            r#"{"snapshot_id":"s1","amount":"-1.5","opponent_id":"u2"}"#,
+        );
+        match AccountEvent::from_message(&snapshot).unwrap() {
+            AccountEvent::Snapshot(snapshot) => {
+                assert_eq!(snapshot.snapshot_id, "s1");
+                assert_eq!(snapshot.amount.unwrap().to_string(), "-1.5");
+            }
+            event => panic!("unexpected event {event:?}"),
+        }
+        assert!(AccountEvent::from_message(&added).is_err());
+    }
+
+    #[test]
+    fn test_transcript_and_mentions() {
+        let transcript = serde_json::json!([
+            {"transcript_id": "t", "message_id": "m2", "category": "ENCRYPTED_IMAGE",
+             "content": "attachment-id", "media_width": 10, "media_height": 20,
+             "media_mime_type": "image/png", "created_at": "2024-01-02T00:00:00Z"},
+            {"transcript_id": "t", "message_id": "m1", "category": "SIGNAL_TEXT",
+             "content": "hello", "created_at": "2024-01-01T00:00:00Z"},
+        ]);
+        let items =
+            transcript_items(&message("PLAIN_TRANSCRIPT", &transcript.to_string())).unwrap();
+        assert_eq!(items[0].message_id, "m1");
+        assert_eq!(
+            transcript_item_payload(&items[0]).unwrap(),
+            MessagePayload::Text("hello".to_string())
+        );
+        match transcript_item_payload(&items[1]).unwrap() {
+            MessagePayload::Image(image) => {
+                assert_eq!(image.attachment_id, "attachment-id");
+                assert_eq!((image.width, image.height), (10, 20));
+            }
+            payload => panic!("unexpected payload {payload:?}"),
+        }
+        assert!(transcript_items(&message("PLAIN_TEXT", "hi")).is_err());
+
+        let text = "@7000 hi @7001, mail a@7002 or @7000 and @x";
+        assert_eq!(mentioned_identity_numbers(text), ["7000", "7001"]);
+        let users = [("u1", "7001"), ("u2", "7000"), ("u3", "7003")].map(|(id, number)| User {
+            user_id: id.to_string(),
+            identity_number: Some(number.to_string()),
+            ..Default::default()
+        });
+        assert_eq!(mentioned_user_ids(text, &users), ["u2", "u1"]);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_mentioned_users_skips_unknown() {
+        let search = |number: String| async move {
+            let error = |status, code| {
+                Err(Error::Api(ApiError {
+                    status,
+                    code,
+                    description: String::new(),
+                }))
+            };
+            match number.as_str() {
+                "7000" => error(202, 10404),
+                "7001" => Err(Error::DataNotFound("no user".to_string())),
+                "7002" => error(404, 404),
+                "7003" => error(500, 500),
+                _ => Ok(vec![User {
+                    user_id: format!("user-{number}"),
+                    identity_number: Some(number),
+                    ..Default::default()
+                }]),
+            }
+        };
+        let users = fetch_mentioned_users_with("@7000 @7001 @7002 @7004", search)
+            .await
+            .unwrap();
+        let ids: Vec<&str> = users.iter().map(|u| u.user_id.as_str()).collect();
+        assert_eq!(ids, ["user-7004"]);
+
+        let result = fetch_mentioned_users_with("@7004 @7003", search).await;
+        assert!(matches!(result, Err(Error::Api(e)) if e.code == 500));
+    }
+}
@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    amount::Amount,
     auth::sign_authentication_token,
     error::Error,
     models::Invoice,
@@ -10,7 +11,7 @@ use crate::{
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct InvoiceRequest {
-    pub amount: String,
+    pub amount: Amount,
     pub asset_id: String,
     #[serde(default)]
     pub memo: Option<String>,
@@ -19,14 +20,14 @@ pub struct InvoiceRequest {
 }
 
 pub async fn create_invoice(
-    amount: &str,
+    amount: Amount,
     asset_id: &str,
     memo: Option<&str>,
     trace_id: Option<&str>,
     safe_user: &SafeUser,
 ) -> Result<Invoice, Error> {
     let data = InvoiceRequest {
-        amount: amount.to_string(),
+        amount,
         asset_id: asset_id.to_string(),
         memo: memo.map(|m| m.to_string()),
         trace_id: trace_id.map(|t| t.to_string()),
@@ -60,7 +61,7 @@ mod tests {
     #[test]
     fn test_invoice_request_serialization() {
         let request = InvoiceRequest {
-            amount: "1".to_string(),
+            amount: "1".parse().unwrap(),
             asset_id: "asset-id".to_string(),
             memo: Some("memo".to_string()),
             trace_id: Some("trace".to_string()),
@@ -1,20 +1,41 @@
 pub mod address;
+pub mod amount;
 pub mod asset;
+pub mod attachment;
 pub mod auth;
+pub mod blaze;
+pub mod bot;
+pub mod broadcast;
+pub mod chain;
+pub mod coin_selection;
 pub mod collectible;
+pub mod consolidation;
 pub mod conversation;
+pub mod crypto;
+pub mod deposit;
+pub mod encrypted_message;
 pub mod error;
+#[cfg(test)]
+mod fixtures;
+pub mod incoming_message;
 pub mod invoice;
 pub mod message;
+pub mod message_queue;
+pub mod mix_address;
 pub mod models;
 pub mod output;
+pub mod payment_stream;
 pub mod pin;
 pub mod request;
+pub mod reservation;
 pub mod safe;
+pub mod safe_multisig;
+pub mod safe_transaction;
 pub mod snapshot;
 pub mod tip;
 pub mod transaction;
 pub mod url_scheme;
 pub mod user;
 pub mod utils;
+pub mod wallet;
 pub mod withdrawal;
@@ -1,24 +1,768 @@
-use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use base64::{
+    Engine as _,
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use uuid::Uuid;
 
 use crate::{
     auth::sign_authentication_token,
+    conversation::ParticipantSession,
+    encrypted_message::{RecipientSession, encrypt_message_data, sessions_checksum},
     error::Error,
     request::{ApiResponse, request},
     safe::SafeUser,
 };
 
+pub const APP_CARD_TITLE_MAX_LENGTH: usize = 36;
+pub const APP_CARD_DESCRIPTION_MAX_LENGTH: usize = 128;
+/// How long after sending a message it can still be recalled.
+pub const MESSAGE_RECALL_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub enum MessageCategory {
+    #[default]
+    PlainText,
+    PlainPost,
+    PlainImage,
+    PlainAudio,
+    PlainVideo,
+    PlainData,
+    PlainSticker,
+    PlainContact,
+    PlainLocation,
+    PlainLive,
+    PlainTranscript,
+    AppCard,
+    AppButtonGroup,
+    MessageRecall,
+    MessagePin,
+    EncryptedText,
+    EncryptedPost,
+    EncryptedImage,
+    EncryptedAudio,
+    EncryptedVideo,
+    EncryptedData,
+    EncryptedSticker,
+    EncryptedContact,
+    EncryptedLocation,
+    EncryptedLive,
+    EncryptedTranscript,
+    SystemConversation,
+    SystemAccountSnapshot,
+    SystemSafeSnapshot,
+    /// Any category this SDK has no variant for, kept as sent.
+    Other(String),
+}
+
+impl MessageCategory {
+    pub fn as_str(&self) -> &str {
+        match self {
+            MessageCategory::PlainText => "PLAIN_TEXT",
+            MessageCategory::PlainPost => "PLAIN_POST",
+            MessageCategory::PlainImage => "PLAIN_IMAGE",
+            MessageCategory::PlainAudio => "PLAIN_AUDIO",
+            MessageCategory::PlainVideo => "PLAIN_VIDEO",
+            MessageCategory::PlainData => "PLAIN_DATA",
+            MessageCategory::PlainSticker => "PLAIN_STICKER",
+            MessageCategory::PlainContact => "PLAIN_CONTACT",
+            MessageCategory::PlainLocation => "PLAIN_LOCATION",
+            MessageCategory::PlainLive => "PLAIN_LIVE",
+            MessageCategory::PlainTranscript => "PLAIN_TRANSCRIPT",
+            MessageCategory::AppCard => "APP_CARD",
+            MessageCategory::AppButtonGroup => "APP_BUTTON_GROUP",
+            MessageCategory::MessageRecall => "MESSAGE_RECALL",
+            MessageCategory::MessagePin => "MESSAGE_PIN",
+            MessageCategory::EncryptedText => "ENCRYPTED_TEXT",
+            MessageCategory::EncryptedPost => "ENCRYPTED_POST",
+            MessageCategory::EncryptedImage => "ENCRYPTED_IMAGE",
+            MessageCategory::EncryptedAudio => "ENCRYPTED_AUDIO",
+            MessageCategory::EncryptedVideo => "ENCRYPTED_VIDEO",
+            MessageCategory::EncryptedData => "ENCRYPTED_DATA",
+            MessageCategory::EncryptedSticker => "ENCRYPTED_STICKER",
+            MessageCategory::EncryptedContact => "ENCRYPTED_CONTACT",
+            MessageCategory::EncryptedLocation => "ENCRYPTED_LOCATION",
+            MessageCategory::EncryptedLive => "ENCRYPTED_LIVE",
+            MessageCategory::EncryptedTranscript => "ENCRYPTED_TRANSCRIPT",
+            MessageCategory::SystemConversation => "SYSTEM_CONVERSATION",
+            MessageCategory::SystemAccountSnapshot => "SYSTEM_ACCOUNT_SNAPSHOT",
+            MessageCategory::SystemSafeSnapshot => "SYSTEM_SAFE_SNAPSHOT",
+            MessageCategory::Other(category) => category,
+        }
+    }
+
+    pub fn is_system(&self) -> bool {
+        self.as_str().starts_with("SYSTEM_")
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.as_str().starts_with("ENCRYPTED_")
+    }
+
+    /// The ENCRYPTED_* category of a PLAIN_* one, other categories have no
+    /// encrypted form.
+    pub fn to_encrypted(&self) -> Option<MessageCategory> {
+        Some(match self {
+            MessageCategory::PlainText => MessageCategory::EncryptedText,
+            MessageCategory::PlainPost => MessageCategory::EncryptedPost,
+            MessageCategory::PlainImage => MessageCategory::EncryptedImage,
+            MessageCategory::PlainAudio => MessageCategory::EncryptedAudio,
+            MessageCategory::PlainVideo => MessageCategory::EncryptedVideo,
+            MessageCategory::PlainData => MessageCategory::EncryptedData,
+            MessageCategory::PlainSticker => MessageCategory::EncryptedSticker,
+            MessageCategory::PlainContact => MessageCategory::EncryptedContact,
+            MessageCategory::PlainLocation => MessageCategory::EncryptedLocation,
+            MessageCategory::PlainLive => MessageCategory::EncryptedLive,
+            MessageCategory::PlainTranscript => MessageCategory::EncryptedTranscript,
+            _ => return None,
+        })
+    }
+
+    /// The PLAIN_* category an ENCRYPTED_* message decrypts to, other
+    /// categories are returned as they are.
+    pub fn to_plain(&self) -> MessageCategory {
+        match self {
+            MessageCategory::EncryptedText => MessageCategory::PlainText,
+            MessageCategory::EncryptedPost => MessageCategory::PlainPost,
+            MessageCategory::EncryptedImage => MessageCategory::PlainImage,
+            MessageCategory::EncryptedAudio => MessageCategory::PlainAudio,
+            MessageCategory::EncryptedVideo => MessageCategory::PlainVideo,
+            MessageCategory::EncryptedData => MessageCategory::PlainData,
+            MessageCategory::EncryptedSticker => MessageCategory::PlainSticker,
+            MessageCategory::EncryptedContact => MessageCategory::PlainContact,
+            MessageCategory::EncryptedLocation => MessageCategory::PlainLocation,
+            MessageCategory::EncryptedLive => MessageCategory::PlainLive,
+            MessageCategory::EncryptedTranscript => MessageCategory::PlainTranscript,
+            other => other.clone(),
+        }
+    }
+}
+
+impl fmt::Display for MessageCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<&str> for MessageCategory {
+    fn from(s: &str) -> Self {
+        match s {
+            "PLAIN_TEXT" => MessageCategory::PlainText,
+            "PLAIN_POST" => MessageCategory::PlainPost,
+            "PLAIN_IMAGE" => MessageCategory::PlainImage,
+            "PLAIN_AUDIO" => MessageCategory::PlainAudio,
+            "PLAIN_VIDEO" => MessageCategory::PlainVideo,
+            "PLAIN_DATA" => MessageCategory::PlainData,
+            "PLAIN_STICKER" => MessageCategory::PlainSticker,
+            "PLAIN_CONTACT" => MessageCategory::PlainContact,
+            "PLAIN_LOCATION" => MessageCategory::PlainLocation,
+            "PLAIN_LIVE" => MessageCategory::PlainLive,
+            "PLAIN_TRANSCRIPT" => MessageCategory::PlainTranscript,
+            "APP_CARD" => MessageCategory::AppCard,
+            "APP_BUTTON_GROUP" => MessageCategory::AppButtonGroup,
+            "MESSAGE_RECALL" => MessageCategory::MessageRecall,
+            "MESSAGE_PIN" => MessageCategory::MessagePin,
+            "ENCRYPTED_TEXT" => MessageCategory::EncryptedText,
+            "ENCRYPTED_POST" => MessageCategory::EncryptedPost,
+            "ENCRYPTED_IMAGE" => MessageCategory::EncryptedImage,
+            "ENCRYPTED_AUDIO" => MessageCategory::EncryptedAudio,
+            "ENCRYPTED_VIDEO" => MessageCategory::EncryptedVideo,
+            "ENCRYPTED_DATA" => MessageCategory::EncryptedData,
+            "ENCRYPTED_STICKER" => MessageCategory::EncryptedSticker,
+            "ENCRYPTED_CONTACT" => MessageCategory::EncryptedContact,
+            "ENCRYPTED_LOCATION" => MessageCategory::EncryptedLocation,
+            "ENCRYPTED_LIVE" => MessageCategory::EncryptedLive,
+            "ENCRYPTED_TRANSCRIPT" => MessageCategory::EncryptedTranscript,
+            "SYSTEM_CONVERSATION" => MessageCategory::SystemConversation,
+            "SYSTEM_ACCOUNT_SNAPSHOT" => MessageCategory::SystemAccountSnapshot,
+            "SYSTEM_SAFE_SNAPSHOT" => MessageCategory::SystemSafeSnapshot,
+            other => MessageCategory::Other(other.to_string()),
+        }
+    }
+}
+
+impl FromStr for MessageCategory {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self::from(s))
+    }
+}
+
+impl Serialize for MessageCategory {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for MessageCategory {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        Ok(Self::from(s.as_ref()))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ImageMessage {
+    pub attachment_id: String,
+    pub mime_type: String,
+    pub width: u32,
+    pub height: u32,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<String>,
+    /// Base64 key and digest of an encrypted attachment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AudioMessage {
+    pub attachment_id: String,
+    pub mime_type: String,
+    pub size: u64,
+    /// Milliseconds.
+    pub duration: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wave_form: Option<String>,
+    /// Base64 key and digest of an encrypted attachment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct VideoMessage {
+    pub attachment_id: String,
+    pub mime_type: String,
+    pub width: u32,
+    pub height: u32,
+    pub size: u64,
+    /// Milliseconds.
+    pub duration: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<String>,
+    /// Base64 key and digest of an encrypted attachment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DataMessage {
+    pub attachment_id: String,
+    pub mime_type: String,
+    pub size: u64,
+    pub name: String,
+    /// Base64 key and digest of an encrypted attachment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct StickerMessage {
+    pub sticker_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ContactMessage {
+    pub user_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LocationMessage {
+    pub latitude: f64,
+    pub longitude: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LiveMessage {
+    pub width: u32,
+    pub height: u32,
+    pub thumb_url: String,
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shareable: Option<bool>,
+}
+
+/// One message of a PLAIN_TRANSCRIPT bundle, media fields are set for
+/// attachment messages only.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct TranscriptMessage {
+    pub transcript_id: String,
+    pub message_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_full_name: Option<String>,
+    pub category: MessageCategory,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_height: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_mime_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_duration: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_digest: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumb_image: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sticker_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shared_user_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quote_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quote_content: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AppButton {
+    pub label: String,
+    pub action: String,
+    /// A "#RRGGBB" color.
+    pub color: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AppCardMessage {
+    pub app_id: String,
+    pub icon_url: String,
+    pub title: String,
+    pub description: String,
+    pub action: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cover_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actions: Option<Vec<AppButton>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shareable: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RecallMessage {
+    pub message_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum PinAction {
+    Pin,
+    Unpin,
+}
+
+/// Pins or unpins messages of a group conversation.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PinMessage {
+    pub action: PinAction,
+    pub message_ids: Vec<String>,
+}
+
+/// The typed data of a message. Text and posts are sent as UTF-8, every
+/// other payload as JSON.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MessagePayload {
+    Text(String),
+    /// Markdown.
+    Post(String),
+    Image(ImageMessage),
+    Audio(AudioMessage),
+    Video(VideoMessage),
+    Data(DataMessage),
+    Sticker(StickerMessage),
+    Contact(ContactMessage),
+    Location(LocationMessage),
+    Live(LiveMessage),
+    Transcript(Vec<TranscriptMessage>),
+    AppCard(AppCardMessage),
+    AppButtonGroup(Vec<AppButton>),
+    Recall(RecallMessage),
+    Pin(PinMessage),
+}
+
+impl MessagePayload {
+    pub fn category(&self) -> MessageCategory {
+        match self {
+            MessagePayload::Text(_) => MessageCategory::PlainText,
+            MessagePayload::Post(_) => MessageCategory::PlainPost,
+            MessagePayload::Image(_) => MessageCategory::PlainImage,
+            MessagePayload::Audio(_) => MessageCategory::PlainAudio,
+            MessagePayload::Video(_) => MessageCategory::PlainVideo,
+            MessagePayload::Data(_) => MessageCategory::PlainData,
+            MessagePayload::Sticker(_) => MessageCategory::PlainSticker,
+            MessagePayload::Contact(_) => MessageCategory::PlainContact,
+            MessagePayload::Location(_) => MessageCategory::PlainLocation,
+            MessagePayload::Live(_) => MessageCategory::PlainLive,
+            MessagePayload::Transcript(_) => MessageCategory::PlainTranscript,
+            MessagePayload::AppCard(_) => MessageCategory::AppCard,
+            MessagePayload::AppButtonGroup(_) => MessageCategory::AppButtonGroup,
+            MessagePayload::Recall(_) => MessageCategory::MessageRecall,
+            MessagePayload::Pin(_) => MessageCategory::MessagePin,
+        }
+    }
+
+    /// Checks the fields the API would reject, before anything is sent.
+    pub fn validate(&self) -> Result<(), Error> {
+        match self {
+            MessagePayload::Text(text) | MessagePayload::Post(text) => {
+                require("text", text)?;
+            }
+            MessagePayload::Image(image) => {
+                require("attachment_id", &image.attachment_id)?;
+                require("mime_type", &image.mime_type)?;
+                if image.width == 0 || image.height == 0 {
+                    return Err(Error::Input(
+                        "image width and height are required".to_string(),
+                    ));
+                }
+                require_key_pair(&image.key, &image.digest)?;
+            }
+            MessagePayload::Audio(audio) => {
+                require("attachment_id", &audio.attachment_id)?;
+                require("mime_type", &audio.mime_type)?;
+                require_key_pair(&audio.key, &audio.digest)?;
+            }
+            MessagePayload::Video(video) => {
+                require("attachment_id", &video.attachment_id)?;
+                require("mime_type", &video.mime_type)?;
+                require_key_pair(&video.key, &video.digest)?;
+            }
+            MessagePayload::Data(data) => {
+                require("attachment_id", &data.attachment_id)?;
+                require("name", &data.name)?;
+                require_key_pair(&data.key, &data.digest)?;
+            }
+            MessagePayload::Sticker(sticker) => require("sticker_id", &sticker.sticker_id)?,
+            MessagePayload::Contact(contact) => require_uuid("user_id", &contact.user_id)?,
+            MessagePayload::Location(location) => {
+                if !(-90.0..=90.0).contains(&location.latitude)
+                    || !(-180.0..=180.0).contains(&location.longitude)
+                {
+                    return Err(Error::Input(format!(
+                        "invalid location: {}, {}",
+                        location.latitude, location.longitude
+                    )));
+                }
+            }
+            MessagePayload::Live(live) => {
+                require("url", &live.url)?;
+                require("thumb_url", &live.thumb_url)?;
+            }
+            MessagePayload::Transcript(messages) => {
+                if messages.is_empty() {
+                    return Err(Error::Input("transcript is empty".to_string()));
+                }
+            }
+            MessagePayload::AppCard(card) => {
+                require_uuid("app_id", &card.app_id)?;
+                require("icon_url", &card.icon_url)?;
+                require("action", &card.action)?;
+                check_length("title", &card.title, APP_CARD_TITLE_MAX_LENGTH)?;
+                check_length(
+                    "description",
+                    &card.description,
+                    APP_CARD_DESCRIPTION_MAX_LENGTH,
+                )?;
+                for button in card.actions.iter().flatten() {
+                    validate_button(button)?;
+                }
+            }
+            MessagePayload::AppButtonGroup(buttons) => {
+                if buttons.is_empty() {
+                    return Err(Error::Input("button group is empty".to_string()));
+                }
+                for button in buttons {
+                    validate_button(button)?;
+                }
+            }
+            MessagePayload::Recall(recall) => require_uuid("message_id", &recall.message_id)?,
+            MessagePayload::Pin(pin) => {
+                if pin.message_ids.is_empty() {
+                    return Err(Error::Input("no message to pin".to_string()));
+                }
+                for message_id in &pin.message_ids {
+                    require_uuid("message_id", message_id)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The raw message data, before base64.
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        let data = match self {
+            MessagePayload::Text(text) | MessagePayload::Post(text) => {
+                return Ok(text.as_bytes().to_vec());
+            }
+            MessagePayload::Image(v) => serde_json::to_vec(v),
+            MessagePayload::Audio(v) => serde_json::to_vec(v),
+            MessagePayload::Video(v) => serde_json::to_vec(v),
+            MessagePayload::Data(v) => serde_json::to_vec(v),
+            MessagePayload::Sticker(v) => serde_json::to_vec(v),
+            MessagePayload::Contact(v) => serde_json::to_vec(v),
+            MessagePayload::Location(v) => serde_json::to_vec(v),
+            MessagePayload::Live(v) => serde_json::to_vec(v),
+            MessagePayload::Transcript(v) => serde_json::to_vec(v),
+            MessagePayload::AppCard(v) => serde_json::to_vec(v),
+            MessagePayload::AppButtonGroup(v) => serde_json::to_vec(v),
+            MessagePayload::Recall(v) => serde_json::to_vec(v),
+            MessagePayload::Pin(v) => serde_json::to_vec(v),
+        };
+        Ok(data?)
+    }
+
+    /// Decodes the raw data of a message, failing on categories without a
+    /// payload type such as system messages.
+    pub fn decode(category: &MessageCategory, data: &[u8]) -> Result<Self, Error> {
+        let text = || {
+            String::from_utf8(data.to_vec())
+                .map_err(|e| Error::Input(format!("invalid message text: {e}")))
+        };
+        Ok(match category {
+            MessageCategory::PlainText => MessagePayload::Text(text()?),
+            MessageCategory::PlainPost => MessagePayload::Post(text()?),
+            MessageCategory::PlainImage => MessagePayload::Image(serde_json::from_slice(data)?),
+            MessageCategory::PlainAudio => MessagePayload::Audio(serde_json::from_slice(data)?),
+            MessageCategory::PlainVideo => MessagePayload::Video(serde_json::from_slice(data)?),
+            MessageCategory::PlainData => MessagePayload::Data(serde_json::from_slice(data)?),
+            MessageCategory::PlainSticker => MessagePayload::Sticker(serde_json::from_slice(data)?),
+            MessageCategory::PlainContact => MessagePayload::Contact(serde_json::from_slice(data)?),
+            MessageCategory::PlainLocation => {
+                MessagePayload::Location(serde_json::from_slice(data)?)
+            }
+            MessageCategory::PlainLive => MessagePayload::Live(serde_json::from_slice(data)?),
+            MessageCategory::PlainTranscript => {
+                MessagePayload::Transcript(serde_json::from_slice(data)?)
+            }
+            MessageCategory::AppCard => MessagePayload::AppCard(serde_json::from_slice(data)?),
+            MessageCategory::AppButtonGroup => {
+                MessagePayload::AppButtonGroup(serde_json::from_slice(data)?)
+            }
+            MessageCategory::MessageRecall => MessagePayload::Recall(serde_json::from_slice(data)?),
+            MessageCategory::MessagePin => MessagePayload::Pin(serde_json::from_slice(data)?),
+            other => {
+                return Err(Error::Input(format!(
+                    "no payload type for message category {other}"
+                )));
+            }
+        })
+    }
+}
+
+fn require(field: &str, value: &str) -> Result<(), Error> {
+    if value.trim().is_empty() {
+        return Err(Error::Input(format!("{field} is required")));
+    }
+    Ok(())
+}
+
+// An encrypted attachment needs both its key and digest.
+fn require_key_pair(key: &Option<String>, digest: &Option<String>) -> Result<(), Error> {
+    if key.is_some() != digest.is_some() {
+        return Err(Error::Input(
+            "attachment key and digest go together".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn require_uuid(field: &str, value: &str) -> Result<(), Error> {
+    Uuid::parse_str(value)
+        .map(|_| ())
+        .map_err(|_| Error::Input(format!("invalid {field}: {value}")))
+}
+
+fn check_length(field: &str, value: &str, max: usize) -> Result<(), Error> {
+    let length = value.chars().count();
+    if length == 0 || length > max {
+        return Err(Error::Input(format!(
+            "{field} must have 1 to {max} characters, got {length}"
+        )));
+    }
+    Ok(())
+}
+
+fn validate_button(button: &AppButton) -> Result<(), Error> {
+    require("button label", &button.label)?;
+    require("button action", &button.action)?;
+    let valid_color = button.color.len() == 7
+        && button
+            .color
+            .strip_prefix('#')
+            .is_some_and(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()));
+    if !valid_color {
+        return Err(Error::Input(format!(
+            "invalid button color {}, expected #RRGGBB",
+            button.color
+        )));
+    }
+    Ok(())
+}
+
+/// Decodes `data_base64`, which senders encode with or without padding and
+/// in either base64 alphabet.
+pub fn decode_message_data(data_base64: &str) -> Result<Vec<u8>, Error> {
+    URL_SAFE_NO_PAD
+        .decode(data_base64.trim_end_matches('='))
+        .or_else(|_| STANDARD.decode(data_base64))
+        .map_err(|e| Error::Input(format!("invalid message data: {e}")))
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MessageRequest {
     pub conversation_id: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub recipient_id: Option<String>,
     pub message_id: String,
-    pub category: String,
+    pub category: MessageCategory,
     pub data_base64: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub representative_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub quote_message_id: Option<String>,
+    /// Set on ENCRYPTED_* messages, see `encrypted_message::sessions_checksum`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipient_sessions: Option<Vec<RecipientSession>>,
+}
+
+impl MessageRequest {
+    /// A validated request carrying `payload`.
+    pub fn new(
+        conversation_id: &str,
+        message_id: &str,
+        payload: &MessagePayload,
+    ) -> Result<Self, Error> {
+        payload.validate()?;
+        Ok(Self {
+            conversation_id: conversation_id.to_string(),
+            recipient_id: None,
+            message_id: message_id.to_string(),
+            category: payload.category(),
+            data_base64: STANDARD.encode(payload.encode()?),
+            representative_id: None,
+            quote_message_id: None,
+            checksum: None,
+            recipient_sessions: None,
+        })
+    }
+
+    /// A validated request carrying `payload` encrypted for `sessions`, the
+    /// sessions of every recipient in the conversation.
+    pub fn new_encrypted(
+        conversation_id: &str,
+        message_id: &str,
+        payload: &MessagePayload,
+        sessions: &[ParticipantSession],
+        safe_user: &SafeUser,
+    ) -> Result<Self, Error> {
+        payload.validate()?;
+        let category = payload
+            .category()
+            .to_encrypted()
+            .ok_or_else(|| Error::Input(format!("{} can not be encrypted", payload.category())))?;
+        Ok(Self {
+            conversation_id: conversation_id.to_string(),
+            recipient_id: None,
+            message_id: message_id.to_string(),
+            category,
+            data_base64: encrypt_message_data(&payload.encode()?, sessions, safe_user)?,
+            representative_id: None,
+            quote_message_id: None,
+            checksum: Some(sessions_checksum(sessions)),
+            recipient_sessions: Some(
+                sessions
+                    .iter()
+                    .filter_map(|s| s.session_id.clone())
+                    .map(|session_id| RecipientSession { session_id })
+                    .collect(),
+            ),
+        })
+    }
+
+    /// Recalls `recalled_message_id`, which we sent at `sent_at` (RFC 3339),
+    /// failing once `MESSAGE_RECALL_WINDOW` has passed.
+    pub fn recall(
+        conversation_id: &str,
+        message_id: &str,
+        recalled_message_id: &str,
+        sent_at: &str,
+    ) -> Result<Self, Error> {
+        let sent_at = DateTime::parse_from_rfc3339(sent_at)
+            .map_err(|e| Error::Input(format!("invalid sent_at {sent_at}: {e}")))?;
+        let age = Utc::now().signed_duration_since(sent_at);
+        if age.to_std().unwrap_or_default() > MESSAGE_RECALL_WINDOW {
+            return Err(Error::Input(format!(
+                "message {recalled_message_id} is too old to recall"
+            )));
+        }
+        let payload = MessagePayload::Recall(RecallMessage {
+            message_id: recalled_message_id.to_string(),
+        });
+        Self::new(conversation_id, message_id, &payload)
+    }
+
+    /// Pins or unpins `message_ids`, only group conversations have pins.
+    pub fn pin(
+        conversation_id: &str,
+        message_id: &str,
+        action: PinAction,
+        message_ids: &[String],
+    ) -> Result<Self, Error> {
+        let payload = MessagePayload::Pin(PinMessage {
+            action,
+            message_ids: message_ids.to_vec(),
+        });
+        Self::new(conversation_id, message_id, &payload)
+    }
+
+    pub fn with_recipient_id(mut self, recipient_id: &str) -> Self {
+        self.recipient_id = Some(recipient_id.to_string());
+        self
+    }
+
+    /// Shows the message as a reply to `quote_message_id`.
+    pub fn with_quote(mut self, quote_message_id: &str) -> Self {
+        self.quote_message_id = Some(quote_message_id.to_string());
+        self
+    }
+
+    pub fn payload(&self) -> Result<MessagePayload, Error> {
+        MessagePayload::decode(&self.category, &decode_message_data(&self.data_base64)?)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -70,10 +814,12 @@ mod tests {
             conversation_id: "conversation-id".to_string(),
             recipient_id: None,
             message_id: "message-id".to_string(),
-            category: "PLAIN_TEXT".to_string(),
+            category: MessageCategory::PlainText,
             data_base64: "SGVsbG8=".to_string(),
             representative_id: None,
             quote_message_id: Some("quote-id".to_string()),
+            checksum: None,
+            recipient_sessions: None,
         };
         let value: serde_json::Value =
             serde_json::from_str(&serde_json::to_string(&request).unwrap()).unwrap();
@@ -96,4 +842,100 @@ mod tests {
         assert_eq!(value["message_id"], "message-id");
         assert_eq!(value["status"], "READ");
     }
+
+    #[test]
+    fn test_message_category() {
+        assert_eq!(
+            "APP_CARD".parse::<MessageCategory>().unwrap(),
+            MessageCategory::AppCard
+        );
+        let other: MessageCategory = serde_json::from_str(r#""SIGNAL_TEXT""#).unwrap();
+        assert_eq!(other, MessageCategory::Other("SIGNAL_TEXT".to_string()));
+        assert_eq!(serde_json::to_string(&other).unwrap(), r#""SIGNAL_TEXT""#);
+
+        let encrypted = MessageCategory::PlainLocation.to_encrypted().unwrap();
+        assert_eq!(encrypted.as_str(), "ENCRYPTED_LOCATION");
+        assert!(encrypted.is_encrypted());
+        assert_eq!(encrypted.to_plain(), MessageCategory::PlainLocation);
+        assert!(MessageCategory::AppCard.to_encrypted().is_none());
+        assert!(MessageCategory::SystemConversation.is_system());
+        assert!(!MessageCategory::PlainText.is_system());
+    }
+
+    #[test]
+    fn test_message_payload_round_trip() {
+        let card = MessagePayload::AppCard(AppCardMessage {
+            app_id: "7766b24c-1a03-4c3a-83a3-b4358266875d".to_string(),
+            icon_url: "https://mixin.one/icon.png".to_string(),
+            title: "Title".to_string(),
+            description: "Description".to_string(),
+            action: "https://mixin.one".to_string(),
+            cover_url: None,
+            actions: Some(vec![AppButton {
+                label: "Open".to_string(),
+                action: "https://mixin.one".to_string(),
+                color: "#1A2b3C".to_string(),
+            }]),
+            shareable: Some(true),
+        });
+        for payload in [
+            MessagePayload::Text("héllo".to_string()),
+            MessagePayload::Location(LocationMessage {
+                latitude: 1.5,
+                longitude: -2.25,
+                name: None,
+                address: None,
+            }),
+            card.clone(),
+        ] {
+            let request = MessageRequest::new("conversation-id", "message-id", &payload).unwrap();
+            assert_eq!(request.category, payload.category());
+            assert_eq!(request.payload().unwrap(), payload);
+        }
+        let request =
+            MessageRequest::new("c", "m", &MessagePayload::Text("hi".to_string())).unwrap();
+        assert_eq!(request.data_base64, "aGk=");
+
+        let MessagePayload::AppCard(mut invalid) = card else {
+            unreachable!()
+        };
+        invalid.title = "t".repeat(APP_CARD_TITLE_MAX_LENGTH + 1);
+        assert!(MessagePayload::AppCard(invalid.clone()).validate().is_err());
+        invalid.title = "Title".to_string();
+        invalid.actions.as_mut().unwrap()[0].color = "red".to_string();
+        assert!(MessagePayload::AppCard(invalid).validate().is_err());
+        assert!(MessagePayload::Text(" ".to_string()).validate().is_err());
+        let recall = MessagePayload::Recall(RecallMessage {
+            message_id: "not-a-uuid".to_string(),
+        });
+        assert!(MessageRequest::new("c", "m", &recall).is_err());
+        assert!(MessagePayload::decode(&MessageCategory::SystemConversation, b"{}").is_err());
+    }
+
+    #[test]
+    fn test_recall_and_pin_requests() {
+        let target = "7766b24c-1a03-4c3a-83a3-b4358266875d";
+        let now = Utc::now();
+        let recall = MessageRequest::recall("c", "m", target, &now.to_rfc3339()).unwrap();
+        assert_eq!(recall.category, MessageCategory::MessageRecall);
+        assert_eq!(
+            recall.payload().unwrap(),
+            MessagePayload::Recall(RecallMessage {
+                message_id: target.to_string()
+            })
+        );
+        let expired = now - MESSAGE_RECALL_WINDOW - chrono::Duration::seconds(1);
+        assert!(MessageRequest::recall("c", "m", target, &expired.to_rfc3339()).is_err());
+        assert!(MessageRequest::recall("c", "m", target, "yesterday").is_err());
+
+        let pin = MessageRequest::pin("c", "m", PinAction::Unpin, &[target.to_string()])
+            .unwrap()
+            .with_quote("q");
+        assert_eq!(pin.quote_message_id.as_deref(), Some("q"));
+        let data = decode_message_data(&pin.data_base64).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&data).unwrap();
+        assert_eq!(value["action"], "UNPIN");
+        assert_eq!(value["message_ids"][0], target);
+        assert!(MessageRequest::pin("c", "m", PinAction::Pin, &[]).is_err());
+    }
 }
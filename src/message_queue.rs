@@ -0,0 +1,367 @@
+use std::collections::{HashSet, VecDeque};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::{
+    error::Error,
+    message::{MessageRequest, post_messages},
+    safe::SafeUser,
+};
+
+/// The most messages `POST /messages` accepts in one request.
+pub const MAX_MESSAGES_PER_REQUEST: usize = 100;
+
+/// How one queued message went.
+#[derive(Debug, Clone)]
+pub struct MessageOutcome {
+    pub message_id: String,
+    pub conversation_id: String,
+    pub recipient_id: Option<String>,
+    /// Requests that carried the message, retries and splits included.
+    pub attempts: u32,
+    /// Why the message was not sent, shared by the messages of a batch.
+    pub error: Option<Arc<Error>>,
+}
+
+impl MessageOutcome {
+    pub fn is_sent(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// An outbound queue for sending many messages.
+///
+/// `flush` sends the queued messages in batches of at most `batch_size`,
+/// spaced to stay under the rate limit. Failed batches are retried with the
+/// same message ids, which the API treats as duplicates of the messages it
+/// already has, so a retry can not send a message twice. A batch the API
+/// rejects for one of its messages is split until the failing messages are
+/// isolated, so every message gets its own outcome, while errors such as a
+/// rejected token fail the whole batch at once. Messages leave the queue once they have
+/// their outcome, so the ones a cancelled `flush` didn't finish stay queued.
+#[derive(Debug)]
+pub struct MessageQueue {
+    batch_size: usize,
+    max_attempts: u32,
+    retry_delay: Duration,
+    max_retry_delay: Duration,
+    rate_limit: Option<(usize, Duration)>,
+    pending: VecDeque<MessageRequest>,
+    pending_ids: HashSet<String>,
+}
+
+impl Default for MessageQueue {
+    fn default() -> Self {
+        Self {
+            batch_size: MAX_MESSAGES_PER_REQUEST,
+            max_attempts: 5,
+            retry_delay: Duration::from_millis(500),
+            max_retry_delay: Duration::from_secs(30),
+            rate_limit: None,
+            pending: VecDeque::new(),
+            pending_ids: HashSet::new(),
+        }
+    }
+}
+
+impl MessageQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.clamp(1, MAX_MESSAGES_PER_REQUEST);
+        self
+    }
+
+    /// Tries of a batch before its messages fail, the first one included.
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Delay before the first retry of a batch, doubled on every retry up to
+    /// `max`.
+    pub fn with_retry_delay(mut self, initial: Duration, max: Duration) -> Self {
+        self.retry_delay = initial;
+        self.max_retry_delay = max.max(initial);
+        self
+    }
+
+    /// Sends at most `messages` messages every `per`.
+    pub fn with_rate_limit(mut self, messages: usize, per: Duration) -> Self {
+        self.rate_limit = Some((messages.max(1), per));
+        self
+    }
+
+    /// Queues `message`, unless a message with its id is already queued.
+    pub fn push(&mut self, message: MessageRequest) -> bool {
+        if !self.pending_ids.insert(message.message_id.clone()) {
+            return false;
+        }
+        self.pending.push_back(message);
+        true
+    }
+
+    pub fn extend<I: IntoIterator<Item = MessageRequest>>(&mut self, messages: I) {
+        for message in messages {
+            self.push(message);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Sends every queued message, returning their outcomes in queue order.
+    pub async fn flush(&mut self, safe_user: &SafeUser) -> Vec<MessageOutcome> {
+        self.flush_with(|batch| async move { post_messages(&batch, safe_user).await })
+            .await
+    }
+
+    pub(crate) async fn flush_with<F, Fut>(&mut self, mut send: F) -> Vec<MessageOutcome>
+    where
+        F: FnMut(Vec<MessageRequest>) -> Fut,
+        Fut: Future<Output = Result<(), Error>>,
+    {
+        let messages: Vec<MessageRequest> = self.pending.iter().cloned().collect();
+        let mut outcomes: Vec<MessageOutcome> = messages
+            .iter()
+            .map(|m| MessageOutcome {
+                message_id: m.message_id.clone(),
+                conversation_id: m.conversation_id.clone(),
+                recipient_id: m.recipient_id.clone(),
+                attempts: 0,
+                error: None,
+            })
+            .collect();
+
+        // Batches are ranges of `messages`, splits go to the front so
+        // outcomes settle in queue order.
+        let mut batches: VecDeque<(usize, usize)> = (0..messages.len())
+            .step_by(self.batch_size)
+            .map(|start| (start, (start + self.batch_size).min(messages.len())))
+            .collect();
+        let mut next_send = Instant::now();
+        while let Some((start, end)) = batches.pop_front() {
+            let batch = &messages[start..end];
+            let mut attempts = 0;
+            let result = loop {
+                tokio::time::sleep_until(next_send).await;
+                if let Some((messages, per)) = self.rate_limit {
+                    next_send = Instant::now() + per.mul_f64(batch.len() as f64 / messages as f64);
+                }
+                attempts += 1;
+                match send(batch.to_vec()).await {
+                    Err(e) if is_retryable(&e) && attempts < self.max_attempts => {
+                        tokio::time::sleep(self.retry_delay_for(attempts)).await;
+                    }
+                    result => break result,
+                }
+            };
+            for outcome in &mut outcomes[start..end] {
+                outcome.attempts += attempts;
+            }
+            match result {
+                Ok(()) => {}
+                Err(e) if is_per_message(&e) && batch.len() > 1 => {
+                    let middle = start + batch.len() / 2;
+                    batches.push_front((middle, end));
+                    batches.push_front((start, middle));
+                    continue;
+                }
+                Err(e) => {
+                    let error = Arc::new(e);
+                    for outcome in &mut outcomes[start..end] {
+                        outcome.error = Some(error.clone());
+                    }
+                }
+            }
+            // Batches settle in queue order, so they are at the front.
+            for message in self.pending.drain(..end - start) {
+                self.pending_ids.remove(&message.message_id);
+            }
+        }
+        outcomes
+    }
+
+    fn retry_delay_for(&self, attempts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+        self.retry_delay
+            .saturating_mul(factor)
+            .min(self.max_retry_delay)
+    }
+}
+
+// Network failures, server errors and rate limiting are worth retrying,
+// anything else is about the messages themselves.
+fn is_retryable(error: &Error) -> bool {
+    match error {
+        Error::Request(_) | Error::Server(_) => true,
+        Error::Api(e) => e.status == 429 || e.code == 429 || e.status >= 500,
+        _ => false,
+    }
+}
+
+// Errors a single message of a batch can cause, e.g. a recipient who blocked
+// the bot or invalid data, so the batch is split to find it. Others, like a
+// rejected token, fail every message alike.
+fn is_per_message(error: &Error) -> bool {
+    match error {
+        Error::Api(e) => e.code == 403 || e.code == 10002,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::MessagePayload;
+    use crate::request::ApiError;
+    use std::cell::RefCell;
+
+    fn message(i: usize) -> MessageRequest {
+        MessageRequest::new(
+            "conversation-id",
+            &format!("message-{i}"),
+            &MessagePayload::Text(format!("hello {i}")),
+        )
+        .unwrap()
+        .with_recipient_id(&format!("user-{i}"))
+    }
+
+    fn api_error(status: i32, code: i32) -> Error {
+        Error::Api(ApiError {
+            status,
+            code,
+            description: String::new(),
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_message_queue_batches_and_retries() {
+        let mut queue = MessageQueue::new()
+            .with_batch_size(3)
+            .with_retry_delay(Duration::from_secs(5), Duration::from_secs(5))
+            .with_rate_limit(6, Duration::from_secs(6));
+        queue.extend((0..7).map(message));
+        assert!(!queue.push(message(3)));
+        assert_eq!(queue.len(), 7);
+
+        let sent = RefCell::new(Vec::new());
+        let started = Instant::now();
+        let outcomes = queue
+            .flush_with(|batch| {
+                let ids: Vec<String> = batch.iter().map(|m| m.message_id.clone()).collect();
+                let first_try = !sent.borrow().contains(&ids);
+                sent.borrow_mut().push(ids);
+                async move {
+                    if batch.len() == 3 && batch[0].message_id == "message-3" && first_try {
+                        return Err(api_error(500, 500));
+                    }
+                    Ok(())
+                }
+            })
+            .await;
+
+        // Batches of 3 are 3 seconds apart, the retry waits 5 seconds.
+        assert_eq!(started.elapsed(), Duration::from_secs(11));
+        assert!(queue.is_empty());
+        let sent = sent.into_inner();
+        assert_eq!(sent.len(), 4);
+        assert_eq!(sent[1], sent[2]);
+        assert_eq!(sent[3], ["message-6"]);
+        assert!(outcomes.iter().all(MessageOutcome::is_sent));
+        let attempts: Vec<u32> = outcomes.iter().map(|o| o.attempts).collect();
+        assert_eq!(attempts, [1, 1, 1, 2, 2, 2, 1]);
+        assert_eq!(outcomes[6].recipient_id.as_deref(), Some("user-6"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_message_queue_outcomes() {
+        let mut queue = MessageQueue::new()
+            .with_batch_size(2)
+            .with_max_attempts(2)
+            .with_retry_delay(Duration::from_millis(10), Duration::from_secs(1));
+        queue.extend((0..5).map(message));
+        let outcomes = queue
+            .flush_with(|batch| async move {
+                if batch.iter().any(|m| m.message_id == "message-1") {
+                    return Err(api_error(202, 10002));
+                }
+                if batch.iter().any(|m| m.message_id == "message-4") {
+                    return Err(api_error(202, 429));
+                }
+                Ok(())
+            })
+            .await;
+
+        let sent: Vec<bool> = outcomes.iter().map(MessageOutcome::is_sent).collect();
+        assert_eq!(sent, [true, false, true, true, false]);
+        assert!(matches!(
+            outcomes[1].error.as_deref(),
+            Some(Error::Api(ApiError { code: 10002, .. }))
+        ));
+        assert!(matches!(
+            outcomes[4].error.as_deref(),
+            Some(Error::Api(ApiError { code: 429, .. }))
+        ));
+        let attempts: Vec<u32> = outcomes.iter().map(|o| o.attempts).collect();
+        assert_eq!(attempts, [2, 2, 1, 1, 2]);
+
+        assert!(is_retryable(&Error::Server("timeout".to_string())));
+        assert!(is_per_message(&api_error(202, 403)));
+        assert!(!is_retryable(&Error::Input("bad".to_string())));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_message_queue_fails_batch_on_request_errors() {
+        let mut queue = MessageQueue::new().with_batch_size(2);
+        queue.extend((0..4).map(message));
+        let sent = RefCell::new(0);
+        let outcomes = queue
+            .flush_with(|batch| {
+                *sent.borrow_mut() += 1;
+                async move {
+                    if batch[0].message_id == "message-0" {
+                        return Err(api_error(401, 401));
+                    }
+                    Ok(())
+                }
+            })
+            .await;
+
+        // The rejected batch is not split, each batch is sent once.
+        assert_eq!(sent.into_inner(), 2);
+        let sent: Vec<bool> = outcomes.iter().map(MessageOutcome::is_sent).collect();
+        assert_eq!(sent, [false, false, true, true]);
+        assert!(queue.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_message_queue_cancelled_flush() {
+        let mut queue = MessageQueue::new()
+            .with_batch_size(2)
+            .with_rate_limit(2, Duration::from_secs(10));
+        queue.extend((0..5).map(message));
+
+        let flush = queue.flush_with(|_| async { Ok(()) });
+        let cancelled = tokio::time::timeout(Duration::from_secs(15), flush).await;
+        assert!(cancelled.is_err());
+        assert_eq!(queue.len(), 1);
+        assert!(!queue.push(message(4)));
+        assert!(queue.push(message(0)));
+
+        let outcomes = queue.flush_with(|_| async { Ok(()) }).await;
+        let ids: Vec<&str> = outcomes.iter().map(|o| o.message_id.as_str()).collect();
+        assert_eq!(ids, ["message-4", "message-0"]);
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,266 @@
+use std::fmt;
+use std::str::FromStr;
+
+use uuid::Uuid;
+
+use crate::{
+    crypto::{Address, MAIN_NETWORK_ID, sha3_256},
+    error::Error,
+};
+
+pub const MIX_ADDRESS_PREFIX: &str = "MIX";
+pub const MIX_ADDRESS_VERSION: u8 = 2;
+pub const MIX_ADDRESS_MAX_MEMBERS: usize = 64;
+
+/// Receivers of a Safe output: either Mixin user ids or main-net addresses,
+/// never both, plus the threshold of signatures needed to spend it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MixAddress {
+    pub version: u8,
+    pub threshold: u8,
+    pub uuid_members: Vec<String>,
+    pub xin_members: Vec<Address>,
+}
+
+impl MixAddress {
+    pub fn new_uuid<T: AsRef<str>>(members: &[T], threshold: u8) -> Result<Self, Error> {
+        validate_members(members.len(), threshold)?;
+        let uuid_members = members
+            .iter()
+            .map(|m| {
+                Uuid::parse_str(m.as_ref())
+                    .map(|u| u.to_string())
+                    .map_err(|e| Error::Input(format!("invalid uuid member {}: {e}", m.as_ref())))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            version: MIX_ADDRESS_VERSION,
+            threshold,
+            uuid_members,
+            xin_members: Vec::new(),
+        })
+    }
+
+    pub fn new_mainnet(members: &[Address], threshold: u8) -> Result<Self, Error> {
+        validate_members(members.len(), threshold)?;
+        Ok(Self {
+            version: MIX_ADDRESS_VERSION,
+            threshold,
+            uuid_members: Vec::new(),
+            xin_members: members.to_vec(),
+        })
+    }
+
+    /// Builds the address from members given as strings, all user ids or all
+    /// "XIN" main-net addresses.
+    pub fn from_members<T: AsRef<str>>(members: &[T], threshold: u8) -> Result<Self, Error> {
+        if members
+            .first()
+            .is_some_and(|m| m.as_ref().starts_with(MAIN_NETWORK_ID))
+        {
+            let addresses = members
+                .iter()
+                .map(|m| Address::from_string(m.as_ref()))
+                .collect::<Result<Vec<_>, _>>()?;
+            Self::new_mainnet(&addresses, threshold)
+        } else {
+            Self::new_uuid(members, threshold)
+        }
+    }
+
+    pub fn from_string(s: &str) -> Result<Self, Error> {
+        let encoded = s
+            .strip_prefix(MIX_ADDRESS_PREFIX)
+            .ok_or_else(|| Error::Input(format!("invalid mix address prefix: {s}")))?;
+        let data = bs58::decode(encoded)
+            .into_vec()
+            .map_err(|e| Error::Input(format!("invalid mix address base58: {e}")))?;
+        if data.len() < 3 + 16 + 4 {
+            return Err(Error::Input(format!(
+                "invalid mix address length: {}",
+                data.len()
+            )));
+        }
+        let (payload, checksum) = data.split_at(data.len() - 4);
+        if mix_checksum(payload) != checksum {
+            return Err(Error::Input("invalid mix address checksum".to_string()));
+        }
+
+        let (version, threshold, total) = (payload[0], payload[1], payload[2] as usize);
+        if version != MIX_ADDRESS_VERSION {
+            return Err(Error::Input(format!(
+                "invalid mix address version: {version}"
+            )));
+        }
+        validate_members(total, threshold)?;
+
+        let members = &payload[3..];
+        let mut address = Self {
+            version,
+            threshold,
+            uuid_members: Vec::new(),
+            xin_members: Vec::new(),
+        };
+        if members.len() == total * 16 {
+            for chunk in members.chunks_exact(16) {
+                let uuid = Uuid::from_slice(chunk)
+                    .map_err(|e| Error::Input(format!("invalid uuid member: {e}")))?;
+                address.uuid_members.push(uuid.to_string());
+            }
+        } else if members.len() == total * 64 {
+            for chunk in members.chunks_exact(64) {
+                address.xin_members.push(Address::from_public_bytes(chunk)?);
+            }
+        } else {
+            return Err(Error::Input(
+                "invalid mix address members length".to_string(),
+            ));
+        }
+        Ok(address)
+    }
+
+    /// The members as strings, user ids or "XIN" addresses, sorted.
+    pub fn members(&self) -> Vec<String> {
+        let mut members: Vec<String> = if self.uuid_members.is_empty() {
+            self.xin_members.iter().map(Address::to_string).collect()
+        } else {
+            self.uuid_members.clone()
+        };
+        members.sort();
+        members
+    }
+
+    fn payload(&self) -> Vec<u8> {
+        let total = self.uuid_members.len() + self.xin_members.len();
+        let mut payload = Vec::with_capacity(3 + total * 64);
+        payload.push(self.version);
+        payload.push(self.threshold);
+        payload.push(total as u8);
+        for member in &self.uuid_members {
+            // Members are validated on construction, a bad one can only come
+            // from editing the public fields and is encoded as nil.
+            payload.extend_from_slice(Uuid::parse_str(member).unwrap_or_default().as_bytes());
+        }
+        for member in &self.xin_members {
+            payload.extend_from_slice(&member.public_bytes());
+        }
+        payload
+    }
+}
+
+impl fmt::Display for MixAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut data = self.payload();
+        data.extend_from_slice(&mix_checksum(&data));
+        write!(
+            f,
+            "{MIX_ADDRESS_PREFIX}{}",
+            bs58::encode(data).into_string()
+        )
+    }
+}
+
+impl FromStr for MixAddress {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_string(s)
+    }
+}
+
+fn mix_checksum(payload: &[u8]) -> [u8; 4] {
+    let mut data = MIX_ADDRESS_PREFIX.as_bytes().to_vec();
+    data.extend_from_slice(payload);
+    let hash = sha3_256(&data);
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+fn validate_members(count: usize, threshold: u8) -> Result<(), Error> {
+    if count == 0 || count > MIX_ADDRESS_MAX_MEMBERS {
+        return Err(Error::Input(format!(
+            "invalid mix address members count: {count}"
+        )));
+    }
+    if threshold == 0 || threshold as usize > count {
+        return Err(Error::Input(format!(
+            "invalid mix address threshold: {threshold}/{count}"
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MEMBER: &str = "67a87828-18f5-46a1-b6cc-c72a97a77c43";
+
+    #[test]
+    fn test_uuid_mix_address() {
+        let address = MixAddress::new_uuid(&[MEMBER], 1).unwrap();
+        assert_eq!(address.to_string(), "MIX3QEeg1WkLrjvjxyMQf6Xc8dxs81tpPc");
+
+        let parsed = MixAddress::from_string("MIX3QEeg1WkLrjvjxyMQf6Xc8dxs81tpPc").unwrap();
+        assert_eq!(parsed, address);
+        assert_eq!(parsed.members(), [MEMBER]);
+
+        let members = [
+            MEMBER,
+            "c94ac88f-4671-3976-b60a-09064f1811e8",
+            "c6d0c728-2624-429b-8e0d-d9d19b6592fa",
+            MEMBER,
+            "c94ac88f-4671-3976-b60a-09064f1811e8",
+            "c6d0c728-2624-429b-8e0d-d9d19b6592fa",
+            MEMBER,
+        ];
+        let address = MixAddress::new_uuid(&members, 4).unwrap();
+        let encoded = "MIX4fwusRK88p5GexHWddUQuYJbKMJTAuBvhudgahRXKndvaM8FdPHS2Hgeo7DQxNVoSkKSEDyZeD8TYBhiwiea9PvCzay1A9Vx1C2nugc4iAmhwLGGv4h3GnABeCXHTwWEto9wEe1MWB49jLzy3nuoM81tqE2XnLvUWv";
+        assert_eq!(address.to_string(), encoded);
+        assert_eq!(encoded.parse::<MixAddress>().unwrap(), address);
+
+        assert!(MixAddress::new_uuid(&[MEMBER], 2).is_err());
+        assert!(MixAddress::new_uuid(&["not-a-uuid"], 1).is_err());
+    }
+
+    #[test]
+    fn test_mainnet_mix_address() {
+        let xin = "XIN3BMNy9pQyj5XWDJtTbaBVE2zQ66zBo2weyc43iL286asdqwApWswAzQC5qba26fh3fzHK9iMoxyx1q3Lgj45KJftzGD9q";
+        let address = MixAddress::from_members(&[xin], 1).unwrap();
+        let encoded = "MIXPYWwhjxKsbFRzAP2Dcb2mMjj7sQQo4MpCSv3NYaYCdQ2kEcbcimpPT81gaxtuNhunLWPx7Sv7fawjZ8DhRmEj8E2hrQM4Z6e";
+        assert_eq!(address.to_string(), encoded);
+
+        let parsed = MixAddress::from_string(encoded).unwrap();
+        assert_eq!(parsed.members(), [xin]);
+        assert_eq!(parsed.threshold, 1);
+    }
+
+    #[test]
+    fn test_mix_address_validation() {
+        // A threshold of 64 with one member can never be reached.
+        assert!(
+            MixAddress::from_string(
+                "MIXSK624cFT3CXbbjYxU17CeYWCwj6CZgkp2VsfiRsDMXw4MzpfYKPKKYwLmfDby2z85MLAbSWZbAB1dfPetCxUf7vwwJnToaG8",
+            )
+            .is_err()
+        );
+
+        let mut address = MixAddress::new_uuid(&[MEMBER, MEMBER], 2).unwrap();
+        address.threshold = 3;
+        assert!(MixAddress::from_string(&address.to_string()).is_err());
+
+        let members = vec![MEMBER; MIX_ADDRESS_MAX_MEMBERS];
+        let mut address = MixAddress::new_uuid(&members, 1).unwrap();
+        assert_eq!(
+            MixAddress::from_string(&address.to_string()).unwrap(),
+            address
+        );
+        assert!(MixAddress::new_uuid(&[MEMBER; MIX_ADDRESS_MAX_MEMBERS + 1], 1).is_err());
+        address.uuid_members.push(MEMBER.to_string());
+        assert!(MixAddress::from_string(&address.to_string()).is_err());
+
+        let mut broken = "MIX3QEeg1WkLrjvjxyMQf6Xc8dxs81tpPc".to_string();
+        broken.replace_range(5..6, "F");
+        assert!(MixAddress::from_string(&broken).is_err());
+        assert!(MixAddress::from_string("XIN3QEeg1WkLrjvjxyMQf6Xc8dxs81tpPc").is_err());
+    }
+}
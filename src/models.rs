@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::amount::Amount;
+
 #[derive(Debug, Deserialize, Serialize, Default)]
 pub struct User {
     pub user_id: String,
@@ -42,8 +44,8 @@ pub struct Asset {
     pub symbol: Option<String>,
     #[serde(default)]
     pub name: Option<String>,
-    #[serde(default)]
-    pub balance: Option<String>,
+    #[serde(default, deserialize_with = "crate::amount::deserialize_optional")]
+    pub balance: Option<Amount>,
     #[serde(default)]
     pub destination: Option<String>,
     #[serde(default)]
@@ -72,12 +74,14 @@ pub struct Asset {
     pub display_name: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Default)]
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
 pub struct Output {
     #[serde(rename = "type")]
     pub output_type: Option<String>,
     pub output_id: String,
     #[serde(default)]
+    pub request_id: Option<String>,
+    #[serde(default)]
     pub transaction_hash: Option<String>,
     #[serde(default)]
     pub output_index: Option<u32>,
@@ -85,8 +89,8 @@ pub struct Output {
     pub asset_id: Option<String>,
     #[serde(default)]
     pub kernel_asset_id: Option<String>,
-    #[serde(default)]
-    pub amount: Option<String>,
+    #[serde(default, deserialize_with = "crate::amount::deserialize_optional")]
+    pub amount: Option<Amount>,
     #[serde(default)]
     pub mask: Option<String>,
     #[serde(default)]
@@ -126,8 +130,8 @@ pub struct Snapshot {
     pub type_name: Option<String>,
     #[serde(default)]
     pub asset_id: Option<String>,
-    #[serde(default)]
-    pub amount: Option<String>,
+    #[serde(default, deserialize_with = "crate::amount::deserialize_optional")]
+    pub amount: Option<Amount>,
     #[serde(default)]
     pub created_at: Option<String>,
     #[serde(default)]
@@ -145,8 +149,8 @@ pub struct Invoice {
     pub invoice_id: String,
     #[serde(default)]
     pub payment_code: Option<String>,
-    #[serde(default)]
-    pub amount: Option<String>,
+    #[serde(default, deserialize_with = "crate::amount::deserialize_optional")]
+    pub amount: Option<Amount>,
     #[serde(default)]
     pub memo: Option<String>,
     #[serde(default)]
@@ -266,8 +270,13 @@ mod tests {
         let asset: Asset = serde_json::from_str(raw).expect("asset");
         assert_eq!(asset.asset_id, "asset-id");
         assert_eq!(asset.symbol.as_deref(), Some("BTC"));
-        assert_eq!(asset.balance.as_deref(), Some("1.2345"));
+        assert_eq!(asset.balance, Some("1.2345".parse().unwrap()));
         assert_eq!(asset.confirmations, Some(6));
+
+        let asset: Asset =
+            serde_json::from_str(r#"{"asset_id":"asset-id","balance":""}"#).expect("asset");
+        assert_eq!(asset.balance, None);
+        assert!(serde_json::from_str::<Asset>(r#"{"asset_id":"a","balance":"x"}"#).is_err());
     }
 
     #[test]
@@ -319,7 +328,7 @@ mod tests {
         let snapshot: Snapshot = serde_json::from_str(raw).expect("snapshot");
         assert_eq!(snapshot.snapshot_id, "snapshot-id");
         assert_eq!(snapshot.asset_id.as_deref(), Some("asset-id"));
-        assert_eq!(snapshot.amount.as_deref(), Some("1.23"));
+        assert_eq!(snapshot.amount, Some("1.23".parse().unwrap()));
     }
 
     #[test]
@@ -10,8 +10,11 @@ use crate::{
 };
 
 pub const OUTPUT_STATE_UNSPENT: &str = "unspent";
+pub const OUTPUT_STATE_SIGNED: &str = "signed";
 pub const OUTPUT_STATE_SPENT: &str = "spent";
 
+const OUTPUTS_PAGE_LIMIT: i64 = 500;
+
 #[derive(Debug, Deserialize, Serialize, Default, Clone)]
 pub struct KernelDeposit {
     pub chain: Option<String>,
@@ -77,6 +80,53 @@ pub async fn list_unspent_outputs(
     .await
 }
 
+/// Every unspent output, read page by page following their sequence, unlike
+/// `list_unspent_outputs` which stops at the oldest 500.
+pub async fn list_all_unspent_outputs(
+    members_hash: &str,
+    threshold: u8,
+    asset_id: Option<&str>,
+    safe_user: &SafeUser,
+) -> Result<Vec<Output>, Error> {
+    list_all_outputs(
+        members_hash,
+        threshold,
+        asset_id,
+        Some(OUTPUT_STATE_UNSPENT),
+        safe_user,
+    )
+    .await
+}
+
+/// Every output in `state`, read page by page following their sequence.
+pub async fn list_all_outputs(
+    members_hash: &str,
+    threshold: u8,
+    asset_id: Option<&str>,
+    state: Option<&str>,
+    safe_user: &SafeUser,
+) -> Result<Vec<Output>, Error> {
+    let mut outputs: Vec<Output> = Vec::new();
+    loop {
+        let offset = outputs.last().and_then(|o| o.sequence).map(|s| s + 1);
+        let page = list_outputs(
+            members_hash,
+            threshold,
+            asset_id,
+            state,
+            offset,
+            Some(OUTPUTS_PAGE_LIMIT),
+            safe_user,
+        )
+        .await?;
+        let done = (page.len() as i64) < OUTPUTS_PAGE_LIMIT;
+        outputs.extend(page);
+        if done || outputs.last().and_then(|o| o.sequence).is_none() {
+            return Ok(outputs);
+        }
+    }
+}
+
 pub async fn get_output(output_id: &str, safe_user: &SafeUser) -> Result<Output, Error> {
     let path = format!("/safe/outputs/{output_id}");
     let token = sign_authentication_token("GET", &path, "", safe_user)?;
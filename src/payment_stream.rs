@@ -0,0 +1,474 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::Duration;
+
+use futures_util::{Stream, stream};
+
+use crate::{
+    amount::Amount,
+    error::Error,
+    models::Output,
+    output::{OUTPUT_STATE_UNSPENT, list_outputs},
+    safe::SafeUser,
+    snapshot::{SafeSnapshotQuery, list_safe_snapshots},
+    utils::hash_members,
+};
+
+const OUTPUTS_PAGE_LIMIT: i64 = 500;
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_ERROR_BACKOFF: Duration = Duration::from_secs(60);
+const SNAPSHOTS_PAGE_LIMIT: u32 = 500;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncomingPayment {
+    pub output_id: String,
+    pub sequence: i64,
+    pub asset_id: String,
+    pub amount: Amount,
+    pub senders: Vec<String>,
+    /// The transaction extra as text, or hex when it isn't valid UTF-8.
+    pub memo: String,
+    pub extra: Vec<u8>,
+    /// The request id the sender submitted the transaction with.
+    pub trace_id: Option<String>,
+    pub transaction_hash: String,
+    pub output_index: u32,
+    /// The snapshot of the payment, found by `next` from its transaction
+    /// hash and output index. `None` while the snapshot isn't listed yet.
+    pub snapshot_id: Option<String>,
+    pub created_at: Option<String>,
+}
+
+impl IncomingPayment {
+    pub fn from_output(output: &Output) -> Result<Self, Error> {
+        let sequence = output
+            .sequence
+            .ok_or_else(|| Error::Input(format!("output {} has no sequence", output.output_id)))?;
+        let extra = hex::decode(output.extra.as_deref().unwrap_or_default()).unwrap_or_else(|_| {
+            output
+                .extra
+                .as_deref()
+                .unwrap_or_default()
+                .as_bytes()
+                .to_vec()
+        });
+        let memo = match std::str::from_utf8(&extra) {
+            Ok(memo) => memo.to_string(),
+            Err(_) => hex::encode(&extra),
+        };
+        Ok(Self {
+            output_id: output.output_id.clone(),
+            sequence,
+            asset_id: output.asset_id.clone().unwrap_or_default(),
+            amount: output.amount.unwrap_or_default(),
+            senders: output.senders.clone().unwrap_or_default(),
+            memo,
+            extra,
+            trace_id: output.request_id.clone().filter(|r| !r.is_empty()),
+            transaction_hash: output.transaction_hash.clone().unwrap_or_default(),
+            output_index: output.output_index.unwrap_or_default(),
+            snapshot_id: None,
+            created_at: output.created_at.clone(),
+        })
+    }
+}
+
+/// Persists the sequence of the last payment the caller finished handling.
+pub trait CursorStore: Send + Sync {
+    fn load(&self) -> Result<i64, Error>;
+    fn save(&self, sequence: i64) -> Result<(), Error>;
+
+    /// Marks `payment` and every payment before it as handled.
+    fn commit(&self, payment: &IncomingPayment) -> Result<(), Error> {
+        if payment.sequence > self.load()? {
+            self.save(payment.sequence)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryCursorStore {
+    sequence: AtomicI64,
+}
+
+impl MemoryCursorStore {
+    pub fn new(sequence: i64) -> Self {
+        Self {
+            sequence: AtomicI64::new(sequence),
+        }
+    }
+}
+
+impl CursorStore for MemoryCursorStore {
+    fn load(&self) -> Result<i64, Error> {
+        Ok(self.sequence.load(Ordering::SeqCst))
+    }
+
+    fn save(&self, sequence: i64) -> Result<(), Error> {
+        self.sequence.store(sequence, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+/// Keeps the cursor as a number in a file, written through a temporary file
+/// and a rename so a crash never leaves it half written.
+#[derive(Debug)]
+pub struct FileCursorStore {
+    path: PathBuf,
+}
+
+impl FileCursorStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl CursorStore for FileCursorStore {
+    fn load(&self) -> Result<i64, Error> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(data) => data
+                .trim()
+                .parse()
+                .map_err(|_| Error::Input(format!("invalid cursor file: {}", self.path.display()))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(Error::Storage(format!("cursor file: {e}"))),
+        }
+    }
+
+    fn save(&self, sequence: i64) -> Result<(), Error> {
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, sequence.to_string())
+            .and_then(|_| std::fs::rename(&tmp, &self.path))
+            .map_err(|e| Error::Storage(format!("cursor file: {e}")))
+    }
+}
+
+/// Incoming payments read by following the unspent outputs of the user by
+/// sequence, starting after the committed cursor.
+///
+/// Delivery is at least once: `next` moves a read position kept in memory and
+/// only `commit` persists it, so whatever wasn't committed before a restart is
+/// delivered again. While reading through `stream`, commit with the store
+/// returned by `cursor`. Outputs spent before the stream reads them are not seen,
+/// so the stream should run ahead of anything spending them.
+pub struct PaymentStream<C = MemoryCursorStore> {
+    cursor: Arc<C>,
+    members_hash: String,
+    user_id: String,
+    asset_id: Option<String>,
+    include_self: bool,
+    poll_interval: Duration,
+    position: Option<i64>,
+    pending: VecDeque<IncomingPayment>,
+}
+
+impl<C: CursorStore> PaymentStream<C> {
+    pub fn new(cursor: C, user_id: &str) -> Self {
+        Self {
+            cursor: Arc::new(cursor),
+            members_hash: hash_members([user_id]),
+            user_id: user_id.to_string(),
+            asset_id: None,
+            include_self: false,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            position: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn with_asset(mut self, asset_id: &str) -> Self {
+        self.asset_id = Some(asset_id.to_string());
+        self
+    }
+
+    /// Also reports outputs sent by the user alone, e.g. change and consolidation.
+    pub fn with_self_payments(mut self, include_self: bool) -> Self {
+        self.include_self = include_self;
+        self
+    }
+
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Waits for the next payment, polling `/safe/outputs` while there is none.
+    pub async fn next(&mut self, safe_user: &SafeUser) -> Result<IncomingPayment, Error> {
+        loop {
+            if let Some(payment) = self.pending.front_mut() {
+                if payment.snapshot_id.is_none() {
+                    payment.snapshot_id = find_snapshot_id(payment, safe_user).await?;
+                }
+                return Ok(self.pending.pop_front().unwrap());
+            }
+            let position = self.position()?;
+            let page = list_outputs(
+                &self.members_hash,
+                1,
+                self.asset_id.as_deref(),
+                Some(OUTPUT_STATE_UNSPENT),
+                Some(position + 1),
+                Some(OUTPUTS_PAGE_LIMIT),
+                safe_user,
+            )
+            .await?;
+            let full = page.len() as i64 == OUTPUTS_PAGE_LIMIT;
+            self.push_outputs(page)?;
+            if self.pending.is_empty() && !full {
+                tokio::time::sleep(self.poll_interval).await;
+            }
+        }
+    }
+
+    /// The payments `next` returns, one after the other. Errors are yielded
+    /// without ending the stream, the next poll tries again after waiting the
+    /// poll interval, doubled for every error in a row up to a minute.
+    pub fn stream<'a>(
+        &'a mut self,
+        safe_user: &'a SafeUser,
+    ) -> impl Stream<Item = Result<IncomingPayment, Error>> + 'a {
+        stream::unfold((self, 0u32), move |(payments, errors)| async move {
+            if errors > 0 {
+                tokio::time::sleep(error_backoff(payments.poll_interval, errors)).await;
+            }
+            let payment = payments.next(safe_user).await;
+            let errors = if payment.is_ok() { 0 } else { errors + 1 };
+            Some((payment, (payments, errors)))
+        })
+    }
+
+    /// The cursor store, to commit payments while `stream` borrows the stream.
+    pub fn cursor(&self) -> Arc<C> {
+        self.cursor.clone()
+    }
+
+    /// Marks `payment` and every payment before it as handled.
+    pub fn commit(&self, payment: &IncomingPayment) -> Result<(), Error> {
+        self.cursor.commit(payment)
+    }
+
+    /// Forgets the payments read but not committed, so `next` delivers them again.
+    pub fn rewind(&mut self) {
+        self.position = None;
+        self.pending.clear();
+    }
+
+    fn position(&mut self) -> Result<i64, Error> {
+        match self.position {
+            Some(position) => Ok(position),
+            None => {
+                let position = self.cursor.load()?;
+                self.position = Some(position);
+                Ok(position)
+            }
+        }
+    }
+
+    fn push_outputs(&mut self, mut outputs: Vec<Output>) -> Result<(), Error> {
+        outputs.sort_by_key(|o| o.sequence);
+        for output in outputs {
+            let Some(sequence) = output.sequence else {
+                continue;
+            };
+            if self.position.is_some_and(|p| sequence <= p) {
+                continue;
+            }
+            self.position = Some(sequence);
+            let from_self = output
+                .senders
+                .as_deref()
+                .is_some_and(|s| s.len() == 1 && s[0] == self.user_id);
+            if from_self && !self.include_self {
+                continue;
+            }
+            self.pending
+                .push_back(IncomingPayment::from_output(&output)?);
+        }
+        Ok(())
+    }
+}
+
+fn error_backoff(interval: Duration, errors: u32) -> Duration {
+    interval
+        .saturating_mul(1 << (errors - 1).min(16))
+        .min(MAX_ERROR_BACKOFF)
+}
+
+// Looks the snapshot of `payment` up among the snapshots of its asset created
+// from shortly before its output on.
+async fn find_snapshot_id(
+    payment: &IncomingPayment,
+    safe_user: &SafeUser,
+) -> Result<Option<String>, Error> {
+    let Some(offset) = payment.created_at.as_deref().and_then(snapshot_offset) else {
+        return Ok(None);
+    };
+    let query = SafeSnapshotQuery {
+        asset: Some(payment.asset_id.clone()),
+        offset: Some(offset),
+        limit: Some(SNAPSHOTS_PAGE_LIMIT),
+        order: Some("ASC".to_string()),
+        ..Default::default()
+    };
+    let snapshots = list_safe_snapshots(&query, safe_user).await?;
+    Ok(snapshots
+        .into_iter()
+        .find(|s| {
+            s.transaction_hash.as_deref() == Some(payment.transaction_hash.as_str())
+                && s.output_index == Some(payment.output_index)
+        })
+        .map(|s| s.snapshot_id))
+}
+
+fn snapshot_offset(created_at: &str) -> Option<String> {
+    let created_at = chrono::DateTime::parse_from_rfc3339(created_at).ok()?;
+    let offset = created_at.to_utc() - chrono::Duration::minutes(1);
+    Some(offset.to_rfc3339_opts(chrono::SecondsFormat::Nanos, true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    const USER_ID: &str = "67a87828-18f5-46a1-b6cc-c72a97a77c43";
+    const SENDER_ID: &str = "965e5c6e-434c-3fa9-b780-c50f43cd955c";
+
+    fn output(id: &str, sequence: i64, sender: &str, extra: &str) -> Output {
+        Output {
+            request_id: Some(format!("trace-{id}")),
+            transaction_hash: Some("00".repeat(32)),
+            output_index: Some(0),
+            asset_id: Some("asset".to_string()),
+            senders: Some(vec![sender.to_string()]),
+            extra: Some(extra.to_string()),
+            sequence: Some(sequence),
+            ..fixtures::output(id, "0.1")
+        }
+    }
+
+    #[test]
+    fn test_incoming_payment_memo() {
+        let payment =
+            IncomingPayment::from_output(&output("a", 1, SENDER_ID, "68656c6c6f")).unwrap();
+        assert_eq!(payment.memo, "hello");
+        assert_eq!(payment.trace_id.as_deref(), Some("trace-a"));
+        assert_eq!(payment.senders, [SENDER_ID]);
+
+        let payment = IncomingPayment::from_output(&output("b", 2, SENDER_ID, "ff00")).unwrap();
+        assert_eq!(payment.memo, "ff00");
+        assert_eq!(payment.extra, [0xff, 0x00]);
+    }
+
+    #[test]
+    fn test_payment_stream_commit_and_rewind() {
+        let mut stream = PaymentStream::new(MemoryCursorStore::new(1), USER_ID);
+        stream.position().unwrap();
+        stream
+            .push_outputs(vec![
+                output("c", 4, SENDER_ID, ""),
+                output("a", 1, SENDER_ID, ""),
+                output("b", 2, SENDER_ID, ""),
+                output("change", 3, USER_ID, ""),
+            ])
+            .unwrap();
+        let ids: Vec<_> = stream
+            .pending
+            .iter()
+            .map(|p| p.output_id.as_str())
+            .collect();
+        assert_eq!(ids, ["b", "c"]);
+        assert_eq!(stream.position, Some(4));
+
+        let b = stream.pending.pop_front().unwrap();
+        stream.commit(&b).unwrap();
+        assert_eq!(stream.cursor.load().unwrap(), 2);
+        stream
+            .commit(&IncomingPayment { sequence: 1, ..b })
+            .unwrap();
+        assert_eq!(stream.cursor.load().unwrap(), 2);
+
+        stream.rewind();
+        assert_eq!(stream.position().unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_payment_stream_as_stream() {
+        use futures_util::StreamExt;
+
+        let user = fixtures::test_user();
+        let mut payments = PaymentStream::new(MemoryCursorStore::new(0), USER_ID);
+        payments.position().unwrap();
+        payments
+            .push_outputs(vec![
+                output("a", 1, SENDER_ID, ""),
+                output("b", 2, SENDER_ID, ""),
+            ])
+            .unwrap();
+
+        let cursor = payments.cursor();
+        let mut stream = Box::pin(payments.stream(&user));
+        let a = stream.next().await.unwrap().unwrap();
+        cursor.commit(&a).unwrap();
+        assert_eq!(cursor.load().unwrap(), 1);
+        let b = stream.next().await.unwrap().unwrap();
+        assert_eq!(b.output_id, "b");
+        drop(stream);
+
+        payments.rewind();
+        assert_eq!(payments.position().unwrap(), 1);
+    }
+
+    struct BrokenCursorStore;
+
+    impl CursorStore for BrokenCursorStore {
+        fn load(&self) -> Result<i64, Error> {
+            Err(Error::Storage("unreadable".to_string()))
+        }
+
+        fn save(&self, _: i64) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_payment_stream_backs_off_after_errors() {
+        use futures_util::StreamExt;
+
+        let user = fixtures::test_user();
+        let mut payments = PaymentStream::new(BrokenCursorStore, USER_ID)
+            .with_poll_interval(Duration::from_secs(1));
+        let mut stream = Box::pin(payments.stream(&user));
+        let start = tokio::time::Instant::now();
+        for _ in 0..3 {
+            assert!(stream.next().await.unwrap().is_err());
+        }
+        assert_eq!(start.elapsed(), Duration::from_secs(3));
+        assert_eq!(error_backoff(Duration::from_secs(1), 10), MAX_ERROR_BACKOFF);
+    }
+
+    #[test]
+    fn test_snapshot_offset() {
+        assert_eq!(
+            snapshot_offset("2024-01-02T03:04:05.123456789Z").as_deref(),
+            Some("2024-01-02T03:03:05.123456789Z")
+        );
+        assert_eq!(snapshot_offset("yesterday"), None);
+    }
+
+    #[test]
+    fn test_file_cursor_store() {
+        let path = std::env::temp_dir().join(format!("mixin-cursor-{}", uuid::Uuid::new_v4()));
+        let store = FileCursorStore::new(&path);
+        assert_eq!(store.load().unwrap(), 0);
+        store.save(42).unwrap();
+        assert_eq!(FileCursorStore::new(&path).load().unwrap(), 42);
+        std::fs::remove_file(path).unwrap();
+
+        let directory = FileCursorStore::new(std::env::temp_dir());
+        assert!(matches!(directory.load(), Err(Error::Storage(_))));
+    }
+}
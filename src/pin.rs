@@ -9,7 +9,7 @@ use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::{error::Error, safe::SafeUser};
 
-fn private_key_to_curve25519(seed: &[u8; 32]) -> [u8; 32] {
+pub(crate) fn private_key_to_curve25519(seed: &[u8; 32]) -> [u8; 32] {
     let mut hasher = Sha512::new();
     hasher.update(seed);
     let digest = hasher.finalize();
@@ -22,7 +22,7 @@ fn private_key_to_curve25519(seed: &[u8; 32]) -> [u8; 32] {
     out
 }
 
-fn public_key_to_curve25519(public_key: &[u8; 32]) -> Result<[u8; 32], Error> {
+pub(crate) fn public_key_to_curve25519(public_key: &[u8; 32]) -> Result<[u8; 32], Error> {
     let compressed = CompressedEdwardsY(*public_key);
     let point = compressed
         .decompress()
@@ -3,7 +3,7 @@ use reqwest::{
     Client,
     header::{AUTHORIZATION, CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue, USER_AGENT},
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::sync::Mutex;
 use std::time::Duration;
 use uuid::Uuid;
@@ -35,7 +35,7 @@ pub struct ApiResponse<T> {
     pub error: Option<ApiError>,
 }
 
-#[derive(Debug, Deserialize, Clone, Default)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
 pub struct ApiError {
     pub status: i32,
     pub code: i32,
@@ -68,6 +68,10 @@ pub fn set_blaze_uri(blaze: String) {
     *BLAZE_URI.lock().unwrap() = blaze;
 }
 
+pub fn get_blaze_uri() -> String {
+    BLAZE_URI.lock().unwrap().clone()
+}
+
 pub fn set_user_agent(ua: String) {
     *USER_AGENT_STR.lock().unwrap() = ua;
 }
@@ -0,0 +1,470 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    amount::Amount,
+    coin_selection::{CoinSelector, Selection},
+    error::Error,
+    models::Output,
+    output::list_all_unspent_outputs,
+    safe::SafeUser,
+    safe_transaction::{TransactionRecipient, send_selection},
+    transaction::{TransactionView, get_transaction},
+    utils::hash_members,
+};
+
+pub const TRANSACTION_STATE_SPENT: &str = "spent";
+
+const DEFAULT_RESERVATION_TTL: Duration = Duration::from_secs(300);
+/// Spent outputs stay locked a while so a stale `/safe/outputs` listing can't pick them.
+const SPENT_RETENTION: Duration = Duration::from_secs(3600);
+const RESERVE_ATTEMPTS: usize = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Reservation {
+    pub request_id: String,
+    pub expires_at: i64,
+    pub spent: bool,
+}
+
+/// Output reservations keyed by output id, the state shared by every store.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Reservations {
+    pub outputs: BTreeMap<String, Reservation>,
+}
+
+impl Reservations {
+    /// Locks all `output_ids` for `request_id`, replacing what the request held
+    /// before, or none of them when one is held by another request.
+    pub fn reserve(
+        &mut self,
+        request_id: &str,
+        output_ids: &[String],
+        expires_at: i64,
+        now: i64,
+    ) -> bool {
+        self.prune(now);
+        let taken = output_ids.iter().any(|id| {
+            self.outputs
+                .get(id)
+                .is_some_and(|r| r.spent || r.request_id != request_id)
+        });
+        if taken {
+            return false;
+        }
+        self.release(request_id);
+        for id in output_ids {
+            self.outputs.insert(
+                id.clone(),
+                Reservation {
+                    request_id: request_id.to_string(),
+                    expires_at,
+                    spent: false,
+                },
+            );
+        }
+        true
+    }
+
+    pub fn release(&mut self, request_id: &str) {
+        self.outputs
+            .retain(|_, r| r.spent || r.request_id != request_id);
+    }
+
+    pub fn mark_spent(&mut self, request_id: &str, expires_at: i64) {
+        for reservation in self.outputs.values_mut() {
+            if reservation.request_id == request_id {
+                reservation.spent = true;
+                reservation.expires_at = expires_at;
+            }
+        }
+    }
+
+    pub fn locked_outputs(&mut self, now: i64) -> HashSet<String> {
+        self.prune(now);
+        self.outputs.keys().cloned().collect()
+    }
+
+    fn prune(&mut self, now: i64) {
+        self.outputs.retain(|_, r| r.expires_at > now);
+    }
+}
+
+/// Where output reservations live. Every method must apply atomically, as
+/// several tasks or processes may share the same store.
+pub trait ReservationStore: Send + Sync {
+    fn reserve(
+        &self,
+        request_id: &str,
+        output_ids: &[String],
+        expires_at: i64,
+        now: i64,
+    ) -> Result<bool, Error>;
+    fn release(&self, request_id: &str) -> Result<(), Error>;
+    fn mark_spent(&self, request_id: &str, expires_at: i64) -> Result<(), Error>;
+    fn locked_outputs(&self, now: i64) -> Result<HashSet<String>, Error>;
+}
+
+/// Reservations held by the current process only.
+#[derive(Debug, Default)]
+pub struct MemoryReservationStore {
+    reservations: Mutex<Reservations>,
+}
+
+impl MemoryReservationStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn update<T>(&self, f: impl FnOnce(&mut Reservations) -> T) -> Result<T, Error> {
+        let mut reservations = self
+            .reservations
+            .lock()
+            .map_err(|_| Error::Storage("reservation store lock poisoned".to_string()))?;
+        Ok(f(&mut reservations))
+    }
+}
+
+impl ReservationStore for MemoryReservationStore {
+    fn reserve(
+        &self,
+        request_id: &str,
+        output_ids: &[String],
+        expires_at: i64,
+        now: i64,
+    ) -> Result<bool, Error> {
+        self.update(|r| r.reserve(request_id, output_ids, expires_at, now))
+    }
+
+    fn release(&self, request_id: &str) -> Result<(), Error> {
+        self.update(|r| r.release(request_id))
+    }
+
+    fn mark_spent(&self, request_id: &str, expires_at: i64) -> Result<(), Error> {
+        self.update(|r| r.mark_spent(request_id, expires_at))
+    }
+
+    fn locked_outputs(&self, now: i64) -> Result<HashSet<String>, Error> {
+        self.update(|r| r.locked_outputs(now))
+    }
+}
+
+/// Reservations kept in a JSON file under an exclusive file lock, so that
+/// several processes spending from the same user don't pick the same outputs.
+#[derive(Debug)]
+pub struct FileReservationStore {
+    path: PathBuf,
+}
+
+impl FileReservationStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn update<T>(&self, f: impl FnOnce(&mut Reservations) -> T) -> Result<T, Error> {
+        let io = |e: std::io::Error| Error::Storage(format!("reservation file: {e}"));
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&self.path)
+            .map_err(io)?;
+        file.lock().map_err(io)?;
+
+        let mut data = String::new();
+        file.read_to_string(&mut data).map_err(io)?;
+        let mut reservations: Reservations = if data.trim().is_empty() {
+            Reservations::default()
+        } else {
+            serde_json::from_str(&data)?
+        };
+        let result = f(&mut reservations);
+
+        write_reservations(&mut file, &reservations).map_err(io)?;
+        file.unlock().map_err(io)?;
+        Ok(result)
+    }
+}
+
+fn write_reservations(file: &mut File, reservations: &Reservations) -> std::io::Result<()> {
+    let data = serde_json::to_vec(reservations)?;
+    file.seek(SeekFrom::Start(0))?;
+    file.set_len(0)?;
+    file.write_all(&data)?;
+    file.sync_data()
+}
+
+impl ReservationStore for FileReservationStore {
+    fn reserve(
+        &self,
+        request_id: &str,
+        output_ids: &[String],
+        expires_at: i64,
+        now: i64,
+    ) -> Result<bool, Error> {
+        self.update(|r| r.reserve(request_id, output_ids, expires_at, now))
+    }
+
+    fn release(&self, request_id: &str) -> Result<(), Error> {
+        self.update(|r| r.release(request_id))
+    }
+
+    fn mark_spent(&self, request_id: &str, expires_at: i64) -> Result<(), Error> {
+        self.update(|r| r.mark_spent(request_id, expires_at))
+    }
+
+    fn locked_outputs(&self, now: i64) -> Result<HashSet<String>, Error> {
+        self.update(|r| r.locked_outputs(now))
+    }
+}
+
+/// Locks the outputs selected for an in-flight request id until it is
+/// confirmed, released or expired.
+#[derive(Debug)]
+pub struct OutputReservations<S = MemoryReservationStore> {
+    store: S,
+    ttl: Duration,
+}
+
+impl Default for OutputReservations {
+    fn default() -> Self {
+        Self::new(MemoryReservationStore::new())
+    }
+}
+
+impl<S: ReservationStore> OutputReservations<S> {
+    pub fn new(store: S) -> Self {
+        Self {
+            store,
+            ttl: DEFAULT_RESERVATION_TTL,
+        }
+    }
+
+    /// How long a reservation is held when neither released nor confirmed.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Drops the outputs reserved by any request.
+    pub fn available_outputs(&self, outputs: Vec<Output>) -> Result<Vec<Output>, Error> {
+        let locked = self.store.locked_outputs(now())?;
+        Ok(outputs
+            .into_iter()
+            .filter(|o| !locked.contains(&o.output_id))
+            .collect())
+    }
+
+    pub fn reserve(&self, request_id: &str, outputs: &[Output]) -> Result<(), Error> {
+        let ids: Vec<String> = outputs.iter().map(|o| o.output_id.clone()).collect();
+        let now = now();
+        if self
+            .store
+            .reserve(request_id, &ids, now + self.ttl.as_secs() as i64, now)?
+        {
+            Ok(())
+        } else {
+            Err(Error::Input(format!(
+                "outputs of request {request_id} are already reserved"
+            )))
+        }
+    }
+
+    /// Selects `amount` among the outputs not reserved yet and reserves them
+    /// for `request_id`, selecting again when another process won the race.
+    pub fn select_and_reserve(
+        &self,
+        request_id: &str,
+        selector: &CoinSelector,
+        outputs: &[Output],
+        amount: Amount,
+    ) -> Result<Selection, Error> {
+        let mut last = None;
+        for _ in 0..RESERVE_ATTEMPTS {
+            let available = self.available_outputs(outputs.to_vec())?;
+            let selection = selector.select(&available, amount)?;
+            match self.reserve(request_id, &selection.outputs) {
+                Ok(()) => return Ok(selection),
+                Err(err) => last = Some(err),
+            }
+        }
+        Err(last.unwrap_or_else(|| Error::Input("outputs reservation failed".to_string())))
+    }
+
+    pub fn release(&self, request_id: &str) -> Result<(), Error> {
+        self.store.release(request_id)
+    }
+
+    pub fn mark_spent(&self, request_id: &str) -> Result<(), Error> {
+        self.store
+            .mark_spent(request_id, now() + SPENT_RETENTION.as_secs() as i64)
+    }
+
+    /// Marks the outputs of `request_id` spent once `get_transaction` reports
+    /// the transaction spent, returning whether it is.
+    pub async fn confirm(&self, request_id: &str, safe_user: &SafeUser) -> Result<bool, Error> {
+        let view = get_transaction(request_id, safe_user).await?;
+        let spent = view.state.as_deref() == Some(TRANSACTION_STATE_SPENT);
+        if spent {
+            self.mark_spent(request_id)?;
+        }
+        Ok(spent)
+    }
+
+    /// Like `safe_transaction::send_transaction`, but only spends outputs no
+    /// other request holds. The outputs stay reserved under `trace_id` until
+    /// `confirm`, and are released when sending fails before
+    /// the transaction could have been submitted.
+    pub async fn send_transaction(
+        &self,
+        asset_id: &str,
+        recipients: &[TransactionRecipient],
+        memo: &str,
+        trace_id: &str,
+        safe_user: &SafeUser,
+    ) -> Result<TransactionView, Error> {
+        let members_hash = hash_members([&safe_user.user_id]);
+        let outputs = list_all_unspent_outputs(&members_hash, 1, Some(asset_id), safe_user).await?;
+        let total = Amount::checked_sum(recipients.iter().map(|r| r.amount))?;
+        let selection =
+            self.select_and_reserve(trace_id, &CoinSelector::default(), &outputs, total)?;
+
+        match send_selection(&selection, recipients, memo, trace_id, safe_user).await {
+            Ok(view) => {
+                if view.state.as_deref() == Some(TRANSACTION_STATE_SPENT) {
+                    self.mark_spent(trace_id)?;
+                }
+                Ok(view)
+            }
+            Err(err) => {
+                if !maybe_submitted(&err) {
+                    self.release(trace_id)?;
+                }
+                Err(err)
+            }
+        }
+    }
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+// Whether the transaction may have reached the API despite `error`, e.g. a
+// timed out or unreadable submit response. Its outputs then stay reserved
+// until `confirm` or the reservation expires.
+fn maybe_submitted(error: &Error) -> bool {
+    match error {
+        Error::Request(_) | Error::Json(_) | Error::Server(_) => true,
+        Error::Api(e) => e.status >= 500,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::outputs;
+
+    fn ids(selection: &Selection) -> Vec<String> {
+        selection
+            .outputs
+            .iter()
+            .map(|o| o.output_id.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_reservations_lifecycle() {
+        let mut reservations = Reservations::default();
+        let ids = vec!["a".to_string(), "b".to_string()];
+        assert!(reservations.reserve("req-1", &ids, 100, 0));
+        assert!(reservations.reserve("req-1", &ids, 100, 0));
+        assert!(!reservations.reserve("req-2", &ids[1..], 100, 0));
+
+        reservations.release("req-1");
+        assert!(reservations.reserve("req-2", &ids[1..], 100, 0));
+        reservations.mark_spent("req-2", 200);
+        assert!(!reservations.reserve("req-2", &ids[1..], 300, 150));
+        assert_eq!(reservations.locked_outputs(150).len(), 1);
+        assert!(reservations.locked_outputs(200).is_empty());
+
+        assert!(reservations.reserve("req-3", &ids, 10, 0));
+        assert!(reservations.reserve("req-4", &ids, 30, 20));
+    }
+
+    #[test]
+    fn test_select_and_reserve_parallel_requests() {
+        let reservations = OutputReservations::default();
+        let outputs = outputs(&["1", "2", "3"]);
+        let selector = CoinSelector::default();
+
+        let first = reservations
+            .select_and_reserve("req-1", &selector, &outputs, "2.5".parse().unwrap())
+            .unwrap();
+        assert_eq!(ids(&first), ["output-2"]);
+        let second = reservations
+            .select_and_reserve("req-2", &selector, &outputs, "2.5".parse().unwrap())
+            .unwrap();
+        assert_eq!(ids(&second), ["output-1", "output-0"]);
+        assert!(
+            reservations
+                .select_and_reserve("req-3", &selector, &outputs, "1".parse().unwrap())
+                .is_err()
+        );
+
+        reservations.release("req-1").unwrap();
+        let third = reservations
+            .select_and_reserve("req-3", &selector, &outputs, "1".parse().unwrap())
+            .unwrap();
+        assert_eq!(ids(&third), ["output-2"]);
+    }
+
+    #[test]
+    fn test_file_reservation_store() {
+        let path =
+            std::env::temp_dir().join(format!("mixin-reservations-{}.json", uuid::Uuid::new_v4()));
+        let first = FileReservationStore::new(&path);
+        let second = FileReservationStore::new(&path);
+        let ids = vec!["a".to_string()];
+
+        assert!(first.reserve("req-1", &ids, 100, 0).unwrap());
+        assert!(!second.reserve("req-2", &ids, 100, 0).unwrap());
+        assert!(second.locked_outputs(0).unwrap().contains("a"));
+        first.release("req-1").unwrap();
+        assert!(second.reserve("req-2", &ids, 100, 0).unwrap());
+        std::fs::remove_file(path).unwrap();
+
+        let unwritable = FileReservationStore::new(std::env::temp_dir());
+        assert!(matches!(
+            unwritable.reserve("req-1", &ids, 100, 0),
+            Err(Error::Storage(_))
+        ));
+    }
+
+    #[test]
+    fn test_release_only_when_not_submitted() {
+        let api = |status| {
+            Error::Api(crate::request::ApiError {
+                status,
+                code: 10002,
+                description: String::new(),
+            })
+        };
+        assert!(maybe_submitted(&Error::Server("timeout".to_string())));
+        assert!(maybe_submitted(&api(502)));
+        assert!(!maybe_submitted(&api(403)));
+        assert!(!maybe_submitted(&Error::Input("bad recipient".to_string())));
+    }
+}
@@ -1,10 +1,13 @@
 use crate::{
     auth,
+    crypto::{self, Address, Key},
     error::Error,
+    mix_address::MixAddress,
     pin::encrypt_ed25519_pin,
     request::{ApiResponse, DEFAULT_API_HOST, DEFAULT_USER_AGENT, HTTP_CLIENT, request},
     tip::{sign_tip_body, tip_body_for_sequencer_register, tip_body_for_verify},
     user::User,
+    utils::unique_conversation_id,
 };
 use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
 use ed25519_dalek::Signer;
@@ -85,12 +88,67 @@ impl SafeUser {
 }
 
 impl GhostKeys {
-    pub fn keys_slice(&self) -> Vec<crypto::Key> {
-        self.keys
-            .iter()
-            .map(|k| crypto::Key::from_string(k).expect("Invalid key format"))
-            .collect()
+    pub fn keys_slice(&self) -> Result<Vec<Key>, Error> {
+        self.keys.iter().map(|k| Key::from_string(k)).collect()
     }
+
+    pub fn mask_key(&self) -> Result<Key, Error> {
+        Key::from_string(&self.mask)
+    }
+
+    /// Derives ghost keys for main-net address receivers with a random mask.
+    pub fn derive(receivers: &[Address], index: u32) -> Result<Self, Error> {
+        Self::derive_with_mask_key(&Key::random(), receivers, index)
+    }
+
+    pub fn derive_with_mask_key(r: &Key, receivers: &[Address], index: u32) -> Result<Self, Error> {
+        let (mask, keys) = crypto::derive_ghost_keys(r, receivers, index as u64)?;
+        Ok(Self {
+            key_type: "ghost_key".to_string(),
+            mask: mask.to_string(),
+            keys: keys.iter().map(Key::to_string).collect(),
+        })
+    }
+}
+
+/// Derives ghost keys for main-net address receivers locally. The mask is
+/// deterministic for the trace id and output index, as in the Go SDK, so
+/// retrying a transfer with the same trace id builds the same outputs.
+pub fn derive_safe_ghost_keys(
+    receivers: &[Address],
+    index: u32,
+    trace_id: &str,
+    safe_user: &SafeUser,
+) -> Result<GhostKeys, Error> {
+    let trace_hash = blake3::hash(trace_id.as_bytes());
+    let seed_hash = ghost_seed_hash(trace_hash.as_bytes(), index);
+    let spend = spend_signing_key(safe_user)?;
+
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&seed_hash);
+    hasher.update(spend.as_bytes());
+    let priv_hash = hasher.finalize();
+
+    let mut seed = [0u8; 64];
+    seed[..32].copy_from_slice(trace_hash.as_bytes());
+    seed[32..].copy_from_slice(priv_hash.as_bytes());
+    GhostKeys::derive_with_mask_key(&Key::new_from_seed(&seed), receivers, index)
+}
+
+/// The `/safe/keys` hint for the output at `index` of the transaction `trace_id`.
+pub fn ghost_key_hint(trace_id: &str, index: u32) -> String {
+    let trace_hash = blake3::hash(trace_id.as_bytes());
+    let seed_hash = ghost_seed_hash(trace_hash.as_bytes(), index);
+    unique_conversation_id(&hex::encode(trace_hash.as_bytes()), &hex::encode(seed_hash))
+}
+
+fn ghost_seed_hash(trace_hash: &[u8; 32], index: u32) -> [u8; 32] {
+    let index = index.to_be_bytes();
+    let start = index.iter().position(|b| *b != 0).unwrap_or(index.len());
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(trace_hash);
+    hasher.update(&index[start..]);
+    *hasher.finalize().as_bytes()
 }
 
 pub async fn request_safe_ghost_keys(
@@ -129,6 +187,65 @@ pub async fn request_safe_ghost_keys(
         .ok_or_else(|| Error::DataNotFound("API response did not contain user data".to_string()))
 }
 
+/// Ghost keys for the outputs of the transaction `trace_id`, one per address
+/// in order: derived locally for main-net address members and requested from
+/// `/safe/keys` for user id members.
+pub async fn request_ghost_keys_for(
+    addresses: &[MixAddress],
+    trace_id: &str,
+    safe_user: &SafeUser,
+) -> Result<Vec<GhostKeys>, Error> {
+    request_ghost_keys_from(addresses, 0, trace_id, safe_user).await
+}
+
+/// Like `request_ghost_keys_for` for outputs starting at `first_index`, e.g.
+/// 1 when output 0 is a withdrawal that needs no keys.
+pub async fn request_ghost_keys_from(
+    addresses: &[MixAddress],
+    first_index: u32,
+    trace_id: &str,
+    safe_user: &SafeUser,
+) -> Result<Vec<GhostKeys>, Error> {
+    let index = |i: usize| first_index + i as u32;
+    let requests: Vec<GhostKeyRequest> = addresses
+        .iter()
+        .enumerate()
+        .filter(|(_, a)| !a.uuid_members.is_empty())
+        .map(|(i, a)| GhostKeyRequest {
+            receivers: a.members(),
+            index: index(i),
+            hint: ghost_key_hint(trace_id, index(i)),
+        })
+        .collect();
+    let mut requested = if requests.is_empty() {
+        Vec::new()
+    } else {
+        request_safe_ghost_keys(&requests, safe_user).await?
+    };
+    if requested.len() != requests.len() {
+        return Err(Error::DataNotFound(format!(
+            "ghost keys count {} != {}",
+            requested.len(),
+            requests.len()
+        )));
+    }
+
+    let mut requested = requested.drain(..);
+    addresses
+        .iter()
+        .enumerate()
+        .map(|(i, a)| {
+            if a.uuid_members.is_empty() {
+                derive_safe_ghost_keys(&a.xin_members, index(i), trace_id, safe_user)
+            } else {
+                requested
+                    .next()
+                    .ok_or_else(|| Error::DataNotFound("missing ghost keys".to_string()))
+            }
+        })
+        .collect()
+}
+
 fn spend_signing_key(user: &SafeUser) -> Result<ed25519_dalek::SigningKey, Error> {
     let key_bytes = hex::decode(&user.spend_private_key)?;
     let seed = match key_bytes.len() {
@@ -224,23 +341,6 @@ pub struct OAuthError {
     pub description: String,
 }
 
-pub mod crypto {
-    use serde::{Deserialize, Serialize};
-
-    #[derive(Debug, Clone, Serialize, Deserialize)]
-    pub struct Key {
-        pub value: String,
-    }
-
-    impl Key {
-        pub fn from_string(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
-            Ok(Self {
-                value: s.to_string(),
-            })
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use std::env;
@@ -290,4 +390,28 @@ mod tests {
         let decoded = URL_SAFE_NO_PAD.decode(signature).expect("decode");
         assert_eq!(decoded.len(), 64);
     }
+
+    #[test]
+    fn test_derive_safe_ghost_keys() {
+        let safe_user = SafeUser::new(
+            "67a87828-18f5-46a1-b6cc-c72a97a77c43".to_string(),
+            "session-id".to_string(),
+            "00".repeat(32),
+            "11".repeat(32),
+            "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f".to_string(),
+        );
+        let address = Address::from_string(
+            "XINSwYaJPnKiwBWqXm4i3e3My9GKguReMRyB1sRSexeHcQ7V66RWsicAiR2dokcQ5kiJsfY5QbEjTcqRQRCxkEyENBaz4AeB",
+        )
+        .expect("address");
+        let ghost = derive_safe_ghost_keys(&[address], 0, "trace-id", &safe_user).expect("ghost");
+        assert_eq!(
+            ghost.mask,
+            "1790b187b0951b2bc957a5986ecb03353c67f0a84968fc38aa2622332acb179d"
+        );
+        assert_eq!(
+            ghost.keys,
+            vec!["95a8bce4f167124fadb226b5e5b3cf6860fe95d9786596eebb7bce2aa9176eac"]
+        );
+    }
 }
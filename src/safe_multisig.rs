@@ -0,0 +1,387 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    amount::Amount,
+    auth::sign_authentication_token,
+    error::Error,
+    models::Output,
+    output::{OUTPUT_STATE_SIGNED, list_all_outputs},
+    pin::encrypt_ed25519_pin,
+    request::{ApiResponse, request},
+    safe::SafeUser,
+    safe_transaction::{SafeTransaction, sign_transaction_inputs_at},
+    tip::{TIP_MULTISIG_REQUEST_SIGN, TIP_MULTISIG_REQUEST_UNLOCK, sign_tip_body, tip_body},
+    transaction::{SafeMultisigReceiver, TransactionRequest},
+    utils::hash_members,
+};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SafeMultisigRequest {
+    #[serde(default, rename = "type")]
+    pub type_name: Option<String>,
+    #[serde(default)]
+    pub request_id: Option<String>,
+    #[serde(default)]
+    pub transaction_hash: Option<String>,
+    #[serde(default)]
+    pub asset_id: Option<String>,
+    #[serde(default)]
+    pub kernel_asset_id: Option<String>,
+    #[serde(default, deserialize_with = "crate::amount::deserialize_optional")]
+    pub amount: Option<Amount>,
+    #[serde(default)]
+    pub receivers: Vec<SafeMultisigReceiver>,
+    #[serde(default)]
+    pub senders: Vec<String>,
+    #[serde(default)]
+    pub senders_hash: Option<String>,
+    #[serde(default)]
+    pub senders_threshold: Option<i64>,
+    #[serde(default)]
+    pub signers: Vec<String>,
+    #[serde(default)]
+    pub revoked_by: Option<String>,
+    #[serde(default)]
+    pub state: Option<String>,
+    #[serde(default)]
+    pub extra: Option<String>,
+    #[serde(default)]
+    pub raw_transaction: Option<String>,
+    #[serde(default)]
+    pub views: Vec<String>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub updated_at: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct SafeMultisigActionRequest<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    raw: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pin_base64: Option<String>,
+}
+
+/// Which members signed a multisig and which are still needed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultisigSignatures {
+    pub signed: Vec<String>,
+    pub missing: Vec<String>,
+    pub threshold: usize,
+}
+
+impl MultisigSignatures {
+    pub fn new<T: AsRef<str>>(members: &[T], threshold: u8, signers: &[T]) -> Self {
+        let signers: BTreeSet<&str> = signers.iter().map(|s| s.as_ref()).collect();
+        let members: BTreeSet<&str> = members.iter().map(|m| m.as_ref()).collect();
+        let (signed, missing) = members.into_iter().partition(|m| signers.contains(m));
+        Self {
+            signed: to_strings(signed),
+            missing: to_strings(missing),
+            threshold: threshold as usize,
+        }
+    }
+
+    /// Members whose index is present in the signatures of every input of
+    /// `tx`, the indexes following the sorted `members`.
+    pub fn from_transaction<T: AsRef<str>>(
+        tx: &SafeTransaction,
+        members: &[T],
+        threshold: u8,
+    ) -> Self {
+        let mut sorted: Vec<&str> = members.iter().map(|m| m.as_ref()).collect();
+        sorted.sort();
+        let signers: Vec<&str> = sorted
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| {
+                !tx.inputs.is_empty()
+                    && (0..tx.inputs.len()).all(|input| {
+                        tx.signatures
+                            .get(input)
+                            .is_some_and(|s| s.contains_key(&(*i as u16)))
+                    })
+            })
+            .map(|(_, m)| *m)
+            .collect();
+        Self::new(&sorted, threshold, &signers)
+    }
+
+    pub fn from_request(request: &SafeMultisigRequest) -> Self {
+        let threshold = request.senders_threshold.unwrap_or_default().clamp(0, 255) as u8;
+        Self::new(&request.senders, threshold, &request.signers)
+    }
+
+    /// Signatures still needed to reach the threshold.
+    pub fn remaining(&self) -> usize {
+        self.threshold.saturating_sub(self.signed.len())
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.remaining() == 0
+    }
+}
+
+fn to_strings(members: Vec<&str>) -> Vec<String> {
+    members.into_iter().map(String::from).collect()
+}
+
+pub async fn create_safe_multisig_requests(
+    requests: &[TransactionRequest],
+    safe_user: &SafeUser,
+) -> Result<Vec<SafeMultisigRequest>, Error> {
+    let path = "/safe/multisigs";
+    let data_str = serde_json::to_string(requests)?;
+    let token = sign_authentication_token("POST", path, &data_str, safe_user)?;
+    let body = request("POST", path, data_str.as_bytes(), &token).await?;
+
+    let parsed: ApiResponse<Vec<SafeMultisigRequest>> = serde_json::from_slice(&body)?;
+    if let Some(api_error) = parsed.error {
+        return Err(Error::Api(api_error));
+    }
+    parsed.data.ok_or_else(|| {
+        Error::DataNotFound("API response did not contain multisig data".to_string())
+    })
+}
+
+pub async fn create_safe_multisig_request(
+    request_id: &str,
+    raw: &str,
+    safe_user: &SafeUser,
+) -> Result<SafeMultisigRequest, Error> {
+    let requests = [TransactionRequest {
+        request_id: request_id.to_string(),
+        raw: raw.to_string(),
+    }];
+    let mut multisigs = create_safe_multisig_requests(&requests, safe_user).await?;
+    if multisigs.len() != 1 {
+        return Err(Error::DataNotFound(format!(
+            "expected one multisig request, got {}",
+            multisigs.len()
+        )));
+    }
+    Ok(multisigs.remove(0))
+}
+
+/// Reads a multisig request by request id or transaction hash.
+pub async fn read_safe_multisig_request(
+    id: &str,
+    safe_user: &SafeUser,
+) -> Result<SafeMultisigRequest, Error> {
+    let path = format!("/safe/multisigs/{id}");
+    let token = sign_authentication_token("GET", &path, "", safe_user)?;
+    let body = request("GET", &path, &[], &token).await?;
+
+    let parsed: ApiResponse<SafeMultisigRequest> = serde_json::from_slice(&body)?;
+    if let Some(api_error) = parsed.error {
+        return Err(Error::Api(api_error));
+    }
+    parsed.data.ok_or_else(|| {
+        Error::DataNotFound("API response did not contain multisig data".to_string())
+    })
+}
+
+/// Adds the signatures of the user to the raw transaction of the request and
+/// submits it together with a TIP_MULTISIG_REQUEST_SIGN signature.
+pub async fn sign_safe_multisig_request(
+    id: &str,
+    safe_user: &SafeUser,
+) -> Result<SafeMultisigRequest, Error> {
+    let multisig = read_safe_multisig_request(id, safe_user).await?;
+    let raw = multisig.raw_transaction.as_deref().ok_or_else(|| {
+        Error::DataNotFound("multisig request is missing raw transaction".to_string())
+    })?;
+    let signed = sign_safe_multisig_raw(raw, &multisig.views, &multisig.senders, safe_user)?;
+    let request_id = multisig.request_id.as_deref().unwrap_or(id);
+
+    let action = SafeMultisigActionRequest {
+        raw: Some(&signed),
+        pin_base64: Some(multisig_pin(
+            TIP_MULTISIG_REQUEST_SIGN,
+            request_id,
+            safe_user,
+        )?),
+    };
+    post_multisig_action(request_id, "sign", &action, safe_user).await
+}
+
+/// Signs every input of `raw` at the index of the user among the sorted `senders`.
+pub fn sign_safe_multisig_raw(
+    raw: &str,
+    views: &[String],
+    senders: &[String],
+    safe_user: &SafeUser,
+) -> Result<String, Error> {
+    let mut sorted = senders.to_vec();
+    sorted.sort();
+    let index = sorted
+        .iter()
+        .position(|s| *s == safe_user.user_id)
+        .ok_or_else(|| Error::Input(format!("{} is not a multisig sender", safe_user.user_id)))?;
+
+    let mut tx = SafeTransaction::decode(raw)?;
+    sign_transaction_inputs_at(&mut tx, views, index as u16, None, safe_user)?;
+    tx.encode()
+}
+
+/// Withdraws the signature of the user, letting the outputs be spent otherwise.
+pub async fn unlock_safe_multisig_request(
+    request_id: &str,
+    safe_user: &SafeUser,
+) -> Result<SafeMultisigRequest, Error> {
+    let action = SafeMultisigActionRequest {
+        raw: None,
+        pin_base64: Some(multisig_pin(
+            TIP_MULTISIG_REQUEST_UNLOCK,
+            request_id,
+            safe_user,
+        )?),
+    };
+    post_multisig_action(request_id, "unlock", &action, safe_user).await
+}
+
+/// Cancels the request for every member.
+pub async fn revoke_safe_multisig_request(
+    request_id: &str,
+    safe_user: &SafeUser,
+) -> Result<SafeMultisigRequest, Error> {
+    let action = SafeMultisigActionRequest {
+        raw: None,
+        pin_base64: None,
+    };
+    post_multisig_action(request_id, "revoke", &action, safe_user).await
+}
+
+/// Multisig requests waiting for signatures on the outputs held by `members`
+/// with `threshold`, found from the signed transactions of those outputs.
+pub async fn list_pending_safe_multisig_requests(
+    members: &[String],
+    threshold: u8,
+    asset_id: Option<&str>,
+    safe_user: &SafeUser,
+) -> Result<Vec<SafeMultisigRequest>, Error> {
+    let outputs = list_all_outputs(
+        &hash_members(members),
+        threshold,
+        asset_id,
+        Some(OUTPUT_STATE_SIGNED),
+        safe_user,
+    )
+    .await?;
+
+    let mut requests = Vec::new();
+    for hash in pending_transaction_hashes(&outputs)? {
+        requests.push(read_safe_multisig_request(&hash, safe_user).await?);
+    }
+    Ok(requests)
+}
+
+fn pending_transaction_hashes(outputs: &[Output]) -> Result<BTreeSet<String>, Error> {
+    let mut hashes = BTreeSet::new();
+    for raw in outputs.iter().filter_map(|o| o.signed_tx.as_deref()) {
+        if !raw.is_empty() {
+            hashes.insert(hex::encode(SafeTransaction::decode(raw)?.payload_hash()?));
+        }
+    }
+    Ok(hashes)
+}
+
+fn multisig_pin(action: &str, request_id: &str, safe_user: &SafeUser) -> Result<String, Error> {
+    let pin = sign_tip_body(
+        &tip_body(&format!("{action}{request_id}")),
+        &safe_user.spend_private_key,
+        safe_user.is_spend_private_sum,
+    )?;
+    encrypt_ed25519_pin(&pin, now_nanos()?, safe_user)
+}
+
+async fn post_multisig_action(
+    request_id: &str,
+    action: &str,
+    data: &SafeMultisigActionRequest<'_>,
+    safe_user: &SafeUser,
+) -> Result<SafeMultisigRequest, Error> {
+    let path = format!("/safe/multisigs/{request_id}/{action}");
+    let data_str = serde_json::to_string(data)?;
+    let token = sign_authentication_token("POST", &path, &data_str, safe_user)?;
+    let body = request("POST", &path, data_str.as_bytes(), &token).await?;
+
+    let parsed: ApiResponse<SafeMultisigRequest> = serde_json::from_slice(&body)?;
+    if let Some(api_error) = parsed.error {
+        return Err(Error::Api(api_error));
+    }
+    parsed.data.ok_or_else(|| {
+        Error::DataNotFound("API response did not contain multisig data".to_string())
+    })
+}
+
+fn now_nanos() -> Result<u64, Error> {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    Ok(SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| Error::Server(e.to_string()))?
+        .as_nanos() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    const MEMBERS: [&str; 3] = [
+        "c94ac88f-4671-3976-b60a-09064f1811e8",
+        "67a87828-18f5-46a1-b6cc-c72a97a77c43",
+        "c6d0c728-2624-429b-8e0d-d9d19b6592fa",
+    ];
+
+    #[test]
+    fn test_multisig_signatures() {
+        let signatures = MultisigSignatures::new(&MEMBERS, 2, &[MEMBERS[0]]);
+        assert_eq!(signatures.signed, [MEMBERS[0]]);
+        assert_eq!(signatures.missing, [MEMBERS[1], MEMBERS[2]]);
+        assert_eq!(signatures.remaining(), 1);
+        assert!(!signatures.is_complete());
+
+        let mut tx = SafeTransaction::new(&"00".repeat(32));
+        tx.inputs = vec![Default::default(), Default::default()];
+        let signed = BTreeMap::from([(0u16, String::new()), (2, String::new())]);
+        tx.signatures = vec![signed.clone(), BTreeMap::from([(0u16, String::new())])];
+        let signatures = MultisigSignatures::from_transaction(&tx, &MEMBERS, 2);
+        assert_eq!(signatures.signed, [MEMBERS[1]]);
+        assert_eq!(signatures.remaining(), 1);
+
+        tx.signatures[1] = signed;
+        assert!(MultisigSignatures::from_transaction(&tx, &MEMBERS, 2).is_complete());
+    }
+
+    #[test]
+    fn test_multisig_request_deserialize() {
+        let raw = r#"{
+            "type": "transaction_request",
+            "request_id": "request-id",
+            "receivers": [{"members": ["receiver-id"], "members_hash": "hash", "threshold": 1}],
+            "senders": ["c94ac88f-4671-3976-b60a-09064f1811e8", "67a87828-18f5-46a1-b6cc-c72a97a77c43"],
+            "senders_threshold": 2,
+            "signers": ["67a87828-18f5-46a1-b6cc-c72a97a77c43"],
+            "state": "pending",
+            "views": ["view"]
+        }"#;
+        let request: SafeMultisigRequest = serde_json::from_str(raw).unwrap();
+        assert_eq!(request.receivers[0].threshold, Some(1));
+        let signatures = MultisigSignatures::from_request(&request);
+        assert_eq!(signatures.missing, [MEMBERS[0]]);
+        assert_eq!(signatures.remaining(), 1);
+    }
+
+    #[test]
+    fn test_multisig_action_serialization() {
+        let action = SafeMultisigActionRequest {
+            raw: Some("raw"),
+            pin_base64: None,
+        };
+        assert_eq!(serde_json::to_string(&action).unwrap(), r#"{"raw":"raw"}"#);
+    }
+}
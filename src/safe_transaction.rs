@@ -0,0 +1,935 @@
+use std::collections::BTreeMap;
+
+use curve25519_dalek::scalar::Scalar;
+use sha2::{Digest, Sha512};
+
+use crate::{
+    amount::Amount,
+    coin_selection::{CoinSelector, Selection},
+    crypto::{Key, sha3_256},
+    error::Error,
+    mix_address::MixAddress,
+    models::Output,
+    output::list_all_unspent_outputs,
+    safe::{GhostKeys, SafeUser, request_ghost_keys_for},
+    transaction::{TransactionView, create_transaction_request, submit_transaction},
+    utils::hash_members,
+};
+
+pub const TX_VERSION_HASH_SIGNATURE: u8 = 0x05;
+pub const OUTPUT_TYPE_SCRIPT: u8 = 0x00;
+pub const OUTPUT_TYPE_WITHDRAWAL_SUBMIT: u8 = 0xa1;
+/// Maximum number of inputs, outputs or signatures in one kernel transaction.
+pub const SLICE_COUNT_LIMIT: usize = 256;
+pub const EXTRA_SIZE_GENERAL_LIMIT: usize = 256;
+pub use crate::amount::AMOUNT_DECIMALS;
+
+const MAGIC: [u8; 2] = [0x77, 0x77];
+const EMPTY: [u8; 2] = [0x00, 0x00];
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SafeTransaction {
+    pub version: u8,
+    pub asset: String,
+    pub inputs: Vec<SafeTransactionInput>,
+    pub outputs: Vec<SafeTransactionOutput>,
+    pub references: Vec<String>,
+    pub extra: Vec<u8>,
+    pub signatures: Vec<BTreeMap<u16, String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SafeTransactionInput {
+    pub hash: String,
+    pub index: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SafeTransactionOutput {
+    pub output_type: u8,
+    pub amount: Amount,
+    pub keys: Vec<Key>,
+    pub mask: Key,
+    pub script: String,
+    pub withdrawal: Option<SafeWithdrawalData>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SafeWithdrawalData {
+    pub address: String,
+    pub tag: String,
+}
+
+/// Receivers of one output: user ids or "XIN" main-net addresses, not mixed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionRecipient {
+    pub members: Vec<String>,
+    pub threshold: u8,
+    pub amount: Amount,
+}
+
+impl TransactionRecipient {
+    pub fn new(address: &MixAddress, amount: Amount) -> Self {
+        Self {
+            members: address.members(),
+            threshold: address.threshold,
+            amount,
+        }
+    }
+
+    pub fn mix_address(&self) -> Result<MixAddress, Error> {
+        MixAddress::from_members(&self.members, self.threshold)
+    }
+}
+
+impl SafeTransaction {
+    pub fn new(asset: &str) -> Self {
+        Self {
+            version: TX_VERSION_HASH_SIGNATURE,
+            asset: asset.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn decode(raw: &str) -> Result<Self, Error> {
+        let bytes = hex::decode(raw)?;
+        let mut dec = Decoder::new(&bytes);
+        if dec.read(2)? != MAGIC || dec.read_u8()? != 0x00 {
+            return Err(Error::Input("invalid transaction magic".to_string()));
+        }
+        let mut tx = Self::new("");
+        tx.version = dec.read_u8()?;
+        if tx.version != TX_VERSION_HASH_SIGNATURE {
+            return Err(Error::Input(format!(
+                "invalid transaction version: {}",
+                tx.version
+            )));
+        }
+        tx.asset = hex::encode(dec.read(32)?);
+
+        for _ in 0..dec.read_u16()? {
+            let hash = hex::encode(dec.read(32)?);
+            let index = dec.read_u16()?;
+            let genesis = dec.read_u16()? as usize;
+            dec.read(genesis)?;
+            if dec.read(2)? != EMPTY || dec.read(2)? != EMPTY {
+                return Err(Error::Input(
+                    "deposit and mint inputs are not supported".to_string(),
+                ));
+            }
+            tx.inputs.push(SafeTransactionInput { hash, index });
+        }
+
+        for _ in 0..dec.read_u16()? {
+            if dec.read_u8()? != 0x00 {
+                return Err(Error::Input("invalid output type prefix".to_string()));
+            }
+            let output_type = dec.read_u8()?;
+            let amount_len = dec.read_u16()? as usize;
+            let amount = dec.read(amount_len)?;
+            if amount.len() > 16 {
+                return Err(Error::Input("output amount overflow".to_string()));
+            }
+            let amount = amount.iter().fold(0u128, |v, b| (v << 8) | *b as u128);
+            let amount = i128::try_from(amount)
+                .map(Amount::from_units)
+                .map_err(|_| Error::Input("output amount overflow".to_string()))?;
+            let keys = (0..dec.read_u16()?)
+                .map(|_| Key::from_slice(dec.read(32)?))
+                .collect::<Result<Vec<_>, _>>()?;
+            let mask = Key::from_slice(dec.read(32)?)?;
+            let script_len = dec.read_u16()? as usize;
+            let script = hex::encode(dec.read(script_len)?);
+            let withdrawal = match dec.read(2)? {
+                prefix if prefix == EMPTY => None,
+                prefix if prefix == MAGIC => Some(SafeWithdrawalData {
+                    address: dec.read_string()?,
+                    tag: dec.read_string()?,
+                }),
+                _ => return Err(Error::Input("invalid withdrawal prefix".to_string())),
+            };
+            tx.outputs.push(SafeTransactionOutput {
+                output_type,
+                amount,
+                keys,
+                mask,
+                script,
+                withdrawal,
+            });
+        }
+
+        for _ in 0..dec.read_u16()? {
+            tx.references.push(hex::encode(dec.read(32)?));
+        }
+        let extra_len = u32::from_be_bytes(dec.read(4)?.try_into().unwrap_or_default());
+        tx.extra = dec.read(extra_len as usize)?.to_vec();
+
+        for _ in 0..dec.read_u16()? {
+            let mut map = BTreeMap::new();
+            for _ in 0..dec.read_u16()? {
+                let index = dec.read_u16()?;
+                map.insert(index, hex::encode(dec.read(64)?));
+            }
+            tx.signatures.push(map);
+        }
+        if !dec.is_empty() {
+            return Err(Error::Input(
+                "unexpected transaction trailing bytes".to_string(),
+            ));
+        }
+        Ok(tx)
+    }
+
+    /// Hex encoding of the transaction including its signatures.
+    pub fn encode(&self) -> Result<String, Error> {
+        Ok(hex::encode(self.marshal(&self.signatures)?))
+    }
+
+    /// Hex encoding of the transaction without signatures, the `raw` of a transaction request.
+    pub fn encode_unsigned(&self) -> Result<String, Error> {
+        Ok(hex::encode(self.marshal(&[])?))
+    }
+
+    /// The kernel transaction hash, which is also the message every input signs.
+    pub fn payload_hash(&self) -> Result<[u8; 32], Error> {
+        Ok(*blake3::hash(&self.marshal(&[])?).as_bytes())
+    }
+
+    fn marshal(&self, signatures: &[BTreeMap<u16, String>]) -> Result<Vec<u8>, Error> {
+        if self.version != TX_VERSION_HASH_SIGNATURE {
+            return Err(Error::Input(format!(
+                "invalid transaction version: {}",
+                self.version
+            )));
+        }
+        let mut enc = Encoder::default();
+        enc.write(&MAGIC);
+        enc.write(&[0x00, self.version]);
+        enc.write(&decode_hash(&self.asset)?);
+
+        enc.write_count(self.inputs.len())?;
+        for input in &self.inputs {
+            enc.write(&decode_hash(&input.hash)?);
+            enc.write_u16(input.index);
+            enc.write_count(0)?; // genesis
+            enc.write(&EMPTY); // deposit
+            enc.write(&EMPTY); // mint
+        }
+
+        enc.write_count(self.outputs.len())?;
+        for output in &self.outputs {
+            enc.write(&[0x00, output.output_type]);
+            let units = u128::try_from(output.amount.units())
+                .map_err(|_| Error::Input(format!("negative output amount: {}", output.amount)))?;
+            let amount = integer_bytes(units);
+            enc.write_count(amount.len())?;
+            enc.write(&amount);
+            enc.write_count(output.keys.len())?;
+            for key in &output.keys {
+                enc.write(key.as_bytes());
+            }
+            enc.write(output.mask.as_bytes());
+            let script = hex::decode(&output.script)?;
+            enc.write_count(script.len())?;
+            enc.write(&script);
+            match &output.withdrawal {
+                Some(withdrawal) => {
+                    enc.write(&MAGIC);
+                    enc.write_count(withdrawal.address.len())?;
+                    enc.write(withdrawal.address.as_bytes());
+                    enc.write_count(withdrawal.tag.len())?;
+                    enc.write(withdrawal.tag.as_bytes());
+                }
+                None => enc.write(&EMPTY),
+            }
+        }
+
+        enc.write_count(self.references.len())?;
+        for reference in &self.references {
+            enc.write(&decode_hash(reference)?);
+        }
+
+        let extra_len = u32::try_from(self.extra.len())
+            .map_err(|_| Error::Input("transaction extra is too long".to_string()))?;
+        enc.write(&extra_len.to_be_bytes());
+        enc.write(&self.extra);
+
+        enc.write_count(signatures.len())?;
+        for map in signatures {
+            enc.write_count(map.len())?;
+            for (index, signature) in map {
+                enc.write_u16(*index);
+                let signature = hex::decode(signature)?;
+                if signature.len() != 64 {
+                    return Err(Error::Input(format!(
+                        "invalid signature length: {}",
+                        signature.len()
+                    )));
+                }
+                enc.write(&signature);
+            }
+        }
+        Ok(enc.bytes)
+    }
+}
+
+pub fn encode_script(threshold: u8) -> String {
+    format!("fffe{threshold:02x}")
+}
+
+/// The kernel asset hash of a Mixin asset id.
+pub fn kernel_asset_id(asset_id: &str) -> String {
+    hex::encode(sha3_256(asset_id.as_bytes()))
+}
+
+/// Builds an unsigned transaction spending `utxos` to `recipients`, where
+/// `ghosts[i]` holds the one-time keys for `recipients[i]`.
+pub fn build_transaction(
+    utxos: &[Output],
+    recipients: &[TransactionRecipient],
+    ghosts: &[GhostKeys],
+    extra: &[u8],
+) -> Result<SafeTransaction, Error> {
+    if recipients.is_empty() {
+        return Err(Error::Input("invalid outputs count: 0".to_string()));
+    }
+    build_outputs(utxos, None, recipients, ghosts, extra)
+}
+
+/// Builds an unsigned withdrawal of `amount` to `withdrawal` as output 0,
+/// followed by `recipients`, so `ghosts[i]` must be derived for output index
+/// `i + 1`. `references` holds the hashes of transactions it depends on.
+pub fn build_withdrawal_transaction(
+    utxos: &[Output],
+    withdrawal: &SafeWithdrawalData,
+    amount: Amount,
+    recipients: &[TransactionRecipient],
+    ghosts: &[GhostKeys],
+    references: &[String],
+    extra: &[u8],
+) -> Result<SafeTransaction, Error> {
+    if withdrawal.address.is_empty() {
+        return Err(Error::Input("withdrawal address is empty".to_string()));
+    }
+    if !amount.is_positive() {
+        return Err(Error::Input(format!("invalid withdrawal amount: {amount}")));
+    }
+    let mut tx = build_outputs(utxos, Some((withdrawal, amount)), recipients, ghosts, extra)?;
+    tx.references = references.to_vec();
+    Ok(tx)
+}
+
+fn build_outputs(
+    utxos: &[Output],
+    withdrawal: Option<(&SafeWithdrawalData, Amount)>,
+    recipients: &[TransactionRecipient],
+    ghosts: &[GhostKeys],
+    extra: &[u8],
+) -> Result<SafeTransaction, Error> {
+    if utxos.is_empty() || utxos.len() > SLICE_COUNT_LIMIT {
+        return Err(Error::Input(format!(
+            "invalid inputs count: {}",
+            utxos.len()
+        )));
+    }
+    let outputs_count = recipients.len() + usize::from(withdrawal.is_some());
+    if outputs_count > SLICE_COUNT_LIMIT {
+        return Err(Error::Input(format!(
+            "invalid outputs count: {outputs_count}"
+        )));
+    }
+    if ghosts.len() != recipients.len() {
+        return Err(Error::Input(format!(
+            "invalid ghost keys count {} != {}",
+            ghosts.len(),
+            recipients.len()
+        )));
+    }
+    if extra.len() > EXTRA_SIZE_GENERAL_LIMIT {
+        return Err(Error::Input(format!("extra is too long: {}", extra.len())));
+    }
+
+    let mut tx = SafeTransaction::new(&output_asset(&utxos[0])?);
+    tx.extra = extra.to_vec();
+    for utxo in utxos {
+        if output_asset(utxo)? != tx.asset {
+            return Err(Error::Input("inconsistent asset in outputs".to_string()));
+        }
+        let hash = utxo
+            .transaction_hash
+            .clone()
+            .ok_or_else(|| Error::Input("output is missing transaction_hash".to_string()))?;
+        let index = utxo
+            .output_index
+            .and_then(|i| u16::try_from(i).ok())
+            .ok_or_else(|| Error::Input("output is missing output_index".to_string()))?;
+        tx.inputs.push(SafeTransactionInput { hash, index });
+    }
+
+    let inputs = Amount::checked_sum(utxos.iter().map(|u| u.amount.unwrap_or_default()))?;
+    let outputs = Amount::checked_sum(
+        withdrawal
+            .map(|(_, amount)| amount)
+            .into_iter()
+            .chain(recipients.iter().map(|r| r.amount)),
+    )?;
+    if inputs != outputs {
+        return Err(Error::Input(format!(
+            "inputs and outputs amount mismatch {inputs} != {outputs}"
+        )));
+    }
+
+    if let Some((withdrawal, amount)) = withdrawal {
+        tx.outputs.push(SafeTransactionOutput {
+            output_type: OUTPUT_TYPE_WITHDRAWAL_SUBMIT,
+            amount,
+            keys: Vec::new(),
+            mask: Key::default(),
+            script: String::new(),
+            withdrawal: Some(withdrawal.clone()),
+        });
+    }
+    for (recipient, ghost) in recipients.iter().zip(ghosts) {
+        let keys = ghost.keys_slice()?;
+        if !recipient.amount.is_positive() {
+            return Err(Error::Input(format!(
+                "invalid output amount: {}",
+                recipient.amount
+            )));
+        }
+        if recipient.threshold == 0 || recipient.threshold as usize > keys.len() {
+            return Err(Error::Input(format!(
+                "invalid threshold {} for {} keys",
+                recipient.threshold,
+                keys.len()
+            )));
+        }
+        tx.outputs.push(SafeTransactionOutput {
+            output_type: OUTPUT_TYPE_SCRIPT,
+            amount: recipient.amount,
+            keys,
+            mask: ghost.mask_key()?,
+            script: encode_script(recipient.threshold),
+            withdrawal: None,
+        });
+    }
+    Ok(tx)
+}
+
+/// Sends `amount` of `asset_id` to `receivers`, user ids or "XIN" addresses,
+/// with `threshold`, paying from the unspent outputs of the user and returning
+/// the change to the user.
+pub async fn send_transfer(
+    asset_id: &str,
+    amount: Amount,
+    receivers: &[String],
+    threshold: u8,
+    memo: &str,
+    trace_id: &str,
+    safe_user: &SafeUser,
+) -> Result<TransactionView, Error> {
+    let recipient = TransactionRecipient {
+        members: receivers.to_vec(),
+        threshold,
+        amount,
+    };
+    send_transaction(asset_id, &[recipient], memo, trace_id, safe_user).await
+}
+
+/// Like `send_transfer` but with several recipients, e.g. a payout plus fee outputs.
+pub async fn send_transaction(
+    asset_id: &str,
+    recipients: &[TransactionRecipient],
+    memo: &str,
+    trace_id: &str,
+    safe_user: &SafeUser,
+) -> Result<TransactionView, Error> {
+    let members_hash = hash_members([&safe_user.user_id]);
+    let outputs = list_all_unspent_outputs(&members_hash, 1, Some(asset_id), safe_user).await?;
+    let total = Amount::checked_sum(recipients.iter().map(|r| r.amount))?;
+    let selection = CoinSelector::default().select(&outputs, total)?;
+    send_selection(&selection, recipients, memo, trace_id, safe_user).await
+}
+
+/// Spends the outputs of `selection`, returning its change to the user.
+pub async fn send_selection(
+    selection: &Selection,
+    recipients: &[TransactionRecipient],
+    memo: &str,
+    trace_id: &str,
+    safe_user: &SafeUser,
+) -> Result<TransactionView, Error> {
+    let mut recipients = recipients.to_vec();
+    if selection.change.is_positive() {
+        recipients.push(TransactionRecipient {
+            members: vec![safe_user.user_id.clone()],
+            threshold: 1,
+            amount: selection.change,
+        });
+    }
+    send_transaction_with_outputs(&selection.outputs, &recipients, memo, trace_id, safe_user).await
+}
+
+/// Builds, signs and submits a transaction spending exactly `utxos`.
+pub async fn send_transaction_with_outputs(
+    utxos: &[Output],
+    recipients: &[TransactionRecipient],
+    memo: &str,
+    trace_id: &str,
+    safe_user: &SafeUser,
+) -> Result<TransactionView, Error> {
+    let addresses = recipients
+        .iter()
+        .map(TransactionRecipient::mix_address)
+        .collect::<Result<Vec<_>, _>>()?;
+    let ghosts = request_ghost_keys_for(&addresses, trace_id, safe_user).await?;
+
+    let mut tx = build_transaction(utxos, recipients, &ghosts, memo.as_bytes())?;
+    let view = create_transaction_request(trace_id, &tx.encode_unsigned()?, safe_user).await?;
+    let views = view
+        .views
+        .ok_or_else(|| Error::DataNotFound("transaction request is missing views".to_string()))?;
+    sign_transaction_inputs(&mut tx, &views, &input_keys(utxos)?, None, safe_user)?;
+    submit_transaction(trace_id, &tx.encode()?, safe_user).await
+}
+
+/// The one-time public keys of each of `utxos`, as expected by
+/// `sign_transaction_inputs`.
+pub(crate) fn input_keys(utxos: &[Output]) -> Result<Vec<Vec<Key>>, Error> {
+    utxos
+        .iter()
+        .map(|u| {
+            u.keys
+                .iter()
+                .flatten()
+                .map(|k| Key::from_string(k))
+                .collect()
+        })
+        .collect()
+}
+
+/// The one-time private key of an input: the view scalar returned by the
+/// transaction request for that input plus the spend scalar of the user.
+pub fn input_private_key(view: &str, safe_user: &SafeUser) -> Result<Key, Error> {
+    let view = Key::from_string(view)?.scalar()?;
+    Ok(Key((view + spend_scalar(safe_user)?).to_bytes()))
+}
+
+/// Signs the inputs of `tx` and merges the signatures into its signature map.
+///
+/// `views[i]` is the view key of input `i`. `keys[i]` are the keys of the output
+/// spent by input `i` and decide where the signature is placed in the map; when
+/// missing the output is treated as owned by the user alone and index 0 is used.
+/// Only the inputs listed in `inputs` are signed, all of them when `None`, so
+/// multisig participants can each add their own signatures.
+pub fn sign_transaction_inputs(
+    tx: &mut SafeTransaction,
+    views: &[String],
+    keys: &[Vec<Key>],
+    inputs: Option<&[usize]>,
+    safe_user: &SafeUser,
+) -> Result<(), Error> {
+    sign_inputs(tx, views, inputs, safe_user, |i, key| {
+        match keys.get(i).filter(|k| !k.is_empty()) {
+            Some(keys) => {
+                let public = key.public()?;
+                keys.iter()
+                    .position(|k| *k == public)
+                    .map(|p| p as u16)
+                    .ok_or_else(|| Error::Input(format!("input {i} is not signable by user")))
+            }
+            None => Ok(0),
+        }
+    })
+}
+
+/// Like `sign_transaction_inputs`, placing every signature at `index`, the
+/// position of the user among the sorted members owning the inputs.
+pub fn sign_transaction_inputs_at(
+    tx: &mut SafeTransaction,
+    views: &[String],
+    index: u16,
+    inputs: Option<&[usize]>,
+    safe_user: &SafeUser,
+) -> Result<(), Error> {
+    sign_inputs(tx, views, inputs, safe_user, |_, _| Ok(index))
+}
+
+fn sign_inputs<F>(
+    tx: &mut SafeTransaction,
+    views: &[String],
+    inputs: Option<&[usize]>,
+    safe_user: &SafeUser,
+    signature_index: F,
+) -> Result<(), Error>
+where
+    F: Fn(usize, &Key) -> Result<u16, Error>,
+{
+    if views.len() != tx.inputs.len() {
+        return Err(Error::Input(format!(
+            "invalid view keys count {} != {}",
+            views.len(),
+            tx.inputs.len()
+        )));
+    }
+    let all: Vec<usize> = (0..tx.inputs.len()).collect();
+    let inputs = inputs.unwrap_or(&all);
+    if let Some(i) = inputs.iter().find(|i| **i >= tx.inputs.len()) {
+        return Err(Error::Input(format!("invalid input index: {i}")));
+    }
+
+    let message = tx.payload_hash()?;
+    tx.signatures.resize(tx.inputs.len(), BTreeMap::new());
+    for &i in inputs {
+        let key = input_private_key(&views[i], safe_user)?;
+        let index = signature_index(i, &key)?;
+        tx.signatures[i].insert(index, hex::encode(key.sign(&message)?));
+    }
+    Ok(())
+}
+
+/// Signs every input of a raw transaction owned by the user alone, as the Go
+/// SDK does for 1/1 bot outputs, and returns the signed raw.
+pub fn sign_raw_transaction(
+    raw: &str,
+    views: &[String],
+    safe_user: &SafeUser,
+) -> Result<String, Error> {
+    let mut tx = SafeTransaction::decode(raw)?;
+    sign_transaction_inputs(&mut tx, views, &[], None, safe_user)?;
+    tx.encode()
+}
+
+fn spend_scalar(safe_user: &SafeUser) -> Result<Scalar, Error> {
+    let key = hex::decode(&safe_user.spend_private_key)?;
+    if key.len() != 32 && key.len() != 64 {
+        return Err(Error::Input("invalid spend private key length".to_string()));
+    }
+    if safe_user.is_spend_private_sum {
+        return Key::from_slice(&key[..32])?.scalar();
+    }
+    let digest = Sha512::digest(&key[..32]);
+    let mut clamped = [0u8; 32];
+    clamped.copy_from_slice(&digest[..32]);
+    clamped[0] &= 248;
+    clamped[31] &= 127;
+    clamped[31] |= 64;
+    Ok(Scalar::from_bytes_mod_order(clamped))
+}
+
+fn output_asset(output: &Output) -> Result<String, Error> {
+    if let Some(asset) = output.kernel_asset_id.as_deref().filter(|a| !a.is_empty()) {
+        return Ok(asset.to_lowercase());
+    }
+    output
+        .asset_id
+        .as_deref()
+        .map(kernel_asset_id)
+        .ok_or_else(|| Error::Input(format!("output {} is missing asset", output.output_id)))
+}
+
+fn decode_hash(s: &str) -> Result<[u8; 32], Error> {
+    hex::decode(s)?
+        .try_into()
+        .map_err(|_| Error::Input(format!("invalid hash: {s}")))
+}
+
+// Minimal big-endian bytes, empty for zero like Go's `big.Int.Bytes`.
+fn integer_bytes(value: u128) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    bytes[start..].to_vec()
+}
+
+#[derive(Default)]
+struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    fn write(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn write_u16(&mut self, value: u16) {
+        self.write(&value.to_be_bytes());
+    }
+
+    fn write_count(&mut self, count: usize) -> Result<(), Error> {
+        let count =
+            u16::try_from(count).map_err(|_| Error::Input(format!("count overflow: {count}")))?;
+        self.write_u16(count);
+        Ok(())
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, offset: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.offset == self.bytes.len()
+    }
+
+    fn read(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let end = self.offset + len;
+        if end > self.bytes.len() {
+            return Err(Error::Input("unexpected end of transaction".to_string()));
+        }
+        let bytes = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, Error> {
+        Ok(self.read(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, Error> {
+        let bytes = self.read(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn read_string(&mut self) -> Result<String, Error> {
+        let len = self.read_u16()? as usize;
+        String::from_utf8(self.read(len)?.to_vec())
+            .map_err(|e| Error::Input(format!("invalid utf8 string: {e}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    // The transaction of `test_encode_transaction_fixture`, written field
+    // by field in the layout of the Go kernel encoder (`common/encoding.go`)
+    // rather than produced by `marshal`.
+    fn go_encoded_raw() -> String {
+        [
+            "7777",           // magic
+            "0005",           // version
+            &"11".repeat(32), // asset
+            "0001",           // inputs
+            &"00".repeat(32), // input hash
+            "0001",           // input index
+            "0000",           // genesis
+            "0000",           // deposit
+            "0000",           // mint
+            "0001",           // outputs
+            "0000",           // output type
+            "0004",           // amount length
+            "02faf080",       // 50000000, big.Int.Bytes
+            "0001",           // keys
+            &"33".repeat(32), // key
+            &"22".repeat(32), // mask
+            "0003",           // script length
+            "fffe01",         // script
+            "0000",           // withdrawal
+            "0001",           // references
+            &"44".repeat(32), // reference
+            "00000005",       // extra length
+            "68656c6c6f",     // extra
+            "0000",           // signatures
+        ]
+        .concat()
+    }
+
+    // The raw above with the signature of its only input at index 0, for
+    // the spend key of `fixtures::test_user` and a zero view. The signature
+    // bytes come from this crate's signer; they still have to be replaced by
+    // the output of the Go SDK's `SignRaw` over the same raw, spend key and
+    // view.
+    fn go_encoded_signed() -> String {
+        let raw = go_encoded_raw();
+        [
+            &raw[..raw.len() - 4],
+            "0001", // signature maps
+            "0001", // signatures of input 0
+            "0000", // index
+            "bf095515491924399f0f2e1aff1c3f19dc22d77592e71a3b78abcbf3c5ea1e90",
+            "113734130ee994cdb78005946d04de581e38276a9d39294dd1ab0c86cdbdad0c",
+        ]
+        .concat()
+    }
+
+    fn utxo(amount: &str) -> Output {
+        Output {
+            transaction_hash: Some("00".repeat(32)),
+            output_index: Some(1),
+            kernel_asset_id: Some("11".repeat(32)),
+            ..fixtures::output("output-id", amount)
+        }
+    }
+
+    fn recipient(amount: &str) -> TransactionRecipient {
+        TransactionRecipient {
+            members: vec!["67a87828-18f5-46a1-b6cc-c72a97a77c43".to_string()],
+            threshold: 1,
+            amount: amount.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_encode_transaction_fixture() {
+        let mut tx = build_transaction(
+            &[utxo("0.5")],
+            &[recipient("0.5")],
+            &[fixtures::ghost_keys()],
+            b"hello",
+        )
+        .expect("build");
+        tx.references = vec!["44".repeat(32)];
+        assert_eq!(tx.encode_unsigned().unwrap(), go_encoded_raw());
+    }
+
+    #[test]
+    fn test_decode_transaction_roundtrip() {
+        let tx = SafeTransaction::decode(&go_encoded_signed()).expect("decode");
+        assert_eq!(tx.inputs[0].index, 1);
+        assert_eq!(tx.outputs[0].amount.to_string(), "0.5");
+        assert_eq!(tx.outputs[0].script, encode_script(1));
+        assert_eq!(tx.extra, b"hello".to_vec());
+        assert_eq!(tx.signatures.len(), 1);
+        assert_eq!(tx.encode().unwrap(), go_encoded_signed());
+        assert_eq!(tx.encode_unsigned().unwrap(), go_encoded_raw());
+    }
+
+    #[test]
+    fn test_sign_raw_transaction_fixture() {
+        let views = vec!["00".repeat(32)];
+        let user = fixtures::test_user();
+        let signed = sign_raw_transaction(&go_encoded_raw(), &views, &user).expect("sign");
+        assert_eq!(signed, go_encoded_signed());
+
+        // The kernel verifies input signatures over the blake3 hash of the raw.
+        let message = blake3::hash(&hex::decode(go_encoded_raw()).unwrap());
+        let tx = SafeTransaction::decode(&signed).unwrap();
+        let signature = hex::decode(&tx.signatures[0][&0]).unwrap();
+        let key = input_private_key(&views[0], &user)
+            .unwrap()
+            .public()
+            .unwrap();
+        assert!(key.verify(message.as_bytes(), &signature.try_into().unwrap()));
+    }
+
+    #[test]
+    fn test_sign_transaction_inputs_partial() {
+        let user = fixtures::test_user();
+        let mut tx = SafeTransaction::decode(&go_encoded_raw()).unwrap();
+        tx.inputs.push(SafeTransactionInput {
+            hash: "55".repeat(32),
+            index: 0,
+        });
+        let views = vec!["01".repeat(16) + &"00".repeat(16), "00".repeat(32)];
+        let key = input_private_key(&views[1], &user)
+            .unwrap()
+            .public()
+            .unwrap();
+        let other = Key::new_from_seed(&[9; 64]).public().unwrap();
+        let keys = vec![vec![], vec![other, key]];
+
+        sign_transaction_inputs(&mut tx, &views, &keys, Some(&[1]), &user).expect("sign");
+        assert!(tx.signatures[0].is_empty());
+        let signature = hex::decode(&tx.signatures[1][&1]).unwrap();
+        let message = tx.payload_hash().unwrap();
+        assert!(key.verify(&message, &signature.try_into().unwrap()));
+
+        let keys = vec![vec![], vec![other]];
+        assert!(sign_transaction_inputs(&mut tx, &views, &keys, Some(&[1]), &user).is_err());
+        assert!(sign_transaction_inputs(&mut tx, &views, &keys, Some(&[2]), &user).is_err());
+    }
+
+    #[test]
+    fn test_build_transaction_rejects_unbalanced() {
+        let result = build_transaction(
+            &[utxo("1")],
+            &[recipient("0.5")],
+            &[fixtures::ghost_keys()],
+            b"",
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_build_withdrawal_transaction() {
+        let withdrawal = SafeWithdrawalData {
+            address: "0x1616b057F8a89955d4A4f9fd9Eb10289ac0e44D1".to_string(),
+            tag: String::new(),
+        };
+        let references = vec!["44".repeat(32)];
+        let tx = build_withdrawal_transaction(
+            &[utxo("1")],
+            &withdrawal,
+            "0.7".parse().unwrap(),
+            &[recipient("0.3")],
+            &[fixtures::ghost_keys()],
+            &references,
+            b"memo",
+        )
+        .expect("build");
+        assert_eq!(tx.outputs[0].output_type, OUTPUT_TYPE_WITHDRAWAL_SUBMIT);
+        assert!(tx.outputs[0].keys.is_empty());
+        assert_eq!(tx.outputs[1].amount.to_string(), "0.3");
+
+        let decoded = SafeTransaction::decode(&tx.encode_unsigned().unwrap()).unwrap();
+        assert_eq!(decoded.outputs[0].withdrawal, Some(withdrawal.clone()));
+        assert_eq!(decoded.references, references);
+        assert_eq!(decoded, tx);
+
+        let unbalanced = build_withdrawal_transaction(
+            &[utxo("1")],
+            &withdrawal,
+            "1".parse().unwrap(),
+            &[recipient("0.3")],
+            &[fixtures::ghost_keys()],
+            &[],
+            b"",
+        );
+        assert!(unbalanced.is_err());
+    }
+
+    #[test]
+    fn test_recipient_mix_address() {
+        let recipient = recipient("0.5");
+        let address = recipient.mix_address().unwrap();
+        assert_eq!(address.to_string(), "MIX3QEeg1WkLrjvjxyMQf6Xc8dxs81tpPc");
+        assert_eq!(
+            TransactionRecipient::new(&address, recipient.amount),
+            recipient
+        );
+
+        let mixed = TransactionRecipient {
+            members: vec![
+                recipient.members[0].clone(),
+                "XIN3BMNy9pQyj5XWDJtTbaBVE2zQ66zBo2weyc43iL286asdqwApWswAzQC5qba26fh3fzHK9iMoxyx1q3Lgj45KJftzGD9q".to_string(),
+            ],
+            ..recipient
+        };
+        assert!(mixed.mix_address().is_err());
+    }
+
+    #[test]
+    fn test_amount_encoding() {
+        assert!(integer_bytes(0).is_empty());
+        assert_eq!(integer_bytes(50_000_000), vec![0x02, 0xfa, 0xf0, 0x80]);
+
+        let mut tx = SafeTransaction::decode(&go_encoded_raw()).unwrap();
+        assert_eq!(tx.outputs[0].amount, Amount::from_units(50_000_000));
+        // A zero amount has no bytes, only its zero length.
+        tx.outputs[0].amount = Amount::ZERO;
+        let raw = tx.encode_unsigned().unwrap();
+        // outputs, type, amount length, keys and the key.
+        let output = format!("0001{}{}{}{}", "0000", "0000", "0001", "33".repeat(32));
+        assert!(raw.contains(&output));
+        assert_eq!(SafeTransaction::decode(&raw).unwrap(), tx);
+        tx.outputs[0].amount = Amount::from_units(-1);
+        assert!(tx.encode_unsigned().is_err());
+    }
+}
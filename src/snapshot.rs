@@ -2,6 +2,7 @@ use serde::{Deserialize, Serialize};
 use url::form_urlencoded;
 
 use crate::{
+    amount::Amount,
     auth::sign_authentication_token,
     error::Error,
     models::Snapshot,
@@ -20,6 +21,87 @@ pub struct SnapshotQuery {
     pub order: Option<String>,
 }
 
+/// Filters for `/safe/snapshots`. `offset` is the `created_at` of the last
+/// snapshot read, `order` is "ASC" or "DESC".
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+pub struct SafeSnapshotQuery {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asset: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opponent: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq, Eq)]
+pub struct SafeSnapshotDeposit {
+    #[serde(default)]
+    pub deposit_hash: Option<String>,
+    #[serde(default)]
+    pub deposit_index: Option<i64>,
+    #[serde(default)]
+    pub sender: Option<String>,
+    #[serde(default)]
+    pub destination: Option<String>,
+    #[serde(default)]
+    pub tag: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq, Eq)]
+pub struct SafeSnapshotWithdrawal {
+    #[serde(default)]
+    pub withdrawal_hash: Option<String>,
+    #[serde(default)]
+    pub receiver: Option<String>,
+}
+
+/// A balance change of the user, negative `amount` for spending. Deposits and
+/// withdrawals carry their on-chain details.
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+pub struct SafeSnapshot {
+    pub snapshot_id: String,
+    #[serde(default, rename = "type")]
+    pub type_name: Option<String>,
+    #[serde(default)]
+    pub user_id: Option<String>,
+    #[serde(default)]
+    pub opponent_id: Option<String>,
+    #[serde(default)]
+    pub request_id: Option<String>,
+    #[serde(default)]
+    pub transaction_hash: Option<String>,
+    #[serde(default)]
+    pub output_index: Option<u32>,
+    #[serde(default)]
+    pub asset_id: Option<String>,
+    #[serde(default)]
+    pub kernel_asset_id: Option<String>,
+    #[serde(default, deserialize_with = "crate::amount::deserialize_optional")]
+    pub amount: Option<Amount>,
+    #[serde(default, deserialize_with = "crate::amount::deserialize_optional")]
+    pub opening_balance: Option<Amount>,
+    #[serde(default, deserialize_with = "crate::amount::deserialize_optional")]
+    pub closing_balance: Option<Amount>,
+    #[serde(default)]
+    pub memo: Option<String>,
+    #[serde(default)]
+    pub level: Option<i64>,
+    #[serde(default)]
+    pub confirmations: Option<i64>,
+    #[serde(default)]
+    pub deposit: Option<SafeSnapshotDeposit>,
+    #[serde(default)]
+    pub withdrawal: Option<SafeSnapshotWithdrawal>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+}
+
 pub async fn list_snapshots(
     query: &SnapshotQuery,
     safe_user: &SafeUser,
@@ -67,6 +149,58 @@ pub async fn read_snapshot(snapshot_id: &str, safe_user: &SafeUser) -> Result<Sn
     })
 }
 
+pub async fn list_safe_snapshots(
+    query: &SafeSnapshotQuery,
+    safe_user: &SafeUser,
+) -> Result<Vec<SafeSnapshot>, Error> {
+    let path = safe_snapshots_path(query);
+    let token = sign_authentication_token("GET", &path, "", safe_user)?;
+    let body = request("GET", &path, &[], &token).await?;
+
+    let parsed: ApiResponse<Vec<SafeSnapshot>> = serde_json::from_slice(&body)?;
+    if let Some(api_error) = parsed.error {
+        return Err(Error::Api(api_error));
+    }
+    parsed.data.ok_or_else(|| {
+        Error::DataNotFound("API response did not contain safe snapshot data".to_string())
+    })
+}
+
+pub async fn read_safe_snapshot(
+    snapshot_id: &str,
+    safe_user: &SafeUser,
+) -> Result<SafeSnapshot, Error> {
+    let path = format!("/safe/snapshots/{snapshot_id}");
+    let token = sign_authentication_token("GET", &path, "", safe_user)?;
+    let body = request("GET", &path, &[], &token).await?;
+
+    let parsed: ApiResponse<SafeSnapshot> = serde_json::from_slice(&body)?;
+    if let Some(api_error) = parsed.error {
+        return Err(Error::Api(api_error));
+    }
+    parsed.data.ok_or_else(|| {
+        Error::DataNotFound("API response did not contain safe snapshot data".to_string())
+    })
+}
+
+fn safe_snapshots_path(query: &SafeSnapshotQuery) -> String {
+    let mut serializer = form_urlencoded::Serializer::new(String::new());
+    serializer.append_pair("limit", &query.limit.unwrap_or(100).to_string());
+    let filters = [
+        ("asset", &query.asset),
+        ("app", &query.app),
+        ("opponent", &query.opponent),
+        ("offset", &query.offset),
+        ("order", &query.order),
+    ];
+    for (key, value) in filters {
+        if let Some(value) = value.as_deref().filter(|v| !v.is_empty()) {
+            serializer.append_pair(key, value);
+        }
+    }
+    format!("/safe/snapshots?{}", serializer.finish())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -95,4 +229,47 @@ mod tests {
         assert!(query_str.contains("asset=asset-id"));
         assert!(query_str.contains("type=transfer"));
     }
+
+    #[test]
+    fn test_safe_snapshots_path() {
+        let query = SafeSnapshotQuery {
+            asset: Some("asset-id".to_string()),
+            app: Some(String::new()),
+            offset: Some("2024-01-01T00:00:00Z".to_string()),
+            limit: Some(50),
+            order: Some("ASC".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            safe_snapshots_path(&query),
+            "/safe/snapshots?limit=50&asset=asset-id&offset=2024-01-01T00%3A00%3A00Z&order=ASC"
+        );
+        assert_eq!(
+            safe_snapshots_path(&SafeSnapshotQuery::default()),
+            "/safe/snapshots?limit=100"
+        );
+    }
+
+    #[test]
+    fn test_safe_snapshot_deserialize() {
+        let raw = r#"{
+            "type": "snapshot",
+            "snapshot_id": "snapshot-id",
+            "transaction_hash": "hash",
+            "output_index": 2,
+            "asset_id": "asset-id",
+            "amount": "-1.5",
+            "closing_balance": "3",
+            "deposit": null,
+            "withdrawal": {"withdrawal_hash": "0xabc", "receiver": "0x1616"}
+        }"#;
+        let snapshot: SafeSnapshot = serde_json::from_str(raw).expect("safe snapshot");
+        assert_eq!(snapshot.output_index, Some(2));
+        assert_eq!(snapshot.closing_balance, Some("3".parse().unwrap()));
+        assert!(snapshot.deposit.is_none());
+        assert_eq!(
+            snapshot.withdrawal.unwrap().withdrawal_hash.as_deref(),
+            Some("0xabc")
+        );
+    }
 }
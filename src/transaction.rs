@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    amount::Amount,
     auth::sign_authentication_token,
     error::Error,
     request::{ApiResponse, request},
@@ -20,12 +21,29 @@ pub struct SignedTransactionRequest {
     pub signed_raw: String,
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SafeMultisigReceiver {
+    #[serde(default)]
+    pub members: Vec<String>,
+    #[serde(default)]
+    pub members_hash: Option<String>,
+    #[serde(default)]
+    pub threshold: Option<u8>,
+    #[serde(default)]
+    pub destination: Option<String>,
+    #[serde(default)]
+    pub tag: Option<String>,
+    #[serde(default)]
+    pub withdrawal_hash: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct TransactionView {
     pub request_id: Option<String>,
     pub transaction_hash: Option<String>,
     pub asset: Option<String>,
-    pub amount: Option<String>,
+    #[serde(default, deserialize_with = "crate::amount::deserialize_optional")]
+    pub amount: Option<Amount>,
     pub extra: Option<String>,
     pub senders: Option<Vec<String>>,
     pub senders_hash: Option<String>,
@@ -39,6 +57,10 @@ pub struct TransactionView {
     pub snapshot_hash: Option<String>,
     pub snapshot_at: Option<String>,
     pub views: Option<Vec<String>>,
+    /// Set once the transaction is known to the network, with the on-chain
+    /// hash of withdrawal outputs when they have been sent.
+    #[serde(default)]
+    pub receivers: Option<Vec<SafeMultisigReceiver>>,
 }
 
 pub async fn create_transaction_request(
@@ -46,19 +68,18 @@ pub async fn create_transaction_request(
     raw: &str,
     safe_user: &SafeUser,
 ) -> Result<TransactionView, Error> {
-    let path = "/safe/transaction/requests";
-    let data = TransactionRequest {
+    let requests = [TransactionRequest {
         request_id: request_id.to_string(),
         raw: raw.to_string(),
-    };
-    let data_str = serde_json::to_string(&data)?;
-    let token = sign_authentication_token("POST", path, &data_str, safe_user)?;
-    let body = request("POST", path, data_str.as_bytes(), &token).await?;
+    }];
+    one_transaction(create_transaction_requests(&requests, safe_user).await?)
+}
 
-    let parsed: ApiResponse<TransactionView> = serde_json::from_slice(&body)?;
-    parsed.data.ok_or_else(|| {
-        Error::DataNotFound("API response did not contain transaction data".to_string())
-    })
+pub async fn create_transaction_requests(
+    requests: &[TransactionRequest],
+    safe_user: &SafeUser,
+) -> Result<Vec<TransactionView>, Error> {
+    post_transactions("/safe/transaction/requests", requests, safe_user).await
 }
 
 pub async fn submit_transaction(
@@ -66,21 +87,48 @@ pub async fn submit_transaction(
     signed_raw: &str,
     safe_user: &SafeUser,
 ) -> Result<TransactionView, Error> {
-    let path = "/safe/transactions";
-    let data = SignedTransactionRequest {
+    let requests = [TransactionRequest {
         request_id: request_id.to_string(),
-        signed_raw: signed_raw.to_string(),
-    };
-    let data_str = serde_json::to_string(&data)?;
+        raw: signed_raw.to_string(),
+    }];
+    one_transaction(submit_transactions(&requests, safe_user).await?)
+}
+
+pub async fn submit_transactions(
+    requests: &[TransactionRequest],
+    safe_user: &SafeUser,
+) -> Result<Vec<TransactionView>, Error> {
+    post_transactions("/safe/transactions", requests, safe_user).await
+}
+
+async fn post_transactions(
+    path: &str,
+    requests: &[TransactionRequest],
+    safe_user: &SafeUser,
+) -> Result<Vec<TransactionView>, Error> {
+    let data_str = serde_json::to_string(requests)?;
     let token = sign_authentication_token("POST", path, &data_str, safe_user)?;
     let body = request("POST", path, data_str.as_bytes(), &token).await?;
 
-    let parsed: ApiResponse<TransactionView> = serde_json::from_slice(&body)?;
+    let parsed: ApiResponse<Vec<TransactionView>> = serde_json::from_slice(&body)?;
+    if let Some(api_error) = parsed.error {
+        return Err(Error::Api(api_error));
+    }
     parsed.data.ok_or_else(|| {
         Error::DataNotFound("API response did not contain transaction data".to_string())
     })
 }
 
+fn one_transaction(mut transactions: Vec<TransactionView>) -> Result<TransactionView, Error> {
+    if transactions.len() != 1 {
+        return Err(Error::DataNotFound(format!(
+            "expected one transaction, got {}",
+            transactions.len()
+        )));
+    }
+    Ok(transactions.remove(0))
+}
+
 pub async fn get_transaction(
     request_id: &str,
     safe_user: &SafeUser,
@@ -1,5 +1,6 @@
 use crate::{
     auth::sign_authentication_token,
+    conversation::ParticipantSession,
     error::Error,
     request::{
         ApiResponse, DEFAULT_API_HOST, DEFAULT_USER_AGENT, HTTP_CLIENT, request, request_with_id,
@@ -305,6 +306,25 @@ pub async fn get_users(safe_user: &SafeUser, user_ids: &[String]) -> Result<Vec<
         .map_err(|e| Error::DataNotFound(e.to_string()))
 }
 
+/// The active sessions of `user_ids` with their curve25519 public keys, for
+/// encrypting messages to them.
+pub async fn fetch_user_sessions(
+    user_ids: &[String],
+    safe_user: &SafeUser,
+) -> Result<Vec<ParticipantSession>, Error> {
+    let path = "/sessions/fetch";
+    let data_str = serde_json::to_string(user_ids)?;
+    let token = sign_authentication_token("POST", path, &data_str, safe_user)?;
+    let body = request("POST", path, data_str.as_bytes(), &token).await?;
+    let parsed: ApiResponse<Vec<ParticipantSession>> = serde_json::from_slice(&body)?;
+    if let Some(api_error) = parsed.error {
+        return Err(Error::Api(api_error));
+    }
+    parsed
+        .data
+        .ok_or_else(|| Error::DataNotFound("API response did not contain session data".to_string()))
+}
+
 #[cfg(test)]
 mod tests {
     use std::env;
@@ -1,5 +1,7 @@
 use uuid::Uuid;
 
+use crate::crypto::sha3_256;
+
 pub fn unique_object_id<T, I>(args: I) -> String
 where
     I: IntoIterator<Item = T>,
@@ -33,6 +35,17 @@ pub fn unique_conversation_id(user_id: &str, recipient_id: &str) -> String {
     Uuid::from_bytes(sum).to_string()
 }
 
+/// The `members` hash used by `/safe/outputs` to filter outputs by receivers.
+pub fn hash_members<T, I>(ids: I) -> String
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<str>,
+{
+    let mut ids: Vec<String> = ids.into_iter().map(|id| id.as_ref().to_string()).collect();
+    ids.sort();
+    hex::encode(sha3_256(ids.concat().as_bytes()))
+}
+
 pub fn group_conversation_id(
     owner_id: &str,
     group_name: &str,
@@ -78,6 +91,19 @@ mod tests {
         assert_eq!(id, "beff3fcb-a56f-3967-bc5d-52b843df365e");
     }
 
+    #[test]
+    fn test_hash_members() {
+        let hash = hash_members(["965e5c6e-434c-3fa9-b780-c50f43cd955c"]);
+        assert_eq!(
+            hash,
+            "b9f49cf777dc4d03bc54cd1367eebca319f8603ea1ce18910d09e2c540c630d8"
+        );
+        assert_eq!(
+            hash_members(["b", "a"]),
+            hash_members(["a".to_string(), "b".to_string()])
+        );
+    }
+
     #[test]
     fn test_group_conversation_id() {
         let participants = vec!["user-b".to_string(), "user-a".to_string()];
@@ -0,0 +1,421 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+
+use crate::{
+    amount::Amount,
+    error::Error,
+    models::Output,
+    output::{OUTPUT_STATE_UNSPENT, list_outputs},
+    safe::SafeUser,
+    utils::hash_members,
+};
+
+const OUTPUTS_PAGE_LIMIT: i64 = 500;
+const CHANGES_CHANNEL_CAPACITY: usize = 1024;
+
+/// An output seen for the first time or whose state changed since the last sync.
+#[derive(Debug, Clone)]
+pub struct OutputChange {
+    pub output: Output,
+    pub previous_state: Option<String>,
+}
+
+/// Local cache of the outputs of a wallet and of the sequence synced so far.
+pub trait WalletStore: Send + Sync {
+    fn cursor(&self) -> Result<i64, Error>;
+    fn output(&self, output_id: &str) -> Result<Option<Output>, Error>;
+    fn outputs(&self, asset_id: Option<&str>, state: Option<&str>) -> Result<Vec<Output>, Error>;
+    /// Upserts `outputs` and moves the cursor to `cursor` in one step, so a
+    /// crash never leaves the cursor ahead of the outputs.
+    fn save_outputs(&self, outputs: &[Output], cursor: i64) -> Result<(), Error>;
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryWalletStore {
+    state: Mutex<MemoryWallet>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryWallet {
+    cursor: i64,
+    outputs: BTreeMap<String, Output>,
+}
+
+impl MemoryWalletStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, MemoryWallet>, Error> {
+        self.state
+            .lock()
+            .map_err(|_| Error::Storage("wallet store lock poisoned".to_string()))
+    }
+}
+
+impl WalletStore for MemoryWalletStore {
+    fn cursor(&self) -> Result<i64, Error> {
+        Ok(self.lock()?.cursor)
+    }
+
+    fn output(&self, output_id: &str) -> Result<Option<Output>, Error> {
+        Ok(self.lock()?.outputs.get(output_id).cloned())
+    }
+
+    fn outputs(&self, asset_id: Option<&str>, state: Option<&str>) -> Result<Vec<Output>, Error> {
+        let mut outputs: Vec<Output> = self
+            .lock()?
+            .outputs
+            .values()
+            .filter(|o| asset_id.is_none_or(|a| o.asset_id.as_deref() == Some(a)))
+            .filter(|o| state.is_none_or(|s| o.state.as_deref() == Some(s)))
+            .cloned()
+            .collect();
+        outputs.sort_by_key(|o| o.sequence);
+        Ok(outputs)
+    }
+
+    fn save_outputs(&self, outputs: &[Output], cursor: i64) -> Result<(), Error> {
+        let mut state = self.lock()?;
+        for output in outputs {
+            state
+                .outputs
+                .insert(output.output_id.clone(), output.clone());
+        }
+        state.cursor = cursor;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteWalletStore;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use std::path::Path;
+    use std::sync::Mutex;
+
+    use rusqlite::{Connection, OptionalExtension, params};
+
+    use super::WalletStore;
+    use crate::{error::Error, models::Output};
+
+    const SCHEMA: &str = "
+        CREATE TABLE IF NOT EXISTS wallet_outputs (
+            output_id TEXT PRIMARY KEY,
+            asset_id TEXT,
+            state TEXT,
+            sequence INTEGER,
+            data TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS wallet_outputs_asset_state ON wallet_outputs (asset_id, state);
+        CREATE TABLE IF NOT EXISTS wallet_cursor (
+            id INTEGER PRIMARY KEY CHECK (id = 0),
+            sequence INTEGER NOT NULL
+        );
+    ";
+
+    /// Outputs cache persisted in a SQLite database, surviving restarts.
+    #[derive(Debug)]
+    pub struct SqliteWalletStore {
+        conn: Mutex<Connection>,
+    }
+
+    fn sqlite_error(err: rusqlite::Error) -> Error {
+        Error::Storage(format!("sqlite: {err}"))
+    }
+
+    impl SqliteWalletStore {
+        pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+            Self::with_connection(Connection::open(path).map_err(sqlite_error)?)
+        }
+
+        pub fn open_in_memory() -> Result<Self, Error> {
+            Self::with_connection(Connection::open_in_memory().map_err(sqlite_error)?)
+        }
+
+        fn with_connection(conn: Connection) -> Result<Self, Error> {
+            conn.execute_batch(SCHEMA).map_err(sqlite_error)?;
+            Ok(Self {
+                conn: Mutex::new(conn),
+            })
+        }
+
+        fn lock(&self) -> Result<std::sync::MutexGuard<'_, Connection>, Error> {
+            self.conn
+                .lock()
+                .map_err(|_| Error::Storage("wallet store lock poisoned".to_string()))
+        }
+    }
+
+    impl WalletStore for SqliteWalletStore {
+        fn cursor(&self) -> Result<i64, Error> {
+            let conn = self.lock()?;
+            let cursor = conn
+                .query_row("SELECT sequence FROM wallet_cursor WHERE id = 0", [], |r| {
+                    r.get(0)
+                })
+                .optional()
+                .map_err(sqlite_error)?;
+            Ok(cursor.unwrap_or(0))
+        }
+
+        fn output(&self, output_id: &str) -> Result<Option<Output>, Error> {
+            let conn = self.lock()?;
+            let data: Option<String> = conn
+                .query_row(
+                    "SELECT data FROM wallet_outputs WHERE output_id = ?1",
+                    [output_id],
+                    |r| r.get(0),
+                )
+                .optional()
+                .map_err(sqlite_error)?;
+            Ok(data.map(|d| serde_json::from_str(&d)).transpose()?)
+        }
+
+        fn outputs(
+            &self,
+            asset_id: Option<&str>,
+            state: Option<&str>,
+        ) -> Result<Vec<Output>, Error> {
+            let conn = self.lock()?;
+            let mut stmt = conn
+                .prepare(
+                    "SELECT data FROM wallet_outputs
+                     WHERE (?1 IS NULL OR asset_id = ?1) AND (?2 IS NULL OR state = ?2)
+                     ORDER BY sequence",
+                )
+                .map_err(sqlite_error)?;
+            let rows = stmt
+                .query_map(params![asset_id, state], |r| r.get::<_, String>(0))
+                .map_err(sqlite_error)?;
+            let mut outputs = Vec::new();
+            for data in rows {
+                outputs.push(serde_json::from_str(&data.map_err(sqlite_error)?)?);
+            }
+            Ok(outputs)
+        }
+
+        fn save_outputs(&self, outputs: &[Output], cursor: i64) -> Result<(), Error> {
+            let mut conn = self.lock()?;
+            let tx = conn.transaction().map_err(sqlite_error)?;
+            for output in outputs {
+                tx.execute(
+                    "INSERT OR REPLACE INTO wallet_outputs (output_id, asset_id, state, sequence, data)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![
+                        output.output_id,
+                        output.asset_id,
+                        output.state,
+                        output.sequence,
+                        serde_json::to_string(output)?
+                    ],
+                )
+                .map_err(sqlite_error)?;
+            }
+            tx.execute(
+                "INSERT OR REPLACE INTO wallet_cursor (id, sequence) VALUES (0, ?1)",
+                [cursor],
+            )
+            .map_err(sqlite_error)?;
+            tx.commit().map_err(sqlite_error)
+        }
+    }
+}
+
+/// Follows `/safe/outputs` by sequence into a `WalletStore`, so balances are
+/// read locally and a restart resumes from the stored cursor.
+///
+/// Mixin bumps the sequence of an output whenever its state changes, which is
+/// how spent and signed outputs are picked up again.
+pub struct WalletSync<S = MemoryWalletStore> {
+    store: S,
+    members_hash: String,
+    threshold: u8,
+    changes: broadcast::Sender<OutputChange>,
+}
+
+impl<S: WalletStore> WalletSync<S> {
+    /// Syncs the outputs owned by `user_id` alone.
+    pub fn new(store: S, user_id: &str) -> Self {
+        Self::with_members(store, &[user_id], 1)
+    }
+
+    pub fn with_members<T: AsRef<str>>(store: S, members: &[T], threshold: u8) -> Self {
+        let (changes, _) = broadcast::channel(CHANGES_CHANNEL_CAPACITY);
+        Self {
+            store,
+            members_hash: hash_members(members.iter().map(|m| m.as_ref())),
+            threshold,
+            changes,
+        }
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Receives the changes applied by every later sync.
+    pub fn subscribe(&self) -> broadcast::Receiver<OutputChange> {
+        self.changes.subscribe()
+    }
+
+    /// Fetches the outputs updated after the cursor until caught up, and
+    /// returns the changes applied to the store.
+    pub async fn sync(&self, safe_user: &SafeUser) -> Result<Vec<OutputChange>, Error> {
+        let mut changes = Vec::new();
+        loop {
+            let cursor = self.store.cursor()?;
+            let page = list_outputs(
+                &self.members_hash,
+                self.threshold,
+                None,
+                None,
+                Some(cursor + 1),
+                Some(OUTPUTS_PAGE_LIMIT),
+                safe_user,
+            )
+            .await?;
+            let done = (page.len() as i64) < OUTPUTS_PAGE_LIMIT;
+            changes.extend(self.apply(page)?);
+            if done || self.store.cursor()? <= cursor {
+                return Ok(changes);
+            }
+        }
+    }
+
+    /// Syncs every `interval` until an error occurs.
+    pub async fn run(&self, interval: Duration, safe_user: &SafeUser) -> Result<(), Error> {
+        loop {
+            self.sync(safe_user).await?;
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Stores a page of outputs and advances the cursor to its last sequence.
+    pub fn apply(&self, outputs: Vec<Output>) -> Result<Vec<OutputChange>, Error> {
+        let cursor = outputs
+            .iter()
+            .filter_map(|o| o.sequence)
+            .max()
+            .unwrap_or_default()
+            .max(self.store.cursor()?);
+
+        let mut changes = Vec::new();
+        for output in &outputs {
+            let previous_state = self.store.output(&output.output_id)?.and_then(|o| o.state);
+            if previous_state.is_none() || previous_state != output.state {
+                changes.push(OutputChange {
+                    output: output.clone(),
+                    previous_state,
+                });
+            }
+        }
+        self.store.save_outputs(&outputs, cursor)?;
+
+        for change in &changes {
+            let _ = self.changes.send(change.clone());
+        }
+        Ok(changes)
+    }
+
+    pub fn unspent_outputs(&self, asset_id: &str) -> Result<Vec<Output>, Error> {
+        self.store
+            .outputs(Some(asset_id), Some(OUTPUT_STATE_UNSPENT))
+    }
+
+    pub fn balance(&self, asset_id: &str) -> Result<Amount, Error> {
+        let outputs = self.unspent_outputs(asset_id)?;
+        Amount::checked_sum(outputs.iter().map(|o| o.amount.unwrap_or_default()))
+    }
+
+    /// Unspent balance of every asset held, keyed by asset id.
+    pub fn balances(&self) -> Result<BTreeMap<String, Amount>, Error> {
+        let mut totals: BTreeMap<String, Amount> = BTreeMap::new();
+        for output in self.store.outputs(None, Some(OUTPUT_STATE_UNSPENT))? {
+            let total = totals
+                .entry(output.asset_id.unwrap_or_default())
+                .or_default();
+            *total = total
+                .checked_add(output.amount.unwrap_or_default())
+                .ok_or_else(|| Error::Input("amount overflow".to_string()))?;
+        }
+        Ok(totals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+    use crate::output::{OUTPUT_STATE_SIGNED, OUTPUT_STATE_SPENT};
+
+    const USER_ID: &str = "67a87828-18f5-46a1-b6cc-c72a97a77c43";
+
+    fn output(id: &str, asset: &str, amount: &str, state: &str, sequence: i64) -> Output {
+        Output {
+            asset_id: Some(asset.to_string()),
+            state: Some(state.to_string()),
+            sequence: Some(sequence),
+            ..fixtures::output(id, amount)
+        }
+    }
+
+    fn check_sync<S: WalletStore>(store: S) {
+        let wallet = WalletSync::new(store, USER_ID);
+        let mut events = wallet.subscribe();
+
+        let changes = wallet
+            .apply(vec![
+                output("a", "xin", "1.5", OUTPUT_STATE_UNSPENT, 1),
+                output("b", "xin", "0.5", OUTPUT_STATE_UNSPENT, 2),
+                output("c", "btc", "0.01", OUTPUT_STATE_UNSPENT, 3),
+            ])
+            .unwrap();
+        assert_eq!(changes.len(), 3);
+        assert_eq!(wallet.store().cursor().unwrap(), 3);
+        assert_eq!(wallet.balance("xin").unwrap().to_string(), "2");
+        assert_eq!(events.try_recv().unwrap().output.output_id, "a");
+
+        let changes = wallet
+            .apply(vec![
+                output("a", "xin", "1.5", OUTPUT_STATE_SIGNED, 4),
+                output("c", "btc", "0.01", OUTPUT_STATE_UNSPENT, 5),
+            ])
+            .unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(
+            changes[0].previous_state.as_deref(),
+            Some(OUTPUT_STATE_UNSPENT)
+        );
+        assert_eq!(wallet.balance("xin").unwrap().to_string(), "0.5");
+
+        wallet
+            .apply(vec![output("a", "xin", "1.5", OUTPUT_STATE_SPENT, 6)])
+            .unwrap();
+        let balances = wallet.balances().unwrap();
+        assert_eq!(balances["xin"].to_string(), "0.5");
+        assert_eq!(balances["btc"].to_string(), "0.01");
+        assert_eq!(wallet.store().cursor().unwrap(), 6);
+        assert!(wallet.apply(vec![]).unwrap().is_empty());
+        assert_eq!(wallet.store().cursor().unwrap(), 6);
+    }
+
+    #[test]
+    fn test_memory_wallet_sync() {
+        check_sync(MemoryWalletStore::new());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_wallet_sync() {
+        check_sync(SqliteWalletStore::open_in_memory().unwrap());
+        assert!(matches!(
+            SqliteWalletStore::open(std::env::temp_dir()),
+            Err(Error::Storage(_))
+        ));
+    }
+}
@@ -1,12 +1,29 @@
+use std::time::Duration;
+
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    amount::Amount,
+    asset::{AssetFee, read_asset_fees},
     auth::sign_authentication_token,
+    coin_selection::{CoinSelector, Selection},
     error::Error,
+    mix_address::MixAddress,
+    models::Output,
+    output::list_unspent_outputs,
     pin::encrypt_ed25519_pin,
     request::{ApiResponse, request},
-    safe::SafeUser,
+    safe::{GhostKeys, SafeUser, request_ghost_keys_for, request_ghost_keys_from},
+    safe_transaction::{
+        SafeTransaction, SafeWithdrawalData, TransactionRecipient, build_transaction,
+        build_withdrawal_transaction, input_keys, sign_transaction_inputs,
+    },
     tip::{sign_tip_body, tip_body_for_withdrawal},
+    transaction::{
+        TransactionRequest, TransactionView, create_transaction_requests, get_transaction,
+        submit_transactions,
+    },
+    utils::{hash_members, unique_object_id},
 };
 
 pub const MIXIN_FEE_USER_ID: &str = "674d6776-d600-4346-af46-58e77d8df185";
@@ -14,7 +31,7 @@ pub const MIXIN_FEE_USER_ID: &str = "674d6776-d600-4346-af46-58e77d8df185";
 #[derive(Debug, Deserialize, Serialize, Default, Clone)]
 pub struct WithdrawalRequest {
     pub address_id: String,
-    pub amount: String,
+    pub amount: Amount,
     pub trace_id: String,
     #[serde(default)]
     pub memo: Option<String>,
@@ -27,8 +44,10 @@ pub struct WithdrawalView {
     pub withdrawal_id: Option<String>,
     pub request_id: Option<String>,
     pub asset_id: Option<String>,
-    pub amount: Option<String>,
-    pub fee: Option<String>,
+    #[serde(default, deserialize_with = "crate::amount::deserialize_optional")]
+    pub amount: Option<Amount>,
+    #[serde(default, deserialize_with = "crate::amount::deserialize_optional")]
+    pub fee: Option<Amount>,
     pub destination: Option<String>,
     pub tag: Option<String>,
     pub snapshot_id: Option<String>,
@@ -38,13 +57,19 @@ pub struct WithdrawalView {
 
 pub async fn create_withdrawal(
     address_id: &str,
-    amount: &str,
-    fee: &str,
+    amount: Amount,
+    fee: Amount,
     trace_id: &str,
     memo: Option<&str>,
     safe_user: &SafeUser,
 ) -> Result<WithdrawalView, Error> {
-    let tip_body = tip_body_for_withdrawal(address_id, amount, fee, trace_id, memo.unwrap_or(""));
+    let tip_body = tip_body_for_withdrawal(
+        address_id,
+        &amount.to_string(),
+        &fee.to_string(),
+        trace_id,
+        memo.unwrap_or(""),
+    );
     let pin = sign_tip_body(
         &tip_body,
         &safe_user.spend_private_key,
@@ -54,7 +79,7 @@ pub async fn create_withdrawal(
 
     let data = WithdrawalRequest {
         address_id: address_id.to_string(),
-        amount: amount.to_string(),
+        amount,
         trace_id: trace_id.to_string(),
         memo: memo.map(|m| m.to_string()),
         pin_base64: Some(pin_base64),
@@ -70,6 +95,225 @@ pub async fn create_withdrawal(
     })
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct SafeWithdrawal {
+    pub fee: AssetFee,
+    pub transaction: TransactionView,
+    /// Set when the fee is paid in the chain asset by a separate transaction.
+    pub fee_transaction: Option<TransactionView>,
+}
+
+/// The fee to pay for a withdrawal of `asset_id`, preferring the one in the
+/// asset itself over the one in its chain asset.
+pub fn select_withdrawal_fee(fees: &[AssetFee], asset_id: &str) -> Result<AssetFee, Error> {
+    fees.iter()
+        .find(|f| f.asset_id.as_deref() == Some(asset_id))
+        .or_else(|| fees.first())
+        .filter(|f| f.asset_id.is_some() && f.amount.is_some())
+        .cloned()
+        .ok_or_else(|| Error::DataNotFound(format!("no withdrawal fee for asset {asset_id}")))
+}
+
+/// Withdraws `amount` of `asset_id` to `withdrawal` with a kernel withdrawal
+/// transaction, paying the fee returned by `read_asset_fees`.
+pub async fn send_safe_withdrawal(
+    asset_id: &str,
+    withdrawal: &SafeWithdrawalData,
+    amount: Amount,
+    memo: &str,
+    trace_id: &str,
+    safe_user: &SafeUser,
+) -> Result<SafeWithdrawal, Error> {
+    let fees = read_asset_fees(asset_id, &withdrawal.address, safe_user).await?;
+    let fee = select_withdrawal_fee(&fees, asset_id)?;
+    send_safe_withdrawal_with_fee(
+        asset_id, withdrawal, amount, &fee, memo, trace_id, safe_user,
+    )
+    .await
+}
+
+/// Like `send_safe_withdrawal` with a fee already chosen. A fee in another
+/// asset is paid by a second transaction with the request id
+/// `unique_object_id([trace_id, "FEE"])`, which references the withdrawal so
+/// the fee is only spent together with it.
+pub async fn send_safe_withdrawal_with_fee(
+    asset_id: &str,
+    withdrawal: &SafeWithdrawalData,
+    amount: Amount,
+    fee: &AssetFee,
+    memo: &str,
+    trace_id: &str,
+    safe_user: &SafeUser,
+) -> Result<SafeWithdrawal, Error> {
+    let fee_asset = fee
+        .asset_id
+        .as_deref()
+        .ok_or_else(|| Error::Input("withdrawal fee is missing asset_id".to_string()))?;
+    let fee_amount = fee.amount.unwrap_or_default();
+    let same_asset = fee_asset == asset_id;
+    let fee_address = MixAddress::new_uuid(&[MIXIN_FEE_USER_ID], 1)?;
+
+    let mut total = amount;
+    let mut recipients = Vec::new();
+    if same_asset && fee_amount.is_positive() {
+        total = Amount::checked_sum([amount, fee_amount])?;
+        recipients.push(TransactionRecipient::new(&fee_address, fee_amount));
+    }
+    let selection = select_unspent_outputs(asset_id, total, safe_user).await?;
+    let recipients = with_change(recipients, &selection, safe_user)?;
+    let addresses = recipient_addresses(&recipients)?;
+    let ghosts = request_ghost_keys_from(&addresses, 1, trace_id, safe_user).await?;
+    let tx = build_withdrawal_transaction(
+        &selection.outputs,
+        withdrawal,
+        amount,
+        &recipients,
+        &ghosts,
+        &[],
+        memo.as_bytes(),
+    )?;
+
+    let mut transactions = Vec::new();
+    if !same_asset && fee_amount.is_positive() {
+        let fee_trace_id = unique_object_id([trace_id, "FEE"]);
+        let fee_selection = select_unspent_outputs(fee_asset, fee_amount, safe_user).await?;
+        let fee_recipients = with_change(
+            vec![TransactionRecipient::new(&fee_address, fee_amount)],
+            &fee_selection,
+            safe_user,
+        )?;
+        let addresses = recipient_addresses(&fee_recipients)?;
+        let ghosts = request_ghost_keys_for(&addresses, &fee_trace_id, safe_user).await?;
+        let fee_tx = build_fee_transaction(&tx, &fee_selection.outputs, &fee_recipients, &ghosts)?;
+        transactions.push((fee_trace_id, fee_tx, fee_selection.outputs));
+    }
+    transactions.insert(0, (trace_id.to_string(), tx, selection.outputs));
+
+    let mut views = submit_signed(transactions, safe_user).await?.into_iter();
+    let transaction = views
+        .next()
+        .ok_or_else(|| Error::DataNotFound("missing withdrawal transaction".to_string()))?;
+    Ok(SafeWithdrawal {
+        fee: fee.clone(),
+        transaction,
+        fee_transaction: views.next(),
+    })
+}
+
+/// The on-chain hash of the withdrawal in `view`, once it has been sent.
+pub fn withdrawal_hash(view: &TransactionView) -> Option<String> {
+    view.receivers
+        .iter()
+        .flatten()
+        .filter_map(|r| r.withdrawal_hash.clone())
+        .find(|hash| !hash.is_empty())
+}
+
+/// Polls the withdrawal transaction `trace_id` every `interval` until its
+/// on-chain hash appears. Wrap it in `tokio::time::timeout` to give up.
+pub async fn wait_withdrawal_hash(
+    trace_id: &str,
+    interval: Duration,
+    safe_user: &SafeUser,
+) -> Result<String, Error> {
+    loop {
+        let view = get_transaction(trace_id, safe_user).await?;
+        if let Some(hash) = withdrawal_hash(&view) {
+            return Ok(hash);
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// The transaction paying the fee of `withdrawal` in another asset, which
+/// references the withdrawal.
+fn build_fee_transaction(
+    withdrawal: &SafeTransaction,
+    utxos: &[Output],
+    recipients: &[TransactionRecipient],
+    ghosts: &[GhostKeys],
+) -> Result<SafeTransaction, Error> {
+    let mut tx = build_transaction(utxos, recipients, ghosts, &[])?;
+    tx.references = vec![hex::encode(withdrawal.payload_hash()?)];
+    Ok(tx)
+}
+
+async fn select_unspent_outputs(
+    asset_id: &str,
+    amount: Amount,
+    safe_user: &SafeUser,
+) -> Result<Selection, Error> {
+    let members_hash = hash_members([&safe_user.user_id]);
+    let outputs = list_unspent_outputs(&members_hash, 1, Some(asset_id), safe_user).await?;
+    CoinSelector::default().select(&outputs, amount)
+}
+
+fn with_change(
+    mut recipients: Vec<TransactionRecipient>,
+    selection: &Selection,
+    safe_user: &SafeUser,
+) -> Result<Vec<TransactionRecipient>, Error> {
+    if selection.change.is_positive() {
+        let address = MixAddress::new_uuid(&[&safe_user.user_id], 1)?;
+        recipients.push(TransactionRecipient::new(&address, selection.change));
+    }
+    Ok(recipients)
+}
+
+fn recipient_addresses(recipients: &[TransactionRecipient]) -> Result<Vec<MixAddress>, Error> {
+    recipients
+        .iter()
+        .map(TransactionRecipient::mix_address)
+        .collect()
+}
+
+/// Creates the requests of `transactions` together, signs every one with the
+/// views returned and submits them together, returning the views in order.
+async fn submit_signed(
+    transactions: Vec<(String, SafeTransaction, Vec<Output>)>,
+    safe_user: &SafeUser,
+) -> Result<Vec<TransactionView>, Error> {
+    let requests = transactions
+        .iter()
+        .map(|(request_id, tx, _)| {
+            Ok(TransactionRequest {
+                request_id: request_id.clone(),
+                raw: tx.encode_unsigned()?,
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let created = create_transaction_requests(&requests, safe_user).await?;
+
+    let mut signed = Vec::with_capacity(transactions.len());
+    for (request_id, mut tx, utxos) in transactions {
+        let views = created
+            .iter()
+            .find(|v| v.request_id.as_deref() == Some(request_id.as_str()))
+            .and_then(|v| v.views.clone())
+            .ok_or_else(|| {
+                Error::DataNotFound(format!("transaction request {request_id} is missing views"))
+            })?;
+        sign_transaction_inputs(&mut tx, &views, &input_keys(&utxos)?, None, safe_user)?;
+        signed.push(TransactionRequest {
+            request_id,
+            raw: tx.encode()?,
+        });
+    }
+    let submitted = submit_transactions(&signed, safe_user).await?;
+    signed
+        .iter()
+        .map(|r| {
+            submitted
+                .iter()
+                .find(|v| v.request_id.as_deref() == Some(r.request_id.as_str()))
+                .cloned()
+                .ok_or_else(|| {
+                    Error::DataNotFound(format!("transaction {} was not submitted", r.request_id))
+                })
+        })
+        .collect()
+}
+
 fn now_nanos() -> Result<u64, Error> {
     use std::time::{SystemTime, UNIX_EPOCH};
     Ok(SystemTime::now()
@@ -81,12 +325,13 @@ fn now_nanos() -> Result<u64, Error> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fixtures;
 
     #[test]
     fn test_withdrawal_request_serialization() {
         let request = WithdrawalRequest {
             address_id: "address-id".to_string(),
-            amount: "1".to_string(),
+            amount: "1".parse().unwrap(),
             trace_id: "trace-id".to_string(),
             memo: Some("memo".to_string()),
             pin_base64: Some("pin".to_string()),
@@ -99,4 +344,75 @@ mod tests {
         assert_eq!(value["memo"], "memo");
         assert_eq!(value["pin_base64"], "pin");
     }
+
+    fn fee(asset_id: &str, amount: &str) -> AssetFee {
+        AssetFee {
+            fee_type: Some("fee".to_string()),
+            asset_id: Some(asset_id.to_string()),
+            amount: Some(amount.parse().unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_select_withdrawal_fee() {
+        let fees = [fee("chain-asset", "0.001"), fee("asset", "1.5")];
+        let selected = select_withdrawal_fee(&fees, "asset").unwrap();
+        assert_eq!(selected.amount, Some("1.5".parse().unwrap()));
+
+        let selected = select_withdrawal_fee(&fees[..1], "asset").unwrap();
+        assert_eq!(selected.asset_id.as_deref(), Some("chain-asset"));
+        assert!(select_withdrawal_fee(&[], "asset").is_err());
+    }
+
+    #[test]
+    fn test_fee_transaction_references_withdrawal() {
+        let utxo = |asset: &str, amount: &str| Output {
+            transaction_hash: Some("00".repeat(32)),
+            output_index: Some(0),
+            kernel_asset_id: Some(asset.repeat(32)),
+            ..fixtures::output("output-id", amount)
+        };
+        let fee_address = MixAddress::new_uuid(&[MIXIN_FEE_USER_ID], 1).unwrap();
+        let withdrawal = SafeWithdrawalData {
+            address: "0x1616".to_string(),
+            tag: String::new(),
+        };
+        let tx = build_withdrawal_transaction(
+            &[utxo("11", "1")],
+            &withdrawal,
+            "1".parse().unwrap(),
+            &[],
+            &[],
+            &[],
+            b"memo",
+        )
+        .unwrap();
+        let fee_tx = build_fee_transaction(
+            &tx,
+            &[utxo("22", "0.01")],
+            &[TransactionRecipient::new(
+                &fee_address,
+                "0.01".parse().unwrap(),
+            )],
+            &[fixtures::ghost_keys()],
+        )
+        .unwrap();
+
+        let tx = SafeTransaction::decode(&tx.encode_unsigned().unwrap()).unwrap();
+        let fee_tx = SafeTransaction::decode(&fee_tx.encode_unsigned().unwrap()).unwrap();
+        assert!(tx.references.is_empty());
+        assert_eq!(fee_tx.references, [hex::encode(tx.payload_hash().unwrap())]);
+        assert_eq!(fee_tx.asset, "22".repeat(32));
+    }
+
+    #[test]
+    fn test_withdrawal_hash() {
+        let mut view: TransactionView = serde_json::from_str(
+            r#"{"request_id":"trace-id","receivers":[{"members":[],"destination":"0x1616","withdrawal_hash":""}]}"#,
+        )
+        .unwrap();
+        assert_eq!(withdrawal_hash(&view), None);
+        view.receivers.as_mut().unwrap()[0].withdrawal_hash = Some("0xabc".to_string());
+        assert_eq!(withdrawal_hash(&view).as_deref(), Some("0xabc"));
+    }
 }